@@ -0,0 +1,256 @@
+use std::{error::Error, f32::consts::PI, fs::File, io::BufReader, path::Path, sync::Arc};
+
+use glam::{vec3a, Vec3A};
+use image::codecs::hdr::HdrDecoder;
+use rand::Rng;
+
+use crate::{
+    bvh::aabb::AABB,
+    geometry::{Hittable, Transformable},
+    material::HitRecord,
+    ray::Ray,
+    rng::rng,
+    vec3::{decode_color, Color, OrthNormBasis},
+};
+
+// Smallest apparent diameter of a sun, in degrees, so that it keeps a finite solid angle. Its
+// shadows are as sharp as a point light's at this size.
+const MIN_SUN_ANGLE: f32 = 0.01;
+
+// Equirectangular image of the radiance arriving from every direction, laid out like the
+// panoramas of the equirectangular camera: longitude across the width with -Z at the center,
+// latitude down the height with +Y at the top
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    data: Vec<Color>,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: u32, height: u32, data: Vec<Color>) -> EnvironmentMap {
+        assert_eq!(data.len(), (width * height) as usize);
+        EnvironmentMap {
+            width,
+            height,
+            data,
+        }
+    }
+
+    // Radiance .hdr files are read as is, other formats are taken as 8-bit sRGB colors
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<EnvironmentMap, Box<dyn Error>> {
+        let path = path.as_ref();
+        let is_hdr = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr"));
+
+        if is_hdr {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let data = decoder
+                .read_image_hdr()?
+                .into_iter()
+                .map(|pixel| Color::from(pixel.0))
+                .collect();
+            return Ok(EnvironmentMap::new(metadata.width, metadata.height, data));
+        }
+
+        let img = image::open(path)?.to_rgb8();
+        let data = img.pixels().map(|pixel| decode_color(*pixel)).collect();
+        Ok(EnvironmentMap::new(img.width(), img.height(), data))
+    }
+
+    pub fn value(&self, direction: Vec3A) -> Color {
+        let d = direction.normalize();
+        let longitude = (-d.x).atan2(d.z).rem_euclid(2. * PI);
+        let latitude = d.y.clamp(-1., 1.).asin();
+
+        let u = longitude / (2. * PI);
+        let v = 0.5 - latitude / PI;
+
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        self.data[(y * self.width + x) as usize]
+    }
+}
+
+// Light arriving in parallel from a disk at infinity, of apparent diameter `angular_size`
+// degrees around `direction` (pointing toward the sun). `color` is the irradiance it gives a
+// surface facing it, whatever its size, so that only the softness of the shadows changes.
+// Listed in the scene lights, it samples the directions toward its disk; the radiance seen in
+// these directions comes from `Background::Sun`.
+pub struct Sun {
+    direction: Vec3A,
+    color: Color,
+    // 1 - cos and sin of the angular radius, kept apart for precision with tiny suns
+    one_minus_cos: f32,
+    sin_max: f32,
+}
+
+#[allow(unused)]
+impl Sun {
+    pub fn new(direction: Vec3A, color: Color, angular_size: f32) -> Sun {
+        let radius = 0.5 * angular_size.max(MIN_SUN_ANGLE).to_radians();
+        let half_sin = (0.5 * radius).sin();
+        Sun {
+            direction: direction.normalize(),
+            color,
+            one_minus_cos: 2. * half_sin * half_sin,
+            sin_max: radius.sin(),
+        }
+    }
+
+    fn solid_angle(&self) -> f32 {
+        2. * PI * self.one_minus_cos
+    }
+
+    fn contains(&self, direction: Vec3A) -> bool {
+        let d = direction.normalize();
+        d.dot(self.direction) > 0. && d.cross(self.direction).length() <= self.sin_max
+    }
+
+    pub fn radiance(&self, direction: Vec3A) -> Color {
+        if self.contains(direction) {
+            self.color / self.solid_angle()
+        } else {
+            Color::ZERO
+        }
+    }
+}
+
+impl Transformable for Sun {}
+
+// Not a surface: it's never hit and only takes part in light sampling
+impl Hittable for Sun {
+    fn hit(&self, _ray: &Ray, _t_min: f32, _t_max: f32) -> Option<HitRecord> {
+        None
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        None
+    }
+
+    fn pdf_value(&self, _origin: Vec3A, v: Vec3A) -> f32 {
+        if self.contains(v) {
+            1. / self.solid_angle()
+        } else {
+            0.
+        }
+    }
+
+    // Uniform over the cone of the disk
+    fn random(&self, _origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let z = rng.gen::<f32>() * self.one_minus_cos;
+        let phi = 2. * PI * rng.gen::<f32>();
+        let sin_theta = (z * (2. - z)).sqrt();
+
+        OrthNormBasis::from_w(self.direction).local(vec3a(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            1. - z,
+        ))
+    }
+}
+
+pub enum Background {
+    Solid(Color),
+    // Vertical blend from `bottom` straight down to `top` straight up
+    Gradient {
+        top: Color,
+        bottom: Color,
+    },
+    Environment(Arc<EnvironmentMap>),
+    // Exponential height fog blending everything seen through it toward `color`. The fog is
+    // `density` thick at y=0 and thins out with altitude by `height_falloff` (0 = uniform fog).
+    Fog {
+        sky: Box<Background>,
+        color: Color,
+        density: f32,
+        height_falloff: f32,
+    },
+    // `sky` with the disk of a sun in front of it
+    Sun {
+        sky: Box<Background>,
+        sun: Arc<Sun>,
+    },
+}
+
+impl Background {
+    // Radiance seen by a ray escaping the scene
+    pub fn value(&self, direction: Vec3A) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { top, bottom } => {
+                let t = 0.5 * (direction.normalize().y + 1.);
+                bottom.lerp(*top, t)
+            }
+            Background::Environment(map) => map.value(direction),
+            Background::Fog { sky, .. } => sky.value(direction),
+            Background::Sun { sky, sun } => sky.value(direction) + sun.radiance(direction),
+        }
+    }
+
+    // Aerial perspective: blends the radiance arriving from `t` along `ray` toward the fog color
+    pub fn apply_fog(&self, ray: &Ray, t: f32, radiance: Color) -> Color {
+        let (transmittance, inscattered) = self.fog(ray, t);
+        radiance * transmittance + inscattered
+    }
+
+    // Fog between the ray origin and `t` as the fraction of radiance that gets through, and the
+    // radiance scattered in toward the origin along the way
+    pub fn fog(&self, ray: &Ray, t: f32) -> (f32, Color) {
+        match self {
+            Background::Fog {
+                sky,
+                color,
+                density,
+                height_falloff,
+            } => {
+                let (sky_transmittance, sky_inscattered) = sky.fog(ray, t);
+                if *density <= 0. {
+                    return (sky_transmittance, sky_inscattered);
+                }
+
+                let length = ray.direction().length();
+                let dir_y = ray.direction().y / length;
+                let dist = t * length;
+
+                let base = density * (-height_falloff * ray.origin().y).exp();
+                let optical_depth = if (height_falloff * dir_y).abs() < 1e-6 {
+                    base * dist
+                } else {
+                    let k = height_falloff * dir_y;
+                    base * (1. - (-k * dist).exp()) / k
+                };
+
+                let transmittance = (-optical_depth).exp();
+                (
+                    sky_transmittance * transmittance,
+                    sky_inscattered * transmittance + *color * (1. - transmittance),
+                )
+            }
+            Background::Sun { sky, .. } => sky.fog(ray, t),
+            _ => (1., Color::ZERO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn ldr_environment_maps_are_decoded_from_srgb() {
+        // -Z looks at the center of the map, the second pixel
+        let path = std::env::temp_dir().join("ldr_environment_map.png");
+        RgbImage::from_fn(2, 1, |x, _| Rgb(if x == 1 { [188; 3] } else { [0; 3] }))
+            .save(&path)
+            .unwrap();
+
+        let map = EnvironmentMap::from_file(&path).unwrap();
+        assert!((map.value(vec3a(0., 0., -1.)).x - 0.5).abs() < 1e-2);
+        assert_eq!(map.value(vec3a(0., 0., 1.)), Color::ZERO);
+    }
+}
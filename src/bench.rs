@@ -0,0 +1,223 @@
+use std::{hint::black_box, sync::Arc, time::Instant};
+
+use glam::{vec3, vec3a, Vec3A};
+
+use crate::{
+    background::Background,
+    camera::Camera,
+    geometry::{
+        sphere::Sphere, triangle_mesh::TriangleMesh, Hittable, Hittables, Primitive, PrimitiveBVH,
+    },
+    material::{Lambertian, Material, Metal},
+    pdf::MisHeuristic,
+    ray::ray_color,
+    sampler::{Sampler, SamplerType},
+    scene::Scene,
+    vec3::Color,
+};
+
+const MAX_DEPTH: u32 = 4;
+
+// Small fixed scene: a ground sphere and a 6x6 grid of alternating diffuse and metal balls
+fn bench_spheres() -> Vec<Sphere> {
+    let diffuse: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.6, 0.3, 0.2)));
+    let metal: Arc<dyn Material> = Arc::new(Metal {
+        albedo: Color::new(0.8, 0.8, 0.9),
+        fuzziness: 0.1,
+    });
+    let ground: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(0.5, 0.5, 0.5)));
+
+    let mut spheres = vec![Sphere::new(vec3a(0., -1000., 0.), 1000., ground)];
+    for a in 0..6 {
+        for b in 0..6 {
+            let center = vec3a(a as f32 - 2.5, 0.4, b as f32 - 2.5);
+            let material = if (a + b) % 2 == 0 { &diffuse } else { &metal };
+            spheres.push(Sphere::new(center, 0.4, material.clone()));
+        }
+    }
+    spheres
+}
+
+// Lumpy ball of 960 smooth shaded triangles, about as many as Suzanne
+fn bench_mesh() -> TriangleMesh {
+    let (columns, rows) = (32, 16);
+    let mut positions: Vec<Vec3A> = Vec::new();
+    for row in 0..=rows {
+        let theta = std::f32::consts::PI * row as f32 / rows as f32;
+        for column in 0..columns {
+            let phi = std::f32::consts::TAU * column as f32 / columns as f32;
+            let radius = 2. + 0.15 * (5. * phi).sin() * (4. * theta).sin();
+            positions.push(
+                radius
+                    * vec3a(
+                        theta.sin() * phi.cos(),
+                        theta.cos(),
+                        theta.sin() * phi.sin(),
+                    )
+                    + vec3a(0., 2., 0.),
+            );
+        }
+    }
+    let normals = positions
+        .iter()
+        .map(|p| (*p - vec3a(0., 2., 0.)).normalize())
+        .collect();
+
+    let vertex = |row: usize, column: usize| row * columns + column % columns;
+    let mut indices: Vec<[usize; 3]> = Vec::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let (a, b) = (vertex(row, column), vertex(row, column + 1));
+            let (c, d) = (vertex(row + 1, column), vertex(row + 1, column + 1));
+            // The poles only have one triangle per column
+            if row > 0 {
+                indices.push([a, b, c]);
+            }
+            if row < rows - 1 {
+                indices.push([b, d, c]);
+            }
+        }
+    }
+
+    let material = Arc::new(Lambertian::from_color(Color::new(0.6, 0.3, 0.2)));
+    TriangleMesh::new(positions, Some(normals), None, indices, material)
+}
+
+fn bench_camera() -> Camera {
+    Camera::look_at(
+        vec3(6., 4., 8.),
+        vec3(0., 0., 0.),
+        vec3(0., 1., 0.),
+        1.,
+        30.,
+        0.,
+        1.,
+        0.,
+        1.,
+    )
+}
+
+// Primary rays only, so that the timing is dominated by the world's intersection code
+fn intersect<W: Hittable>(scene: &Scene<W>, height: u32, samples: u32) -> usize {
+    let mut hits = 0;
+
+    for y in 0..height {
+        for x in 0..height {
+            let mut sampler = Sampler::new(SamplerType::Random, x, y, 1);
+            for i in 0..samples {
+                sampler.start_sample(i);
+                let (dx, dy) = sampler.next_2d();
+                let u = (x as f32 + dx) / height as f32;
+                let v = (y as f32 + dy) / height as f32;
+                let ray = scene.camera.get_ray(u, v, &mut sampler);
+                if scene.world.hit(&ray, 0.0001, f32::INFINITY).is_some() {
+                    hits += 1;
+                }
+            }
+        }
+    }
+
+    hits
+}
+
+fn render<W: Hittable>(scene: &Scene<W>, height: u32, samples: u32) -> Color {
+    let mut total = Color::ZERO;
+
+    for y in 0..height {
+        for x in 0..height {
+            let mut sampler = Sampler::new(SamplerType::Random, x, y, 1);
+            for i in 0..samples {
+                sampler.start_sample(i);
+                let (dx, dy) = sampler.next_2d();
+                let u = (x as f32 + dx) / height as f32;
+                let v = (y as f32 + dy) / height as f32;
+                let ray = scene.camera.get_ray(u, v, &mut sampler);
+                total += ray_color(
+                    &ray,
+                    &scene.background,
+                    &scene.world,
+                    &scene.lights,
+                    MAX_DEPTH,
+                    MisHeuristic::Balance,
+                    false,
+                );
+            }
+        }
+    }
+
+    total
+}
+
+/// Renders the same small scenes through the dynamic `Arc<dyn Hittable>` world and through a
+/// statically typed `PrimitiveBVH` world on a single thread, and prints the best of five runs:
+/// a grid of spheres, and a mesh about as big as Suzanne.
+pub fn run_benchmark(height: u32, samples: u32) {
+    let background = || Background::Solid(Color::new(0.7, 0.8, 1.));
+    let scenes = |objects: Hittables, primitives: Vec<Primitive>| {
+        (
+            Scene::new(bench_camera(), objects, Vec::new(), background(), 0., 1.),
+            Scene::with_world(
+                bench_camera(),
+                PrimitiveBVH::new(primitives),
+                Vec::new(),
+                background(),
+                0.,
+                1.,
+            ),
+        )
+    };
+
+    let spheres = scenes(
+        bench_spheres()
+            .into_iter()
+            .map(|sphere| Arc::new(sphere) as Arc<dyn Hittable>)
+            .collect(),
+        bench_spheres().into_iter().map(Primitive::Sphere).collect(),
+    );
+
+    // The dynamic world intersects the mesh with its own BVH, the static one its triangles
+    let mesh = bench_mesh();
+    let primitives = mesh.primitives();
+    let mesh = scenes(vec![Arc::new(mesh)], primitives);
+
+    let time = |f: &dyn Fn()| {
+        (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                f();
+                start.elapsed().as_secs_f32()
+            })
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    println!("                        dyn world   static world   speedup");
+    for (name, (dyn_scene, static_scene)) in [("spheres", spheres), ("mesh", mesh)] {
+        let dyn_hit = time(&|| {
+            black_box(intersect(&dyn_scene, height, samples));
+        });
+        let static_hit = time(&|| {
+            black_box(intersect(&static_scene, height, samples));
+        });
+        let dyn_render = time(&|| {
+            black_box(render(&dyn_scene, height, samples));
+        });
+        let static_render = time(&|| {
+            black_box(render(&static_scene, height, samples));
+        });
+
+        println!(
+            "{:<7} primary rays   {:>9.3}s   {:>11.3}s   {:>6.2}x",
+            name,
+            dyn_hit,
+            static_hit,
+            dyn_hit / static_hit
+        );
+        println!(
+            "{:<7} full render    {:>9.3}s   {:>11.3}s   {:>6.2}x",
+            name,
+            dyn_render,
+            static_render,
+            dyn_render / static_render
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use rayon::prelude::*;
+
+use crate::vec3::{luminance, Color};
+
+// Luminance above which pixels glow: brighter ones clip to white without tone mapping
+const THRESHOLD: f32 = 1.;
+
+// Standard deviation of the blur, as a fraction of the image height
+const SPREAD: f32 = 0.02;
+
+// Normalized taps of a Gaussian of standard deviation `sigma`, out to 3 sigmas on each side
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (3. * sigma).ceil() as i32;
+    let taps: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2. * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = taps.iter().sum();
+    taps.iter().map(|tap| tap / total).collect()
+}
+
+// Convolves the rows of a `width` x `height` buffer with `kernel`, or its columns when
+// `vertical`, clamping to the borders
+fn blur_pass(
+    width: i32,
+    height: i32,
+    colors: &[Color],
+    kernel: &[f32],
+    vertical: bool,
+) -> Vec<Color> {
+    let radius = (kernel.len() / 2) as i32;
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            kernel
+                .iter()
+                .enumerate()
+                .map(|(k, tap)| {
+                    let offset = k as i32 - radius;
+                    let (u, v) = if vertical {
+                        (x, (y + offset).clamp(0, height - 1))
+                    } else {
+                        ((x + offset).clamp(0, width - 1), y)
+                    };
+                    *tap * colors[(v * width + u) as usize]
+                })
+                .fold(Color::ZERO, |sum, color| sum + color)
+        })
+        .collect()
+}
+
+/// Adds a glow around the bright parts of a `width` x `height` linear radiance buffer: the
+/// radiance above a luminance of 1 is blurred with a separable Gaussian a fiftieth of the image
+/// height wide, and added back scaled by `intensity`.
+pub fn bloom(width: u32, height: u32, radiance: &[Color], intensity: f32) -> Vec<Color> {
+    // Only the excess over the threshold spreads, keeping the hue of the pixel
+    let bright: Vec<Color> = radiance
+        .iter()
+        .map(|color| {
+            let l = luminance(*color);
+            if l > THRESHOLD {
+                *color * (l - THRESHOLD) / l
+            } else {
+                Color::ZERO
+            }
+        })
+        .collect();
+
+    let (width, height) = (width as i32, height as i32);
+    let kernel = gaussian_kernel((SPREAD * height as f32).max(1.));
+    let glow = blur_pass(width, height, &bright, &kernel, false);
+    let glow = blur_pass(width, height, &glow, &kernel, true);
+
+    radiance
+        .iter()
+        .zip(glow)
+        .map(|(color, glow)| *color + intensity * glow)
+        .collect()
+}
@@ -1,10 +1,10 @@
-use std::{cmp::Ordering, f32::INFINITY};
+use std::cmp::Ordering;
 
 use glam::{vec3a, Vec3A};
 
 use crate::ray::Ray;
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AABB {
     pub min: Vec3A,
     pub max: Vec3A,
@@ -12,28 +12,100 @@ pub struct AABB {
 
 impl AABB {
     pub fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
         for a in 0..3 {
+            // A ray parallel to the slab never crosses its planes: it stays either inside or outside
+            if ray.direction()[a] == 0. {
+                let origin = ray.origin()[a];
+                if origin < self.min[a] || origin > self.max[a] {
+                    return false;
+                }
+                continue;
+            }
+
             let inv_d = 1. / ray.direction()[a];
             let t0 = (self.min[a] - ray.origin()[a]) * inv_d;
             let t1 = (self.max[a] - ray.origin()[a]) * inv_d;
 
-            let min: f32;
-            let max: f32;
-
+            // The slab intervals must be intersected across all three axes
             if inv_d < 0. {
-                min = t1.max(t_min);
-                max = t0.min(t_max);
+                t_min = t1.max(t_min);
+                t_max = t0.min(t_max);
             } else {
-                min = t0.max(t_min);
-                max = t1.min(t_max);
+                t_min = t0.max(t_min);
+                t_max = t1.min(t_max);
             }
 
-            if max <= min {
+            if t_max <= t_min {
                 return false;
             }
         }
         true
     }
+
+    pub fn centroid(&self) -> Vec3A {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d.x >= d.y && d.x >= d.z {
+            0
+        } else if d.y >= d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn contains(&self, other: &AABB) -> bool {
+        self.min.cmple(other.min).all() && self.max.cmpge(other.max).all()
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // Slab test with the ray's inverse direction precomputed, for traversals testing many boxes.
+    // Returns the distance at which the ray enters the box.
+    pub fn entry_distance(
+        &self,
+        origin: Vec3A,
+        inv_dir: Vec3A,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<f32> {
+        // As in `hit`, a ray parallel to a slab stays either inside or outside it. Its slab
+        // distances would otherwise be NaN when the origin is on one of the planes.
+        let parallel = inv_dir.abs().cmpeq(Vec3A::splat(f32::INFINITY));
+        let outside = origin.cmplt(self.min) | origin.cmpgt(self.max);
+        if (parallel & outside).any() {
+            return None;
+        }
+
+        let t0 = Vec3A::select(
+            parallel,
+            Vec3A::splat(-f32::INFINITY),
+            (self.min - origin) * inv_dir,
+        );
+        let t1 = Vec3A::select(
+            parallel,
+            Vec3A::splat(f32::INFINITY),
+            (self.max - origin) * inv_dir,
+        );
+
+        let t_near = t0.min(t1).max_element().max(t_min);
+        let t_far = t0.max(t1).min_element().min(t_max);
+
+        if t_near < t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
 }
 
 pub fn surrounding_box(box0: AABB, box1: AABB) -> AABB {
@@ -56,8 +128,8 @@ pub fn surrounding_box(box0: AABB, box1: AABB) -> AABB {
 }
 
 pub fn surrounding_box_vec(aabbs: &[AABB]) -> AABB {
-    let mut min = vec3a(INFINITY, INFINITY, INFINITY);
-    let mut max = vec3a(-INFINITY, -INFINITY, -INFINITY);
+    let mut min = Vec3A::splat(f32::INFINITY);
+    let mut max = Vec3A::splat(-f32::INFINITY);
 
     for aabb in aabbs.iter() {
         min = min.min(aabb.min);
@@ -68,5 +140,73 @@ pub fn surrounding_box_vec(aabbs: &[AABB]) -> AABB {
 }
 
 pub fn aabb_compare(a: &AABB, b: &AABB, axis: usize) -> Ordering {
-    return a.min[axis].partial_cmp(&b.min[axis]).unwrap();
+    a.min[axis].partial_cmp(&b.min[axis]).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> AABB {
+        AABB {
+            min: Vec3A::ZERO,
+            max: Vec3A::ONE,
+        }
+    }
+
+    #[test]
+    fn rays_parallel_to_a_slab_hit_within_it() {
+        let aabb = unit_box();
+
+        // Along x, through the box, then skimming its y = 1 face and its z = 0 face
+        for origin in [
+            vec3a(-1., 0.5, 0.5),
+            vec3a(-1., 1., 0.5),
+            vec3a(-1., 0.5, 0.),
+        ] {
+            let ray = Ray::new(origin, vec3a(1., 0., 0.), 0.);
+            assert!(aabb.hit(&ray, 0., f32::INFINITY), "{:?} misses", origin);
+        }
+    }
+
+    #[test]
+    fn rays_parallel_to_a_slab_miss_outside_it() {
+        let aabb = unit_box();
+
+        for origin in [vec3a(-1., 1.001, 0.5), vec3a(-1., 0.5, -0.001)] {
+            let ray = Ray::new(origin, vec3a(1., 0., 0.), 0.);
+            assert!(!aabb.hit(&ray, 0., f32::INFINITY), "{:?} hits", origin);
+        }
+    }
+
+    #[test]
+    fn traversal_slab_test_handles_parallel_rays() {
+        let aabb = unit_box();
+        let entry = |origin: Vec3A| {
+            let direction = vec3a(1., 0., 0.);
+            aabb.entry_distance(origin, direction.recip(), 0., f32::INFINITY)
+        };
+
+        assert_eq!(entry(vec3a(-1., 0.5, 0.5)), Some(1.));
+        assert_eq!(entry(vec3a(-1., 1.5, 0.5)), None);
+        // Skimming the y = 1 and z = 0 faces, whose slab distances would be 0 * inf
+        assert_eq!(entry(vec3a(-1., 1., 0.5)), Some(1.));
+        assert_eq!(entry(vec3a(-1., 0.5, 0.)), Some(1.));
+        assert_eq!(entry(vec3a(-1., 1.001, 0.)), None);
+    }
+
+    #[test]
+    fn surrounding_boxes_contain_their_boxes() {
+        let a = unit_box();
+        let b = AABB {
+            min: vec3a(-1., 0.5, 2.),
+            max: vec3a(0.5, 3., 4.),
+        };
+
+        let both = surrounding_box(a, b);
+        assert_eq!(both.min, vec3a(-1., 0., 0.));
+        assert_eq!(both.max, vec3a(1., 3., 4.));
+        assert_eq!(surrounding_box_vec(&[a, b]), both);
+        assert!(both.contains(&a) && both.contains(&b) && !a.contains(&both));
+    }
 }
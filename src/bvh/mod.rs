@@ -1,9 +1,10 @@
+use std::fmt;
+
 use aabb::AABB;
-use rand::{thread_rng, Rng};
 
-use crate::{bvh::aabb::aabb_compare, ray::Ray};
+use crate::{bvh::aabb::aabb_compare, material::HitRecord, ray::Ray};
 
-use self::aabb::surrounding_box_vec;
+use self::aabb::{surrounding_box, surrounding_box_vec};
 
 pub mod aabb;
 
@@ -11,6 +12,23 @@ pub trait Bounded {
     fn aabb(&self) -> AABB;
 }
 
+impl Bounded for AABB {
+    fn aabb(&self) -> AABB {
+        *self
+    }
+}
+
+// Relative costs of visiting a node and of intersecting a primitive for the SAH
+const SAH_TRAVERSAL_COST: f32 = 0.125;
+const SAH_INTERSECTION_COST: f32 = 1.;
+const SAH_BUCKETS: usize = 12;
+
+// Below this depth only median splits are made, so that a tree over up to 2^32 primitives fits
+// in the traversal stack
+const SAH_MAX_DEPTH: usize = 24;
+const TRAVERSAL_STACK_SIZE: usize = 64;
+
+#[derive(Debug, PartialEq)]
 pub enum BVHNode {
     Leaf {
         parent_index: usize,
@@ -31,10 +49,8 @@ impl BVHNode {
         indices: &[usize],
         nodes: &mut Vec<BVHNode>,
         parent_index: usize,
+        depth: usize,
     ) -> usize {
-        let mut rng = thread_rng();
-        let axis = rng.gen_range(0..2) as usize;
-
         if indices.len() == 1 {
             let primitive_index = indices[0];
             let node_index = nodes.len();
@@ -42,27 +58,16 @@ impl BVHNode {
                 parent_index,
                 primitive_index,
             });
-            return node_index;
+            node_index
         } else {
-            let mut aabbs = indices
-                .iter()
-                .map(|idx| primitives[*idx].aabb())
-                .collect::<Vec<_>>();
-
-            aabbs.sort_by(|a, b| aabb_compare(a, b, axis));
-
-            let mid = indices.len() / 2;
-
-            let mut left: Vec<usize> = vec![];
-            let mut right: Vec<usize> = vec![];
-
-            for idx in indices.iter() {
-                if aabb_compare(&primitives[*idx].aabb(), &aabbs[mid], axis).is_lt() {
-                    left.push(*idx);
-                } else {
-                    right.push(*idx);
-                }
-            }
+            let sah_split = if depth < SAH_MAX_DEPTH {
+                BVHNode::sah_split(primitives, indices)
+            } else {
+                None
+            };
+            let (left, right) =
+                sah_split.unwrap_or_else(|| BVHNode::median_split(primitives, indices));
+            let (left, right) = (left.as_slice(), right.as_slice());
 
             let node_index = nodes.len();
 
@@ -85,8 +90,8 @@ impl BVHNode {
                     .collect::<Vec<_>>(),
             );
 
-            let child_l_index = BVHNode::build_rec(primitives, &left, nodes, node_index);
-            let child_r_index = BVHNode::build_rec(primitives, &right, nodes, node_index);
+            let child_l_index = BVHNode::build_rec(primitives, left, nodes, node_index, depth + 1);
+            let child_r_index = BVHNode::build_rec(primitives, right, nodes, node_index, depth + 1);
 
             nodes[node_index] = BVHNode::Node {
                 parent_index,
@@ -96,7 +101,182 @@ impl BVHNode {
                 child_r_aabb,
             };
 
-            return node_index;
+            node_index
+        }
+    }
+
+    // Splits along the longest axis at the median, used when the SAH can't separate the
+    // primitives
+    fn median_split<P: Bounded>(primitives: &[P], indices: &[usize]) -> (Vec<usize>, Vec<usize>) {
+        let axis = surrounding_box_vec(
+            &indices
+                .iter()
+                .map(|idx| primitives[*idx].aabb())
+                .collect::<Vec<_>>(),
+        )
+        .longest_axis();
+
+        let mut sorted = indices.to_vec();
+        sorted.sort_by(|a, b| aabb_compare(&primitives[*a].aabb(), &primitives[*b].aabb(), axis));
+
+        // Splitting the sorted indices rather than comparing against the median box keeps
+        // both children non-empty when several primitives share the same bound
+        let right = sorted.split_off(sorted.len() / 2);
+        (sorted, right)
+    }
+
+    // Buckets the primitives by centroid along each axis and picks the split plane with the
+    // lowest surface area heuristic cost, if any beats intersecting every primitive
+    fn sah_split<P: Bounded>(
+        primitives: &[P],
+        indices: &[usize],
+    ) -> Option<(Vec<usize>, Vec<usize>)> {
+        let aabbs = indices
+            .iter()
+            .map(|idx| primitives[*idx].aabb())
+            .collect::<Vec<_>>();
+        let area = surrounding_box_vec(&aabbs).surface_area();
+        let centroids = surrounding_box_vec(
+            &aabbs
+                .iter()
+                .map(|aabb| AABB {
+                    min: aabb.centroid(),
+                    max: aabb.centroid(),
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let bucket_of = |aabb: &AABB, axis: usize| {
+            let extent = centroids.max[axis] - centroids.min[axis];
+            let offset = (aabb.centroid()[axis] - centroids.min[axis]) / extent;
+            ((offset * SAH_BUCKETS as f32) as usize).min(SAH_BUCKETS - 1)
+        };
+
+        let mut best: Option<(f32, usize, usize)> = None;
+
+        for axis in 0..3 {
+            if centroids.max[axis] - centroids.min[axis] <= 0. {
+                continue;
+            }
+
+            let mut counts = [0; SAH_BUCKETS];
+            let mut bounds: [Option<AABB>; SAH_BUCKETS] = [None; SAH_BUCKETS];
+            for aabb in aabbs.iter() {
+                let b = bucket_of(aabb, axis);
+                counts[b] += 1;
+                bounds[b] = Some(bounds[b].map_or(*aabb, |bound| surrounding_box(bound, *aabb)));
+            }
+
+            for split in 1..SAH_BUCKETS {
+                let side = |range: &[Option<AABB>], counts: &[usize]| {
+                    let count: usize = counts.iter().sum();
+                    let bound = range.iter().flatten().copied().reduce(surrounding_box);
+                    (count, bound.map_or(0., |b| b.surface_area()))
+                };
+                let (count_l, area_l) = side(&bounds[..split], &counts[..split]);
+                let (count_r, area_r) = side(&bounds[split..], &counts[split..]);
+                if count_l == 0 || count_r == 0 {
+                    continue;
+                }
+
+                let cost = SAH_TRAVERSAL_COST
+                    + (area_l * count_l as f32 + area_r * count_r as f32) / area
+                        * SAH_INTERSECTION_COST;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (cost, axis, split) = best?;
+        if cost >= indices.len() as f32 * SAH_INTERSECTION_COST {
+            return None;
+        }
+
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        for (idx, aabb) in indices.iter().zip(aabbs.iter()) {
+            if bucket_of(aabb, axis) < split {
+                left.push(*idx);
+            } else {
+                right.push(*idx);
+            }
+        }
+
+        Some((left, right))
+    }
+
+    // Checks the subtree under `index` and returns the box around its primitives, marking them
+    // as `seen`
+    fn validate_rec<P: Bounded>(
+        nodes: &[BVHNode],
+        index: usize,
+        parent: usize,
+        primitives: &[P],
+        seen: &mut [bool],
+    ) -> Result<AABB, String> {
+        let node = nodes
+            .get(index)
+            .ok_or(format!("node {} is out of range", index))?;
+
+        match *node {
+            BVHNode::Node {
+                parent_index,
+                child_l_index,
+                ref child_l_aabb,
+                child_r_index,
+                ref child_r_aabb,
+            } => {
+                if index != 0 && parent_index != parent {
+                    return Err(format!(
+                        "node {} has parent {} instead of {}",
+                        index, parent_index, parent
+                    ));
+                }
+
+                let left = BVHNode::validate_rec(nodes, child_l_index, index, primitives, seen)?;
+                let right = BVHNode::validate_rec(nodes, child_r_index, index, primitives, seen)?;
+                if !child_l_aabb.contains(&left) {
+                    return Err(format!(
+                        "the left box of node {} doesn't contain its subtree",
+                        index
+                    ));
+                }
+                if !child_r_aabb.contains(&right) {
+                    return Err(format!(
+                        "the right box of node {} doesn't contain its subtree",
+                        index
+                    ));
+                }
+
+                Ok(surrounding_box(left, right))
+            }
+            BVHNode::Leaf {
+                parent_index,
+                primitive_index,
+            } => {
+                if index != 0 && parent_index != parent {
+                    return Err(format!(
+                        "leaf {} has parent {} instead of {}",
+                        index, parent_index, parent
+                    ));
+                }
+
+                match seen.get_mut(primitive_index) {
+                    None => Err(format!(
+                        "leaf {} refers to missing primitive {}",
+                        index, primitive_index
+                    )),
+                    Some(true) => Err(format!(
+                        "primitive {} is in more than one leaf",
+                        primitive_index
+                    )),
+                    Some(seen) => {
+                        *seen = true;
+                        Ok(primitives[primitive_index].aabb())
+                    }
+                }
+            }
         }
     }
 
@@ -132,31 +312,375 @@ impl BVHNode {
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct BVH {
     nodes: Vec<BVHNode>,
 }
 
+pub struct BVHStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_leaf_depth: usize,
+    pub average_leaf_depth: f32,
+    pub average_leaf_size: f32,
+    // Expected cost of a random ray through the tree, in primitive intersections
+    pub sah_cost: f32,
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves of {:.2} primitives on average, leaf depth {:.2} on average and {} \
+             at most, SAH cost {:.2}",
+            self.node_count,
+            self.leaf_count,
+            self.average_leaf_size,
+            self.average_leaf_depth,
+            self.max_leaf_depth,
+            self.sah_cost
+        )
+    }
+}
+
 impl BVH {
     pub fn new<P: Bounded>(primitives: &[P]) -> Self {
         let indices = (0..primitives.len()).collect::<Vec<usize>>();
         let expected_node_count = primitives.len() * 2;
         let mut nodes = Vec::with_capacity(expected_node_count);
-        BVHNode::build_rec(primitives, &indices, &mut nodes, 0);
+        if !primitives.is_empty() {
+            BVHNode::build_rec(primitives, &indices, &mut nodes, 0, 0);
+        }
         BVH { nodes }
     }
 
-    pub fn traverse<'a, P: Bounded>(
+    // Closest intersection along the ray. Children are visited nearest first and skipped once a
+    // hit closer than their box is known.
+    pub fn traverse_nearest<P, F: Fn(&P, f32, f32) -> Option<HitRecord>>(
+        &self,
+        ray: &Ray,
+        primitives: &[P],
+        hit: F,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        let origin = ray.origin();
+        let inv_dir = ray.direction().recip();
+
+        let mut closest = None;
+        let mut t = t_max;
+
+        // Nodes left to visit, with the distance at which the ray enters them
+        let mut stack = [(0, 0.); TRAVERSAL_STACK_SIZE];
+        let mut stack_len = 0;
+
+        if !self.nodes.is_empty() {
+            stack[0] = (0, t_min);
+            stack_len = 1;
+        }
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let (index, t_enter) = stack[stack_len];
+
+            // A closer hit was found since this node was pushed
+            if t_enter >= t {
+                continue;
+            }
+
+            match self.nodes[index] {
+                BVHNode::Node {
+                    ref child_l_aabb,
+                    child_l_index,
+                    ref child_r_aabb,
+                    child_r_index,
+                    ..
+                } => {
+                    let left = child_l_aabb
+                        .entry_distance(origin, inv_dir, t_min, t)
+                        .map(|t_l| (child_l_index, t_l));
+                    let right = child_r_aabb
+                        .entry_distance(origin, inv_dir, t_min, t)
+                        .map(|t_r| (child_r_index, t_r));
+
+                    // The nearer child is pushed last so that it is visited first
+                    let (first, second) = match (left, right) {
+                        (Some(l), Some(r)) if r.1 < l.1 => (Some(r), Some(l)),
+                        _ => (left, right),
+                    };
+                    for child in [second, first].into_iter().flatten() {
+                        stack[stack_len] = child;
+                        stack_len += 1;
+                    }
+                }
+                BVHNode::Leaf {
+                    primitive_index, ..
+                } => {
+                    if let Some(rec) = hit(&primitives[primitive_index], t_min, t) {
+                        t = rec.t;
+                        closest = Some(rec);
+                    }
+                }
+            }
+        }
+
+        closest
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats {
+            node_count: self.nodes.len(),
+            leaf_count: 0,
+            max_leaf_depth: 0,
+            average_leaf_depth: 0.,
+            average_leaf_size: 0.,
+            sah_cost: 0.,
+        };
+
+        // Node index, depth, and surface area of the node's box (unknown for a lone root leaf)
+        let mut stack = Vec::new();
+        if let Some(BVHNode::Node {
+            child_l_aabb,
+            child_r_aabb,
+            ..
+        }) = self.nodes.first()
+        {
+            stack.push((
+                0,
+                0,
+                surrounding_box(*child_l_aabb, *child_r_aabb).surface_area(),
+            ));
+        } else if !self.nodes.is_empty() {
+            stack.push((0, 0, 1.));
+        }
+        let root_area = stack.first().map_or(1., |(_, _, area)| *area);
+
+        let mut depth_sum = 0;
+        let mut primitive_sum = 0;
+        while let Some((index, depth, area)) = stack.pop() {
+            match self.nodes[index] {
+                BVHNode::Node {
+                    ref child_l_aabb,
+                    child_l_index,
+                    ref child_r_aabb,
+                    child_r_index,
+                    ..
+                } => {
+                    stats.sah_cost += SAH_TRAVERSAL_COST * area / root_area;
+                    stack.push((child_l_index, depth + 1, child_l_aabb.surface_area()));
+                    stack.push((child_r_index, depth + 1, child_r_aabb.surface_area()));
+                }
+                BVHNode::Leaf { .. } => {
+                    stats.sah_cost += SAH_INTERSECTION_COST * area / root_area;
+                    stats.leaf_count += 1;
+                    stats.max_leaf_depth = stats.max_leaf_depth.max(depth);
+                    depth_sum += depth;
+                    // Leaves hold a single primitive
+                    primitive_sum += 1;
+                }
+            }
+        }
+
+        if stats.leaf_count > 0 {
+            stats.average_leaf_depth = depth_sum as f32 / stats.leaf_count as f32;
+            stats.average_leaf_size = primitive_sum as f32 / stats.leaf_count as f32;
+        }
+        stats
+    }
+
+    /// Checks the structure of a tree built over `primitives`: every node's child boxes must
+    /// contain the primitives of the child subtrees, the parent indices must match, and each
+    /// primitive must be in exactly one leaf.
+    pub fn validate<P: Bounded>(&self, primitives: &[P]) -> Result<(), String> {
+        let mut seen = vec![false; primitives.len()];
+        if !self.nodes.is_empty() {
+            BVHNode::validate_rec(&self.nodes, 0, 0, primitives, &mut seen)?;
+        }
+
+        match seen.iter().position(|seen| !seen) {
+            Some(missing) => Err(format!("primitive {} isn't in any leaf", missing)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn traverse<'a, P>(
         &'a self,
         ray: &Ray,
         primitives: &'a [P],
         t_min: f32,
         t_max: f32,
-    ) -> Vec<&P> {
+    ) -> Vec<&'a P> {
         let mut indices = Vec::new();
-        BVHNode::traverse_rec(&self.nodes, 0, ray, t_min, t_max, &mut indices);
+        if !self.nodes.is_empty() {
+            BVHNode::traverse_rec(&self.nodes, 0, ray, t_min, t_max, &mut indices);
+        }
         indices
             .iter()
             .map(|index| &primitives[*index])
             .collect::<Vec<_>>()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::{vec3a, Vec3A};
+    use rand::Rng;
+
+    use super::*;
+    use crate::{
+        geometry::{sphere::Sphere, Hittable},
+        material::Lambertian,
+        rng,
+    };
+
+    // `n` unit boxes strung along `axis`, a little out of order and offset on the other axes
+    fn boxes_along(axis: usize, n: usize) -> Vec<AABB> {
+        (0..n)
+            .map(|i| {
+                let mut min = vec3a(0.1, 0.2, 0.3) * (i % 3) as f32;
+                min[axis] = 2. * ((i * 7) % n) as f32;
+                AABB {
+                    min,
+                    max: min + Vec3A::ONE,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_primitive_gets_a_leaf() {
+        let boxes = boxes_along(0, 37);
+        let bvh = BVH::new(&boxes);
+
+        let stats = bvh.stats();
+        assert_eq!(stats.leaf_count, 37);
+        assert_eq!(stats.node_count, 2 * 37 - 1);
+        assert_eq!(bvh.validate(&boxes), Ok(()));
+    }
+
+    #[test]
+    fn child_boxes_contain_their_subtrees() {
+        for axis in 0..3 {
+            let boxes = boxes_along(axis, 20);
+            assert_eq!(BVH::new(&boxes).validate(&boxes), Ok(()));
+        }
+
+        // A box too small for its primitive is caught
+        let boxes = boxes_along(0, 4);
+        let mut bvh = BVH::new(&boxes);
+        if let BVHNode::Node {
+            ref mut child_l_aabb,
+            ..
+        } = bvh.nodes[0]
+        {
+            child_l_aabb.max.x -= 0.5;
+        }
+        assert!(bvh.validate(&boxes).is_err());
+    }
+
+    #[test]
+    fn splits_along_z_when_the_primitives_are_spread_along_it() {
+        let boxes = boxes_along(2, 16);
+        let bvh = BVH::new(&boxes);
+
+        match bvh.nodes[0] {
+            BVHNode::Node {
+                child_l_aabb,
+                child_r_aabb,
+                ..
+            } => assert!(
+                child_l_aabb.max.z <= child_r_aabb.min.z
+                    || child_r_aabb.max.z <= child_l_aabb.min.z
+            ),
+            BVHNode::Leaf { .. } => panic!("the root of 16 primitives is a leaf"),
+        }
+    }
+
+    #[test]
+    fn same_primitives_give_the_same_tree() {
+        // Boxes that overlap and share bounds, so that ties have to be broken the same way
+        let boxes: Vec<AABB> = (0..50)
+            .map(|i| {
+                let min = vec3a((i % 5) as f32, (i % 3) as f32, (i % 7) as f32) * 0.5;
+                AABB {
+                    min,
+                    max: min + Vec3A::ONE,
+                }
+            })
+            .collect();
+
+        assert_eq!(BVH::new(&boxes), BVH::new(&boxes));
+    }
+
+    #[test]
+    fn nearest_hit_matches_a_brute_force_search() {
+        rng::set_seed(3);
+        let mut rng = rng::rng();
+        let material = Arc::new(Lambertian::from_rgb(0.5, 0.5, 0.5));
+        let random_point = |rng: &mut rng::PixelRng| {
+            vec3a(rng.gen(), rng.gen(), rng.gen()) * 20. - Vec3A::splat(10.)
+        };
+
+        // Overlapping spheres of all sizes
+        let spheres: Vec<Sphere> = (0..200)
+            .map(|_| {
+                let center = random_point(&mut rng);
+                Sphere::new(center, rng.gen_range(0.1..2.), material.clone())
+            })
+            .collect();
+        let bvh = BVH::new(&spheres);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = random_point(&mut rng);
+            let ray = Ray::new(origin, random_point(&mut rng) - origin, 0.);
+            let t_max = rng.gen_range(0.5..2.);
+
+            let nearest = bvh.traverse_nearest(
+                &ray,
+                &spheres,
+                |sphere, t_min, t_max| sphere.hit(&ray, t_min, t_max),
+                0.001,
+                t_max,
+            );
+            let brute_force = spheres
+                .iter()
+                .filter_map(|sphere| sphere.hit(&ray, 0.001, t_max))
+                .min_by(|a, b| a.t.total_cmp(&b.t));
+
+            match (nearest, brute_force) {
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert_eq!(a.t, b.t);
+                }
+                (None, None) => (),
+                (a, b) => panic!(
+                    "BVH hit: {}, brute force hit: {} for {:?}",
+                    a.is_some(),
+                    b.is_some(),
+                    ray
+                ),
+            }
+        }
+        assert!(hits > 500);
+    }
+
+    #[test]
+    fn stats_describe_a_balanced_tree() {
+        let boxes = boxes_along(1, 8);
+        let stats = BVH::new(&boxes).stats();
+
+        assert_eq!(stats.leaf_count, 8);
+        assert_eq!(stats.average_leaf_size, 1.);
+        assert_eq!(stats.max_leaf_depth, 3);
+        assert_eq!(stats.average_leaf_depth, 3.);
+        // Every ray through the tree traverses the root
+        assert!(stats.sah_cost > SAH_TRAVERSAL_COST);
+
+        let empty = BVH::new::<AABB>(&[]).stats();
+        assert_eq!((empty.node_count, empty.leaf_count), (0, 0));
+        assert_eq!(empty.average_leaf_size, 0.);
+    }
+}
@@ -1,72 +1,248 @@
-use glam::{vec3a, Affine3A, Vec3A};
-use rand::{thread_rng, Rng};
+use std::f32::consts::PI;
 
-use crate::{geometry::Transformable, ray::Ray};
+use glam::{vec3a, Affine3A, Vec3, Vec3A};
+
+use crate::{bvh::aabb::AABB, geometry::Transformable, ray::Ray, sampler::Sampler};
+
+// How camera rays spread from the film: from a single point for perspective, or in parallel for
+// orthographic. Both `vfov` (degrees) and `height` describe the vertical extent of the view.
+// Equirectangular cameras see the whole sphere around them, mapping the film's width to
+// longitude and its height to latitude.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective { vfov: f32 },
+    Orthographic { height: f32 },
+    Equirectangular,
+}
+
+impl Projection {
+    // Half height of the film at unit distance, or of the view for orthographic cameras
+    fn scale(&self) -> f32 {
+        match *self {
+            Projection::Perspective { vfov } => (vfov.to_radians() * 0.5).tan(),
+            Projection::Orthographic { height } => height * 0.5,
+            Projection::Equirectangular => 1.,
+        }
+    }
+}
 
 pub struct Camera {
     time0: f32,
     time1: f32,
     pub aspect_ratio: f32,
-    pub vertical_fov: f32,
+    pub projection: Projection,
     scale: f32,
     pub near_plane_dist: f32,
     pub far_plane_dist: f32,
+    lens_radius: f32,
+    focus_dist: f32,
     camera_to_world: Affine3A,
     ray_origin: Vec3A,
 }
 
 impl Camera {
+    // See `set_lens` for `aperture` and `focus_dist`
     pub fn new(
         aspect_ratio: f32,
         vertical_fov: f32,
+        aperture: f32,
+        focus_dist: f32,
         near_plane_dist: f32,
         far_plane_dist: f32,
         camera_to_world: Affine3A,
-        time0: f32,
-        time1: f32,
     ) -> Self {
-        Camera {
-            time0,
-            time1,
+        Camera::with_projection(
             aspect_ratio,
-            vertical_fov,
-            scale: (vertical_fov.to_radians() * 0.5).tan(),
+            Projection::Perspective { vfov: vertical_fov },
+            aperture,
+            focus_dist,
+            near_plane_dist,
+            far_plane_dist,
+            camera_to_world,
+        )
+    }
+
+    pub fn with_projection(
+        aspect_ratio: f32,
+        projection: Projection,
+        aperture: f32,
+        focus_dist: f32,
+        near_plane_dist: f32,
+        far_plane_dist: f32,
+        camera_to_world: Affine3A,
+    ) -> Self {
+        let mut camera = Camera {
+            time0: 0.,
+            time1: 1.,
+            aspect_ratio,
+            projection,
+            scale: projection.scale(),
             near_plane_dist,
             far_plane_dist,
+            lens_radius: 0.,
+            focus_dist: 1.,
             camera_to_world,
             ray_origin: camera_to_world.transform_point3a(Vec3A::ZERO),
+        };
+        camera.set_lens(aperture, focus_dist);
+        camera
+    }
+
+    // Right-handed camera looking from `lookfrom` toward `lookat`, with `vup` pointing up the
+    // film
+    #[allow(clippy::too_many_arguments)]
+    pub fn look_at(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        aspect_ratio: f32,
+        vertical_fov: f32,
+        aperture: f32,
+        focus_dist: f32,
+        time0: f32,
+        time1: f32,
+    ) -> Self {
+        // `look_at_rh` builds the world to camera transform
+        let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vup).inverse();
+
+        let mut camera = Camera::new(
+            aspect_ratio,
+            vertical_fov,
+            aperture,
+            focus_dist,
+            0.1,
+            100.,
+            camera_to_world,
+        );
+        camera.set_shutter(time0, time1);
+        camera
+    }
+
+    // Looks down the -z axis at the center of `bbox`, from just far enough for its bounding
+    // sphere, grown by `margin` times its radius, to fit in the field of view. The lens is
+    // focused on the center.
+    pub fn frame(bbox: &AABB, aspect_ratio: f32, vertical_fov: f32, margin: f32) -> Self {
+        let center = Vec3::from(bbox.centroid());
+        let radius = (1. + margin) * 0.5 * (bbox.max - bbox.min).length();
+        // Narrow images are bounded by their horizontal field of view instead
+        let half_fov = if aspect_ratio >= 1. {
+            0.5 * vertical_fov.to_radians()
+        } else {
+            ((0.5 * vertical_fov.to_radians()).tan() * aspect_ratio).atan()
+        };
+        let distance = radius / half_fov.sin();
+
+        Camera::look_at(
+            center + distance * Vec3::Z,
+            center,
+            Vec3::Y,
+            aspect_ratio,
+            vertical_fov,
+            0.,
+            distance,
+            0.,
+            1.,
+        )
+    }
+
+    // Rotates the camera around its viewing axis
+    pub fn roll(&mut self, angle: f32) {
+        self.camera_to_world = self.camera_to_world * Affine3A::from_rotation_z(angle.to_radians());
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.scale = projection.scale();
+    }
+
+    pub fn set_shutter(&mut self, time0: f32, time1: f32) {
+        self.time0 = time0;
+        self.time1 = time1;
+    }
+
+    // Thin lens of diameter `aperture` focused at `focus_dist` along the viewing axis, an
+    // aperture of 0 keeps the pinhole camera
+    pub fn set_lens(&mut self, aperture: f32, focus_dist: f32) {
+        self.lens_radius = aperture.max(0.) / 2.;
+        self.focus_dist = focus_dist;
+    }
+
+    // Ray through the film at normalized coordinates (u, v) in [0, 1], (0.5, 0.5) being the
+    // center of the image. The pixel jitter is expected to already be folded into (u, v).
+    pub fn get_ray(&self, u: f32, v: f32, sampler: &mut Sampler) -> Ray {
+        if self.projection == Projection::Equirectangular {
+            return self.get_panoramic_ray(u, v, sampler);
+        }
+
+        let px = (2. * u - 1.) * self.scale * self.aspect_ratio;
+        let py = (2. * v - 1.) * self.scale;
+
+        let time = self.time0 + sampler.next_1d() * (self.time1 - self.time0);
+
+        // Orthographic rays all share the viewing direction and start across the film
+        if let Projection::Orthographic { .. } = self.projection {
+            let origin = self.camera_to_world.transform_point3a(vec3a(px, py, 0.));
+            let direction = self.camera_to_world.transform_vector3a(vec3a(0., 0., -1.));
+            return Ray::new(origin, direction, time);
+        }
+
+        if self.lens_radius <= 0. {
+            let ray_p = self.camera_to_world.transform_point3a(vec3a(px, py, -1.));
+            return Ray::new(self.ray_origin, ray_p - self.ray_origin, time);
         }
+
+        // Start from a point on the lens and aim at where the pinhole ray crosses the focal plane
+        let (r1, r2) = sampler.next_2d();
+        let r = self.lens_radius * r1.sqrt();
+        let theta = 2. * PI * r2;
+        let lens_p = vec3a(r * theta.cos(), r * theta.sin(), 0.);
+
+        let origin = self.camera_to_world.transform_point3a(lens_p);
+        let focus_p = self
+            .camera_to_world
+            .transform_point3a(vec3a(px, py, -1.) * self.focus_dist);
+
+        Ray::new(origin, focus_p - origin, time)
     }
 
-    pub fn default() -> Self {
-        let vfov: f32 = 30.;
+    // Longitude goes all the way around from the left edge of the image to its right edge, so
+    // that panoramas tile horizontally, with the viewing axis at the center of the image
+    fn get_panoramic_ray(&self, u: f32, v: f32, sampler: &mut Sampler) -> Ray {
+        let longitude = 2. * PI * u;
+        let latitude = PI * (v - 0.5);
+
+        let time = self.time0 + sampler.next_1d() * (self.time1 - self.time0);
+
+        let direction = vec3a(
+            -longitude.sin() * latitude.cos(),
+            latitude.sin(),
+            longitude.cos() * latitude.cos(),
+        );
+        Ray::new(
+            self.ray_origin,
+            self.camera_to_world.transform_vector3a(direction),
+            time,
+        )
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        let projection = Projection::Perspective { vfov: 30. };
         Camera {
             time0: 0.,
             time1: 1.,
             aspect_ratio: 1.,
-            vertical_fov: vfov,
-            scale: (vfov.to_radians() * 0.5).tan(),
+            projection,
+            scale: projection.scale(),
             near_plane_dist: 0.1,
             far_plane_dist: 100.,
+            lens_radius: 0.,
+            focus_dist: 1.,
             camera_to_world: Affine3A::IDENTITY,
             ray_origin: Vec3A::ZERO,
         }
     }
-
-    pub fn get_ray(&self, x: f32, y: f32, img_width: u32, img_height: u32) -> Ray {
-        let mut rng = thread_rng();
-
-        let px = (2. * (x + 0.5) / (img_width as f32) - 1.) * self.scale * self.aspect_ratio;
-        let py = (2. * (y + 0.5) / (img_height as f32) - 1.) * self.scale;
-
-        let ray_p = self.camera_to_world.transform_point3a(vec3a(px, py, -1.));
-
-        Ray::new(
-            self.ray_origin,
-            ray_p - self.ray_origin,
-            rng.gen_range(self.time0..self.time1),
-        )
-    }
 }
 
 impl Transformable for Camera {
@@ -75,3 +251,79 @@ impl Transformable for Camera {
         self.ray_origin = other.transform_point3a(self.ray_origin);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::sampler::SamplerType;
+
+    #[test]
+    fn center_ray_points_down_the_viewing_axis() {
+        let lookfrom = vec3(1., 2., 3.);
+        let lookat = vec3(-2., 0., -1.);
+        let camera = Camera::look_at(lookfrom, lookat, vec3(0., 1., 0.), 1.5, 40., 0., 1., 0., 1.);
+        let mut sampler = Sampler::new(SamplerType::Random, 0, 0, 1);
+
+        let ray = camera.get_ray(0.5, 0.5, &mut sampler);
+        let forward = Vec3A::from(lookat - lookfrom).normalize();
+        assert!((ray.origin() - Vec3A::from(lookfrom)).length() < 1e-5);
+        assert!(ray.direction().normalize().dot(forward) > 1. - 1e-5);
+    }
+
+    #[test]
+    fn corner_rays_span_the_field_of_view() {
+        let camera = Camera::look_at(
+            vec3(0., 0., 0.),
+            vec3(0., 0., -1.),
+            vec3(0., 1., 0.),
+            2.,
+            90.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let mut sampler = Sampler::new(SamplerType::Random, 0, 0, 1);
+
+        // With a 90° vertical field of view, the film spans [-1, 1] vertically and [-2, 2]
+        // horizontally at a distance of 1
+        let d = camera.get_ray(1., 1., &mut sampler).direction();
+        assert!((d / -d.z - vec3a(2., 1., -1.)).length() < 1e-5);
+        let d = camera.get_ray(0., 0., &mut sampler).direction();
+        assert!((d / -d.z - vec3a(-2., -1., -1.)).length() < 1e-5);
+    }
+
+    #[test]
+    fn rolling_turns_the_film_around_the_viewing_axis() {
+        let camera = |angle: f32| {
+            let mut camera = Camera::look_at(
+                vec3(0., 0., 0.),
+                vec3(0., 0., -1.),
+                vec3(0., 1., 0.),
+                1.,
+                90.,
+                0.,
+                1.,
+                0.,
+                1.,
+            );
+            camera.roll(angle);
+            camera
+        };
+        let mut sampler = Sampler::new(SamplerType::Random, 0, 0, 1);
+
+        for angle in [90., -90.] {
+            let camera = camera(angle);
+            let d = camera.get_ray(0.5, 0.5, &mut sampler).direction();
+            assert!((d.normalize() - vec3a(0., 0., -1.)).length() < 1e-5);
+        }
+
+        // The top of the film turns toward -x when rolling counterclockwise, and +x otherwise
+        let d = camera(90.).get_ray(0.5, 1., &mut sampler).direction();
+        assert!((d / -d.z - vec3a(-1., 0., -1.)).length() < 1e-5);
+        let d = camera(-90.).get_ray(0.5, 1., &mut sampler).direction();
+        assert!((d / -d.z - vec3a(1., 0., -1.)).length() < 1e-5);
+    }
+}
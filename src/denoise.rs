@@ -0,0 +1,96 @@
+use glam::Vec3A;
+use rayon::prelude::*;
+
+use crate::{
+    ray::FirstHit,
+    vec3::{Color, ToneMap},
+};
+
+// Passes of the filter, each one reaching twice as far as the previous: 5 passes cover a 61
+// pixels wide footprint
+const PASSES: u32 = 5;
+
+// B3 spline taps of the 5x5 kernel, along each axis
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+// Tolerances of the edge-stopping weights: the exponent of the cosine between normals, and the
+// distance between albedos
+const NORMAL_POWER: i32 = 64;
+const ALBEDO_SIGMA: f32 = 0.1;
+
+fn normal_weight(n: Vec3A, m: Vec3A) -> f32 {
+    // Pixels that only saw the background have no normal, and are alike
+    if n == Vec3A::ZERO || m == Vec3A::ZERO {
+        return if n == m { 1. } else { 0. };
+    }
+    n.dot(m).max(0.).powi(NORMAL_POWER)
+}
+
+/// Edge-avoiding à-trous filter (Dammertz et al. 2010) over a `width` x `height` linear radiance
+/// buffer. Each pass blurs with a 5x5 B3 spline kernel whose taps are spread twice as far apart
+/// as in the previous pass, weighting every tap by how close its color is to the center's, and
+/// its normal and albedo when the first hits are given. `strength` scales the color tolerance:
+/// 0 leaves the image untouched, and higher values smooth more at the cost of detail.
+pub fn denoise(
+    width: u32,
+    height: u32,
+    radiance: &[Color],
+    guides: Option<&[FirstHit]>,
+    strength: f32,
+) -> Vec<Color> {
+    if strength <= 0. {
+        return radiance.to_vec();
+    }
+
+    let (width, height) = (width as i32, height as i32);
+    let mut colors = radiance.to_vec();
+    for pass in 0..PASSES {
+        let step = 1 << pass;
+        // The noise left shrinks with each pass, and so does the tolerance
+        let color_sigma = strength / (1 << pass) as f32;
+
+        colors = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let center = colors[i as usize];
+                // Colors are compared after compressing their range, so that bright pixels
+                // aren't all edges
+                let compressed = ToneMap::Reinhard.apply(center);
+
+                let mut sum = Color::ZERO;
+                let mut total = 0.;
+                for (dy, ky) in (-2..=2).zip(KERNEL) {
+                    for (dx, kx) in (-2..=2).zip(KERNEL) {
+                        let (u, v) = (x + step * dx, y + step * dy);
+                        if u < 0 || u >= width || v < 0 || v >= height {
+                            continue;
+                        }
+                        let j = (v * width + u) as usize;
+
+                        let distance =
+                            (ToneMap::Reinhard.apply(colors[j]) - compressed).length_squared();
+                        let mut weight = kx * ky * (-distance / (color_sigma * color_sigma)).exp();
+                        if let Some(guides) = guides {
+                            let (a, b) = (&guides[i as usize], &guides[j]);
+                            let albedo = (a.albedo - b.albedo).length_squared();
+                            weight *= normal_weight(a.normal, b.normal)
+                                * (-albedo / (ALBEDO_SIGMA * ALBEDO_SIGMA)).exp();
+                        }
+
+                        sum += weight * colors[j];
+                        total += weight;
+                    }
+                }
+
+                // The center tap always has a weight, unless its color is not a number
+                if total > 0. {
+                    sum / total
+                } else {
+                    center
+                }
+            })
+            .collect();
+    }
+    colors
+}
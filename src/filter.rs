@@ -0,0 +1,56 @@
+use std::str::FromStr;
+
+// Standard deviation of the Gaussian filter, in pixels, which is cut off at 3 of them
+const GAUSSIAN_SIGMA: f32 = 0.5;
+
+// Pixel reconstruction filters: samples are spread over the filter's support around the pixel
+// center and weighted by it. The box filter gives every sample within the pixel the same weight,
+// while the tent and Gaussian ones reach into the neighboring pixels and favor samples near the
+// center, trading a little sharpness for smoother edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            _ => Err(format!("unknown filter `{}`", s)),
+        }
+    }
+}
+
+impl Filter {
+    // Half width of the support, in pixels
+    fn radius(&self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.,
+            Filter::Gaussian => 3. * GAUSSIAN_SIGMA,
+        }
+    }
+
+    // Turns a uniform number in [0, 1) into an offset along one axis from the corner of the
+    // pixel, and the weight of the filter there. The 2D filters are separable, so the weight of
+    // a sample is the product of those of its two offsets.
+    pub fn sample(&self, u: f32) -> (f32, f32) {
+        if *self == Filter::Box {
+            return (u, 1.);
+        }
+
+        let x = (2. * u - 1.) * self.radius();
+        let weight = match self {
+            Filter::Box => 1.,
+            Filter::Tent => 1. - x.abs(),
+            Filter::Gaussian => (-x * x / (2. * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp(),
+        };
+        (0.5 + x, weight)
+    }
+}
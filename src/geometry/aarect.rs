@@ -1,16 +1,19 @@
-use std::{f32::INFINITY, sync::Arc};
+use std::sync::Arc;
 
 use glam::{vec3a, Vec3A};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::{
     bvh::aabb::AABB,
     material::{HitRecord, Material},
     ray::Ray,
+    rng::rng,
 };
 
 use super::{Hittable, Transformable};
 
+// Axis-aligned rectangles face the positive direction of their normal axis, a FlipFace makes them
+// face the other way
 pub struct XYRect {
     pub material: Arc<dyn Material>,
     pub x0: f32,
@@ -46,7 +49,7 @@ impl Hittable for XYRect {
             if x >= self.x0 && x <= self.x1 && y >= self.y0 && y <= self.y1 {
                 return Some(HitRecord {
                     p: ray.at(t),
-                    normal: vec3a(0., 0., (ray.origin()[2] - self.k).signum()),
+                    normal: vec3a(0., 0., 1.),
                     t,
                     mat: self.material.clone(),
                     u: (x - self.x0) / (self.x1 - self.x0),
@@ -64,6 +67,27 @@ impl Hittable for XYRect {
             max: vec3a(self.x1, self.y1, self.k + 0.0001),
         })
     }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
+            let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+            let dist_squared = rec.t * rec.t * v.length_squared();
+            let cosine = v.dot(rec.normal).abs() / v.length();
+
+            return dist_squared / (cosine * area);
+        }
+        0.
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let random_point = vec3a(
+            rng.gen_range(self.x0..self.x1),
+            rng.gen_range(self.y0..self.y1),
+            self.k,
+        );
+        random_point - origin
+    }
 }
 
 pub struct XZRect {
@@ -101,7 +125,7 @@ impl Hittable for XZRect {
             if x >= self.x0 && x <= self.x1 && z >= self.z0 && z <= self.z1 {
                 return Some(HitRecord {
                     p: ray.at(t),
-                    normal: vec3a(0., (ray.origin()[1] - self.k).signum(), 0.),
+                    normal: vec3a(0., 1., 0.),
                     t,
                     mat: self.material.clone(),
                     u: (x - self.x0) / (self.x1 - self.x0),
@@ -121,7 +145,7 @@ impl Hittable for XZRect {
     }
 
     fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
-        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, INFINITY) {
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
             let area = (self.x1 - self.x0) * (self.z1 - self.z0);
             let dist_squared = rec.t * rec.t * v.length_squared();
             let cosine = v.dot(rec.normal).abs() / v.length();
@@ -132,7 +156,7 @@ impl Hittable for XZRect {
     }
 
     fn random(&self, origin: Vec3A) -> Vec3A {
-        let mut rng = thread_rng();
+        let mut rng = rng();
         let random_point = vec3a(
             rng.gen_range(self.x0..self.x1),
             self.k,
@@ -177,7 +201,7 @@ impl Hittable for YZRect {
             if y >= self.y0 && y <= self.y1 && z >= self.z0 && z <= self.z1 {
                 return Some(HitRecord {
                     p: ray.at(t),
-                    normal: vec3a((ray.origin()[0] - self.k).signum(), 0., 0.),
+                    normal: vec3a(1., 0., 0.),
                     t,
                     mat: self.material.clone(),
                     u: (y - self.y0) / (self.y1 - self.y0),
@@ -195,4 +219,25 @@ impl Hittable for YZRect {
             max: vec3a(self.k + 0.0001, self.y1, self.z1),
         })
     }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
+            let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+            let dist_squared = rec.t * rec.t * v.length_squared();
+            let cosine = v.dot(rec.normal).abs() / v.length();
+
+            return dist_squared / (cosine * area);
+        }
+        0.
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let random_point = vec3a(
+            self.k,
+            rng.gen_range(self.y0..self.y1),
+            rng.gen_range(self.z0..self.z1),
+        );
+        random_point - origin
+    }
 }
@@ -1,12 +1,13 @@
 use std::sync::Arc;
 
 use glam::vec3a;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::{
     bvh::aabb::AABB,
     material::{texture::Texture, HitRecord, Isotropic, Material},
     ray::Ray,
+    rng::rng,
     vec3::Color,
 };
 
@@ -38,43 +39,51 @@ impl ConstantMedium {
             neg_inv_density: -1. / density,
         }
     }
+
+    // Scatters with `phase_function` instead of evenly, such as a `HenyeyGreenstein` material
+    pub fn with_phase_function(
+        boundary: Arc<dyn Hittable>,
+        density: f32,
+        phase_function: Arc<dyn Material>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            phase_function,
+            neg_inv_density: -1. / density,
+        }
+    }
 }
 
 impl Transformable for ConstantMedium {}
 
 impl Hittable for ConstantMedium {
+    // The distance the ray travels in the medium carries over from one span to the next
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut rng = thread_rng();
-        if let Some(mut rec1) = self.boundary.hit(ray, -f32::INFINITY, f32::INFINITY) {
-            if let Some(mut rec2) = self.boundary.hit(ray, rec1.t + 0.0001, f32::INFINITY) {
-                rec1.t = rec1.t.max(t_min);
-                rec2.t = rec2.t.min(t_max);
-
-                if rec1.t >= rec2.t {
-                    return None;
-                }
-
-                rec1.t = rec1.t.max(0.);
-
-                let ray_length = ray.direction().length();
-                let dist_in_boundary = (rec2.t - rec1.t) * ray_length;
-                let hit_distance = self.neg_inv_density * rng.gen::<f32>().ln();
-
-                if hit_distance > dist_in_boundary {
-                    return None;
-                }
-
-                let t = rec1.t + hit_distance / ray_length;
-
-                return Some(HitRecord {
-                    t,
-                    p: ray.at(t),
-                    normal: vec3a(1., 0., 0.),
-                    mat: self.phase_function.clone(),
-                    u: 0.,
-                    v: 0.,
-                });
+        let mut rng = rng();
+        let ray_length = ray.direction().length();
+        // Drawn on reaching the medium, so that rays missing it don't use up random numbers
+        let mut hit_distance: Option<f32> = None;
+
+        for (t_enter, t_exit) in spans(self.boundary.as_ref(), ray, t_min, t_max) {
+            let dist_in_boundary = (t_exit - t_enter) * ray_length;
+            let distance =
+                *hit_distance.get_or_insert_with(|| self.neg_inv_density * rng.gen::<f32>().ln());
+
+            if distance > dist_in_boundary {
+                hit_distance = Some(distance - dist_in_boundary);
+                continue;
             }
+
+            let t = t_enter + distance / ray_length;
+
+            return Some(HitRecord {
+                t,
+                p: ray.at(t),
+                normal: vec3a(1., 0., 0.),
+                mat: self.phase_function.clone(),
+                u: 0.,
+                v: 0.,
+            });
         }
         None
     }
@@ -83,3 +92,99 @@ impl Hittable for ConstantMedium {
         self.boundary.bounding_box(time0, time1)
     }
 }
+
+// Spans of `ray` inside `boundary` within [`t_min`, `t_max`] and in front of its origin, in order.
+// The boundary may be concave: the ray goes through each stretch between an entry and the next
+// exit in turn.
+pub(super) fn spans<'a>(
+    boundary: &'a dyn Hittable,
+    ray: &'a Ray,
+    t_min: f32,
+    t_max: f32,
+) -> impl Iterator<Item = (f32, f32)> + 'a {
+    let mut t = -f32::INFINITY;
+    std::iter::from_fn(move || loop {
+        let rec1 = boundary.hit(ray, t, f32::INFINITY)?;
+        let rec2 = boundary.hit(ray, rec1.t + 0.0001, f32::INFINITY)?;
+        // Far away, the offset can be lost to rounding and the entry found again as its exit
+        if rec1.t >= t_max || rec2.t <= rec1.t {
+            return None;
+        }
+        t = rec2.t + 0.0001;
+
+        let t_enter = rec1.t.max(t_min).max(0.);
+        let t_exit = rec2.t.min(t_max);
+        if t_enter < t_exit {
+            return Some((t_enter, t_exit));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3A;
+
+    use super::*;
+    use crate::{
+        geometry::{
+            csg::{Csg, CsgOp},
+            sphere::Sphere,
+        },
+        material::Lambertian,
+        rng::set_seed,
+    };
+
+    fn sphere(x: f32) -> Arc<dyn Hittable> {
+        let white = Arc::new(Lambertian::from_color(Color::ONE));
+        Arc::new(Sphere::new(vec3a(x, 0., 0.), 1., white))
+    }
+
+    // Along the x axis from x = -5
+    fn ray() -> Ray {
+        Ray::new(vec3a(-5., 0., 0.), Vec3A::X, 0.)
+    }
+
+    #[test]
+    fn a_sphere_has_a_single_span() {
+        let boundary = sphere(0.);
+        let ray = ray();
+
+        let all: Vec<_> = spans(boundary.as_ref(), &ray, 0.001, f32::INFINITY).collect();
+        assert_eq!(all, [(4., 6.)]);
+        let clipped: Vec<_> = spans(boundary.as_ref(), &ray, 5., 5.5).collect();
+        assert_eq!(clipped, [(5., 5.5)]);
+
+        // As before spans, a ray crossing the sphere scatters with odds 1 - exp(-density * 2)
+        set_seed(5);
+        let medium = ConstantMedium::from_color(boundary, 0.5, Color::ONE);
+        let n = 50000;
+        let hits = (0..n)
+            .filter(|_| medium.hit(&ray, 0.001, f32::INFINITY).is_some())
+            .count();
+        let expected = 1. - (-1f32).exp();
+        assert!((hits as f32 / n as f32 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn concave_boundaries_skip_the_gaps() {
+        // Two disjoint spheres, with a gap between x = 1 and x = 3
+        let boundary: Arc<dyn Hittable> = Arc::new(Csg::new(sphere(0.), sphere(4.), CsgOp::Union));
+        let ray = ray();
+
+        let all: Vec<_> = spans(boundary.as_ref(), &ray, 0.001, f32::INFINITY).collect();
+        assert_eq!(all, [(4., 6.), (8., 10.)]);
+
+        // A thin medium scatters in both spheres, but never in the gap
+        set_seed(3);
+        let medium = ConstantMedium::from_color(boundary, 0.2, Color::ONE);
+        let hits: Vec<f32> = (0..10000)
+            .filter_map(|_| medium.hit(&ray, 0.001, f32::INFINITY))
+            .map(|rec| rec.t)
+            .collect();
+        assert!(hits.iter().any(|&t| t < 6.));
+        assert!(hits.iter().any(|&t| t > 8.));
+        assert!(hits
+            .iter()
+            .all(|&t| (4. ..=6.).contains(&t) || (8. ..=10.).contains(&t)));
+    }
+}
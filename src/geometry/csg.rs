@@ -0,0 +1,194 @@
+use std::{cmp::Ordering, sync::Arc};
+
+use crate::{
+    bvh::aabb::{surrounding_box, AABB},
+    material::HitRecord,
+    ray::Ray,
+};
+
+use super::{Hittable, Transformable};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    // The right operand is carved out of the left one
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Boolean combination of two closed solids. The spans of the ray inside each operand (see
+// `Hittable::intervals`) are merged according to the operation, so both operands must support
+// the interval query: the combination is invisible otherwise. Surfaces of the right operand
+// bounding a difference take its material, with their normals turned inward.
+pub struct Csg {
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
+    pub op: CsgOp,
+}
+
+impl Csg {
+    pub fn new(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>, op: CsgOp) -> Csg {
+        Csg { left, right, op }
+    }
+}
+
+impl Transformable for Csg {}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.intervals(ray)?
+            .into_iter()
+            .flat_map(|(enter, exit)| [enter, exit])
+            .find(|rec| rec.t >= t_min && rec.t <= t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        let left = self.left.bounding_box(time0, time1)?;
+        match self.op {
+            CsgOp::Union => Some(surrounding_box(
+                left,
+                self.right.bounding_box(time0, time1)?,
+            )),
+            CsgOp::Intersection => {
+                let right = self.right.bounding_box(time0, time1)?;
+                Some(AABB {
+                    min: left.min.max(right.min),
+                    max: left.max.min(right.max),
+                })
+            }
+            CsgOp::Difference => Some(left),
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let left = self.left.intervals(ray)?;
+        let right = self.right.intervals(ray)?;
+
+        // Walking along the ray, every boundary of an operand toggles whether the ray is inside
+        // it, and a boundary of the result is wherever that changes the combination
+        let mut boundaries: Vec<(bool, HitRecord)> = left
+            .into_iter()
+            .flat_map(|(enter, exit)| [(true, enter), (true, exit)])
+            .chain(
+                right
+                    .into_iter()
+                    .flat_map(|(enter, exit)| [(false, enter), (false, exit)]),
+            )
+            .collect();
+        boundaries.sort_by(|a, b| a.1.t.partial_cmp(&b.1.t).unwrap_or(Ordering::Equal));
+
+        let mut out: Vec<(HitRecord, HitRecord)> = Vec::new();
+        let (mut in_left, mut in_right, mut inside) = (false, false, false);
+        let mut enter: Option<HitRecord> = None;
+
+        for (is_left, mut rec) in boundaries {
+            if is_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+                if self.op == CsgOp::Difference {
+                    rec.normal = -rec.normal;
+                }
+            }
+
+            if self.op.contains(in_left, in_right) != inside {
+                inside = !inside;
+                match enter.take() {
+                    None => enter = Some(rec),
+                    Some(enter) => out.push((enter, rec)),
+                }
+            }
+        }
+
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3a, Vec3A};
+
+    use super::*;
+    use crate::{
+        geometry::{cuboid::AaBox, quad::Quad, sphere::Sphere},
+        material::Lambertian,
+        vec3::Color,
+    };
+
+    fn sphere(x: f32, radius: f32) -> Arc<dyn Hittable> {
+        let white = Arc::new(Lambertian::from_color(Color::ONE));
+        Arc::new(Sphere::new(vec3a(x, 0., 0.), radius, white))
+    }
+
+    // Along the x axis from x = -5, as (t, normal.x) pairs
+    fn spans(csg: &Csg) -> Vec<((f32, f32), (f32, f32))> {
+        let ray = Ray::new(vec3a(-5., 0., 0.), Vec3A::X, 0.);
+        csg.intervals(&ray)
+            .unwrap()
+            .into_iter()
+            .map(|(enter, exit)| ((enter.t, enter.normal.x), (exit.t, exit.normal.x)))
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_spheres_combine_their_spans() {
+        // The spans [4, 6] and [5, 7] along the ray
+        let (left, right) = (sphere(0., 1.), sphere(1., 1.));
+        let csg = |op| Csg::new(left.clone(), right.clone(), op);
+
+        assert_eq!(spans(&csg(CsgOp::Union)), [((4., -1.), (7., 1.))]);
+        assert_eq!(spans(&csg(CsgOp::Intersection)), [((5., -1.), (6., 1.))]);
+        // The surface carved by the right sphere faces out of the result
+        assert_eq!(spans(&csg(CsgOp::Difference)), [((4., -1.), (5., 1.))]);
+    }
+
+    #[test]
+    fn disjoint_spheres_keep_their_spans() {
+        let (left, right) = (sphere(0., 1.), sphere(4., 1.));
+        let csg = |op| Csg::new(left.clone(), right.clone(), op);
+
+        assert_eq!(
+            spans(&csg(CsgOp::Union)),
+            [((4., -1.), (6., 1.)), ((8., -1.), (10., 1.))]
+        );
+        assert!(spans(&csg(CsgOp::Intersection)).is_empty());
+        assert_eq!(spans(&csg(CsgOp::Difference)), [((4., -1.), (6., 1.))]);
+    }
+
+    #[test]
+    fn spheres_carve_holes_in_boxes() {
+        let white = Arc::new(Lambertian::from_color(Color::ONE));
+        let cube = Arc::new(AaBox::new(Vec3A::splat(-1.), Vec3A::ONE, white));
+        let csg = Csg::new(cube, sphere(0., 0.5), CsgOp::Difference);
+        assert_eq!(
+            spans(&csg),
+            [((4., -1.), (4.5, 1.)), ((5.5, -1.), (6., 1.))]
+        );
+
+        // Past the first wall, the ray goes through the hole to the inner side of the second
+        let ray = Ray::new(vec3a(-5., 0., 0.), Vec3A::X, 0.);
+        assert_eq!(csg.hit(&ray, 0.001, f32::INFINITY).unwrap().t, 4.);
+        assert_eq!(csg.hit(&ray, 4.7, f32::INFINITY).unwrap().t, 5.5);
+
+        // Operands without an inside can't be combined
+        let quad = Arc::new(Quad::new(
+            Vec3A::ZERO,
+            Vec3A::Y,
+            Vec3A::Z,
+            Arc::new(Lambertian::from_color(Color::ONE)),
+        ));
+        assert!(Csg::new(quad, sphere(0., 1.), CsgOp::Union)
+            .hit(&ray, 0.001, f32::INFINITY)
+            .is_none());
+    }
+}
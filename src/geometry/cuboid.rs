@@ -1,11 +1,13 @@
 use std::sync::Arc;
 
 use glam::Vec3A;
+use rand::Rng;
 
 use crate::{
     bvh::aabb::AABB,
     material::{HitRecord, Material},
     ray::Ray,
+    rng::rng,
 };
 
 use super::{
@@ -90,3 +92,248 @@ impl Hittable for Cuboid {
         })
     }
 }
+
+// Axis-aligned box intersected with the slab method in a single test, instead of through six
+// rects. Normals and UVs match the ones of the corresponding `Cuboid` sides.
+pub struct AaBox {
+    min: Vec3A,
+    max: Vec3A,
+    material: Arc<dyn Material>,
+}
+
+// Distance along the ray, axis and position of one of the planes of a box
+type BoxPlane = (f32, usize, f32);
+
+// Axis and position of the plane of a face of a box, and its area
+type BoxFace = (usize, f32, f32);
+
+impl AaBox {
+    pub fn new(min: Vec3A, max: Vec3A, material: Arc<dyn Material>) -> AaBox {
+        AaBox { min, max, material }
+    }
+
+    // Entry and exit planes of the ray, over its whole line
+    fn slabs(&self, ray: &Ray) -> Option<(BoxPlane, BoxPlane)> {
+        let origin = ray.origin();
+        let direction = ray.direction();
+
+        let mut enter = (f32::NEG_INFINITY, 0, 0.);
+        let mut exit = (f32::INFINITY, 0, 0.);
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) / direction[axis];
+            let t1 = (self.max[axis] - origin[axis]) / direction[axis];
+            let (near, far) = if t0 < t1 {
+                ((t0, axis, self.min[axis]), (t1, axis, self.max[axis]))
+            } else {
+                ((t1, axis, self.max[axis]), (t0, axis, self.min[axis]))
+            };
+
+            if near.0 > enter.0 {
+                enter = near;
+            }
+            if far.0 < exit.0 {
+                exit = far;
+            }
+        }
+        if enter.0 > exit.0 {
+            return None;
+        }
+        Some((enter, exit))
+    }
+
+    // Faces seen from `origin`, as the axis and position of their plane and their area, and their
+    // total area. From inside the box, all of them are.
+    fn visible_faces(&self, origin: Vec3A) -> (Vec<BoxFace>, f32) {
+        let size = self.max - self.min;
+        let mut faces: Vec<BoxFace> = Vec::new();
+        let inside = (0..3).all(|axis| (self.min[axis]..=self.max[axis]).contains(&origin[axis]));
+        for axis in 0..3 {
+            let area = size[(axis + 1) % 3] * size[(axis + 2) % 3];
+            if inside || origin[axis] < self.min[axis] {
+                faces.push((axis, self.min[axis], area));
+            }
+            if inside || origin[axis] > self.max[axis] {
+                faces.push((axis, self.max[axis], area));
+            }
+        }
+        let total = faces.iter().map(|&(.., area)| area).sum();
+        (faces, total)
+    }
+
+    // The normal points along the axis of the plane, with the given sign
+    fn record(&self, ray: &Ray, (t, axis, _): BoxPlane, sign: f32) -> HitRecord {
+        let p = ray.at(t);
+        let mut normal = Vec3A::ZERO;
+        normal[axis] = sign;
+
+        // Same parameterization as the XY, XZ and YZ rects
+        let (a, b) = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+
+        HitRecord {
+            p,
+            normal,
+            t,
+            mat: self.material.clone(),
+            u: (p[a] - self.min[a]) / (self.max[a] - self.min[a]),
+            v: (p[b] - self.min[b]) / (self.max[b] - self.min[b]),
+        }
+    }
+}
+
+impl Transformable for AaBox {}
+
+impl Hittable for AaBox {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (enter, exit) = self.slabs(ray)?;
+
+        // Rays starting inside the box hit it on their way out
+        let plane = if enter.0 >= t_min && enter.0 <= t_max {
+            enter
+        } else if exit.0 >= t_min && exit.0 <= t_max {
+            exit
+        } else {
+            return None;
+        };
+
+        let (_, axis, k) = plane;
+        Some(self.record(ray, plane, (ray.origin()[axis] - k).signum()))
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        Some(AABB {
+            min: self.min,
+            max: self.max,
+        })
+    }
+
+    // Points are sampled uniformly over the faces seen from the origin, through which each
+    // direction toward the box enters it
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        let (_, area) = self.visible_faces(origin);
+        if area <= 0. {
+            return 0.;
+        }
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
+            let dist_squared = rec.t * rec.t * v.length_squared();
+            let cosine = v.dot(rec.normal).abs() / v.length();
+
+            return dist_squared / (cosine * area);
+        }
+        0.
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let (faces, area) = self.visible_faces(origin);
+
+        let mut target = rng.gen::<f32>() * area;
+        let &(axis, k, _) = faces
+            .iter()
+            .find(|&&(.., face_area)| {
+                target -= face_area;
+                target < 0.
+            })
+            .or(faces.last())
+            .unwrap();
+
+        let mut point =
+            self.min + (self.max - self.min) * Vec3A::new(rng.gen(), rng.gen(), rng.gen());
+        point[axis] = k;
+        point - origin
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let Some((enter, exit)) = self.slabs(ray) else {
+            return Some(Vec::new());
+        };
+
+        // Outward normals: against the ray where it enters, along it where it leaves
+        let direction = ray.direction();
+        Some(vec![(
+            self.record(ray, enter, -direction[enter.1].signum()),
+            self.record(ray, exit, direction[exit.1].signum()),
+        )])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3a;
+
+    use super::*;
+    use crate::{
+        material::Lambertian,
+        rng,
+        vec3::{random_in_unit_sphere, Color},
+    };
+
+    #[test]
+    fn box_hits_match_the_six_rects() {
+        let (min, max) = (vec3a(-1., 0., 2.), vec3a(3., 0.5, 4.));
+        let material: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::ONE));
+        let aa_box = AaBox::new(min, max, material.clone());
+        let cuboid = Cuboid::new(min, max, material);
+
+        rng::set_seed(1);
+        let mut rng = rng::rng();
+        let mut hits = 0;
+        for _ in 0..2000 {
+            // From around the box and from inside it, toward a point around it
+            let origin =
+                min + (max - min) * vec3a(rng.gen(), rng.gen(), rng.gen()) * 3. - (max - min);
+            let target = min + (max - min) * vec3a(rng.gen(), rng.gen(), rng.gen()) * 1.2
+                - 0.1 * (max - min);
+            let ray = Ray::new(origin, target - origin, 0.);
+
+            match (aa_box.hit(&ray, 0.001, 100.), cuboid.hit(&ray, 0.001, 100.)) {
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert!((a.t - b.t).abs() < 1e-4);
+                    assert!((a.p - b.p).length() < 1e-4);
+                    // The rects keep a fixed normal while the box turns it toward the ray origin,
+                    // materials facing it toward the ray anyway
+                    assert_eq!(a.normal.abs(), b.normal.abs());
+                    assert!((a.u - b.u).abs() < 1e-4 && (a.v - b.v).abs() < 1e-4);
+                }
+                (None, None) => (),
+                (a, b) => panic!(
+                    "box hit: {}, rects hit: {} for {:?}",
+                    a.is_some(),
+                    b.is_some(),
+                    ray
+                ),
+            }
+        }
+        assert!(hits > 500);
+    }
+
+    #[test]
+    fn box_density_integrates_to_one() {
+        let aa_box = AaBox::new(
+            vec3a(1., -0.5, -1.),
+            vec3a(2., 1., 0.5),
+            Arc::new(Lambertian::from_color(Color::ONE)),
+        );
+
+        rng::set_seed(2);
+        for origin in [Vec3A::ZERO, vec3a(1.5, 0., 0.)] {
+            // Directions drawn by `random` point at the box
+            for _ in 0..100 {
+                let v = aa_box.random(origin);
+                assert!(aa_box.pdf_value(origin, v) > 0.);
+            }
+
+            // Monte Carlo estimate of the integral over the sphere of directions
+            let n = 50000;
+            let sum: f32 = (0..n)
+                .map(|_| aa_box.pdf_value(origin, random_in_unit_sphere().normalize()))
+                .sum();
+            let integral = 4. * std::f32::consts::PI * sum / n as f32;
+            assert!((integral - 1.).abs() < 0.05, "{}", integral);
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use glam::{vec3a, Affine3A, Vec3A};
+use rand::Rng;
+
+use crate::{
+    bvh::aabb::AABB,
+    material::{HitRecord, Material},
+    ray::Ray,
+    rng::rng,
+    vec3::OrthNormBasis,
+};
+
+use super::{Hittable, Transformable};
+
+// Flat disk facing `normal`. Its texture coordinates are the distance to the center over the
+// radius, and the angle around the normal over 2π. Hits report that normal from either side, so
+// that one-sided lights only shine from the front.
+pub struct Disk {
+    center: Vec3A,
+    radius: f32,
+    material: Arc<dyn Material>,
+    uvw: OrthNormBasis,
+    aabb: AABB,
+}
+
+impl Disk {
+    pub fn new(center: Vec3A, normal: Vec3A, radius: f32, material: Arc<dyn Material>) -> Disk {
+        let uvw = OrthNormBasis::from_w(normal);
+        Disk {
+            center,
+            radius,
+            material,
+            aabb: compute_disk_aabb(center, uvw.w, radius),
+            uvw,
+        }
+    }
+}
+
+// Along each axis, the disk extends by the radius times the sine of the angle between the axis
+// and the normal
+fn compute_disk_aabb(center: Vec3A, normal: Vec3A, radius: f32) -> AABB {
+    let sines = vec3a(
+        (1. - normal.x * normal.x).max(0.).sqrt(),
+        (1. - normal.y * normal.y).max(0.).sqrt(),
+        (1. - normal.z * normal.z).max(0.).sqrt(),
+    );
+    // Added padding for non-zero width AABB
+    let extent = radius * sines + Vec3A::splat(0.0001);
+
+    AABB {
+        min: center - extent,
+        max: center + extent,
+    }
+}
+
+// Rigid transforms only: the radius is kept as is
+impl Transformable for Disk {
+    fn apply_transform(&mut self, other: Affine3A) {
+        self.center = other.transform_point3a(self.center);
+        self.uvw = OrthNormBasis::from_w(other.transform_vector3a(self.uvw.w));
+
+        self.aabb = compute_disk_aabb(self.center, self.uvw.w, self.radius);
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let normal = self.uvw.w;
+        let denom = normal.dot(ray.direction());
+
+        // Parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = normal.dot(self.center - ray.origin()) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let p = ray.at(t);
+        let offset = p - self.center;
+        let distance_squared = offset.length_squared();
+        if distance_squared > self.radius * self.radius {
+            return None;
+        }
+
+        let phi = offset.dot(self.uvw.v).atan2(offset.dot(self.uvw.u)) + PI;
+
+        Some(HitRecord {
+            p,
+            normal,
+            t,
+            mat: self.material.clone(),
+            u: distance_squared.sqrt() / self.radius,
+            v: phi / (2. * PI),
+        })
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        Some(self.aabb)
+    }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
+            let area = PI * self.radius * self.radius;
+            let dist_squared = rec.t * rec.t * v.length_squared();
+            let cosine = v.dot(self.uvw.w).abs() / v.length();
+
+            return dist_squared / (cosine * area);
+        }
+        0.
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let r1: f32 = rng.gen();
+        let r2: f32 = rng.gen();
+
+        // Taking the square root of r1 spreads the points uniformly over the area
+        let r = self.radius * r1.sqrt();
+        let phi = 2. * PI * r2;
+        let random_point = self.center + self.uvw.local(vec3a(r * phi.cos(), r * phi.sin(), 0.));
+        random_point - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{DiffuseLight, Lambertian},
+        rng::set_seed,
+        vec3::{random_in_unit_sphere, Color},
+    };
+
+    #[test]
+    fn one_sided_lights_only_shine_from_the_front() {
+        let disk = Disk::new(
+            Vec3A::ZERO,
+            Vec3A::Z,
+            1.,
+            Arc::new(DiffuseLight::from_color(Color::ONE)),
+        );
+        let emitted = |from_z: f32| {
+            let ray = Ray::new(vec3a(0.2, 0.3, from_z), vec3a(0., 0., -from_z), 0.);
+            let rec = disk.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.p)
+        };
+
+        assert_eq!(emitted(1.), Color::ONE);
+        assert_eq!(emitted(-1.), Color::ZERO);
+    }
+
+    #[test]
+    fn light_sampling_density_integrates_to_one() {
+        set_seed(10);
+        let disk = Disk::new(
+            vec3a(0., 2., 0.),
+            vec3a(0., -1., 1.),
+            1.,
+            Arc::new(Lambertian::from_color(Color::ONE)),
+        );
+        let origin = vec3a(0.5, 0., 0.);
+
+        // Uniform directions over the sphere have a density of 1 / 4π
+        let n = 50000;
+        let sum: f32 = (0..n)
+            .map(|_| disk.pdf_value(origin, random_in_unit_sphere().normalize()))
+            .sum();
+        let integral = 4. * PI * sum / n as f32;
+        assert!((integral - 1.).abs() < 0.05, "{}", integral);
+
+        // Sampled directions all hit the disk
+        for _ in 0..1000 {
+            assert!(disk.pdf_value(origin, disk.random(origin)) > 0.);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use glam::{vec2, vec3a, Affine3A, Vec2, Vec3A};
+use image::{ImageBuffer, Luma};
+
+use crate::{bvh::aabb::AABB, material::HitRecord, material::Material, ray::Ray};
+
+use super::{triangle_mesh::TriangleMesh, Hittable, Transformable};
+
+// Terrain whose elevations are sampled from a grayscale image, one vertex per pixel. It lies
+// on the XZ plane centered on the origin, with `spacing` between neighboring samples along x
+// and z, and white pixels `height` above black ones. The image rows run along +z, and the UVs
+// map the whole image over the terrain so that textures can be draped on it.
+pub struct HeightField {
+    mesh: TriangleMesh,
+}
+
+impl HeightField {
+    pub fn new(
+        heights: &ImageBuffer<Luma<u16>, Vec<u16>>,
+        spacing: f32,
+        height: f32,
+        material: Arc<dyn Material>,
+    ) -> HeightField {
+        let (width, depth) = (heights.width() as usize, heights.height() as usize);
+        let elevation = |x: usize, z: usize| {
+            height * heights.get_pixel(x as u32, z as u32)[0] as f32 / u16::MAX as f32
+        };
+        let origin = -0.5 * spacing * vec3a((width - 1) as f32, 0., (depth - 1) as f32);
+
+        let mut positions: Vec<Vec3A> = Vec::with_capacity(width * depth);
+        let mut normals: Vec<Vec3A> = Vec::with_capacity(width * depth);
+        let mut uvs: Vec<Vec2> = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                positions
+                    .push(origin + vec3a(spacing * x as f32, elevation(x, z), spacing * z as f32));
+
+                // Slopes from the neighboring samples, one-sided on the borders
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(width - 1));
+                let (z0, z1) = (z.saturating_sub(1), (z + 1).min(depth - 1));
+                let dx =
+                    (elevation(x1, z) - elevation(x0, z)) / (spacing * (x1 - x0).max(1) as f32);
+                let dz =
+                    (elevation(x, z1) - elevation(x, z0)) / (spacing * (z1 - z0).max(1) as f32);
+                normals.push(vec3a(-dx, 1., -dz).normalize());
+
+                // Image textures have v pointing up the image
+                uvs.push(vec2(
+                    x as f32 / (width - 1).max(1) as f32,
+                    1. - z as f32 / (depth - 1).max(1) as f32,
+                ));
+            }
+        }
+
+        // Two triangles per cell, wound so that they face +y
+        let index = |x: usize, z: usize| z * width + x;
+        let mut triangles: Vec<[usize; 3]> = Vec::with_capacity(2 * width * depth);
+        for z in 0..depth.saturating_sub(1) {
+            for x in 0..width.saturating_sub(1) {
+                triangles.push([index(x, z), index(x, z + 1), index(x + 1, z)]);
+                triangles.push([index(x + 1, z), index(x, z + 1), index(x + 1, z + 1)]);
+            }
+        }
+
+        // Neighboring cells share their edges
+        let mut mesh = TriangleMesh::new(positions, Some(normals), Some(uvs), triangles, material);
+        mesh.set_watertight(true);
+        HeightField { mesh }
+    }
+}
+
+impl Transformable for HeightField {
+    fn apply_transform(&mut self, other: Affine3A) {
+        self.mesh.apply_transform(other);
+    }
+}
+
+impl Hittable for HeightField {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.mesh.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.mesh.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, vec3::Color};
+
+    // Slope rising along +x, up to `height` on the right column
+    fn ramp(width: u32, height: f32) -> HeightField {
+        let heights = ImageBuffer::from_fn(width, 3, |x, _| {
+            Luma([(u16::MAX as u32 * x / (width - 1)) as u16])
+        });
+        let material = Arc::new(Lambertian::from_color(Color::ONE));
+        HeightField::new(&heights, 1., height, material)
+    }
+
+    #[test]
+    fn terrain_is_centered_on_the_origin() {
+        let aabb = ramp(5, 2.).bounding_box(0., 1.).unwrap();
+        assert!((aabb.min - vec3a(-2., 0., -1.)).abs().max_element() < 1e-3);
+        assert!((aabb.max - vec3a(2., 2., 1.)).abs().max_element() < 1e-3);
+    }
+
+    #[test]
+    fn rays_from_above_hit_the_slope() {
+        let terrain = ramp(5, 2.);
+        // The ramp rises by 0.5 per unit along x, from y = 0 at x = -2
+        for x in [-1.5, -0.3, 0.8, 1.7] {
+            let ray = Ray::new(vec3a(x, 5., 0.2), -Vec3A::Y, 0.);
+            let rec = terrain.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            assert!((rec.p.y - 0.5 * (x + 2.)).abs() < 1e-4, "{}", x);
+            // Smooth normals lean away from the rise
+            let expected = vec3a(-0.5, 1., 0.).normalize();
+            assert!((rec.normal - expected).length() < 1e-4, "{:?}", rec.normal);
+        }
+    }
+}
@@ -1,19 +1,25 @@
 pub mod aarect;
 pub mod constant_medium;
+pub mod csg;
 pub mod cuboid;
+pub mod disk;
+pub mod height_field;
+pub mod quad;
 pub mod sphere;
 pub mod transform;
 pub mod triangle;
+pub mod triangle_mesh;
+pub mod volumetric_medium;
 
 use std::cmp::Ordering;
 use std::sync::Arc;
 
 use glam::{vec3a, Affine3A, Vec3A};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
-use crate::bvh::aabb::{surrounding_box, AABB};
-use crate::bvh::Bounded;
-use crate::{material::HitRecord, ray::Ray};
+use crate::bvh::aabb::{surrounding_box, surrounding_box_vec, AABB};
+use crate::bvh::{Bounded, BVH};
+use crate::{material::HitRecord, ray::Ray, rng::rng};
 
 use self::sphere::Sphere;
 use self::triangle::Triangle;
@@ -41,6 +47,44 @@ impl Primitive {
     }
 }
 
+// Flat BVH over concrete primitives, intersected without dynamic dispatch in its leaves
+pub struct PrimitiveBVH {
+    primitives: Vec<Primitive>,
+    bvh: BVH,
+    aabb: AABB,
+}
+
+impl PrimitiveBVH {
+    pub fn new(primitives: Vec<Primitive>) -> PrimitiveBVH {
+        let bvh = BVH::new(&primitives);
+        let aabb = surrounding_box_vec(&primitives.iter().map(|p| p.aabb()).collect::<Vec<_>>());
+
+        PrimitiveBVH {
+            primitives,
+            bvh,
+            aabb,
+        }
+    }
+}
+
+impl Transformable for PrimitiveBVH {}
+
+impl Hittable for PrimitiveBVH {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.bvh.traverse_nearest(
+            ray,
+            &self.primitives,
+            |primitive, t_min, t_max| primitive.hit(ray, t_min, t_max),
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        Some(self.aabb)
+    }
+}
+
 // TODO: Think about having sized hittables or rethink the way we store objects
 pub type Hittables = Vec<Arc<dyn Hittable>>;
 
@@ -59,6 +103,37 @@ pub trait Hittable: Transformable + Send + Sync {
     fn random(&self, origin: Vec3A) -> Vec3A {
         Vec3A::new(1., 0., 0.)
     }
+    // Spans of the whole line of the ray inside a closed solid, in order, as the hits where it
+    // enters and leaves them with outward normals. Only `Sphere`, `AaBox`, `Csg` and `Transform`
+    // of these enclose a volume, the other hittables return `None`.
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        None
+    }
+}
+
+impl<H: Hittable + ?Sized> Transformable for Arc<H> {}
+
+// Lets a shared trait object stand in wherever a concrete world type is expected
+impl<H: Hittable + ?Sized> Hittable for Arc<H> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        (**self).bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        (**self).pdf_value(origin, v)
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        (**self).random(origin)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        (**self).intervals(ray)
+    }
 }
 
 impl Transformable for Hittables {}
@@ -120,11 +195,71 @@ impl Hittable for Hittables {
     }
 
     fn random(&self, origin: Vec3A) -> Vec3A {
-        let mut rng = thread_rng();
+        let mut rng = rng();
         self[rng.gen_range(0..self.len())].random(origin)
     }
 }
 
+// Objects picked in proportion to their weights rather than uniformly when sampling directions
+// toward them, e.g. lights by their power
+pub struct WeightedHittables {
+    objects: Hittables,
+    probabilities: Vec<f32>,
+    cdf: Vec<f32>,
+}
+
+impl WeightedHittables {
+    // Objects whose weights are all zero are picked uniformly
+    pub fn new(objects: Hittables, weights: &[f32]) -> WeightedHittables {
+        let total: f32 = weights.iter().sum();
+        let probabilities: Vec<f32> = if total > 0. {
+            weights.iter().map(|weight| weight / total).collect()
+        } else {
+            vec![1. / objects.len() as f32; objects.len()]
+        };
+        let cdf = probabilities
+            .iter()
+            .scan(0., |sum, p| {
+                *sum += p;
+                Some(*sum)
+            })
+            .collect();
+
+        WeightedHittables {
+            objects,
+            probabilities,
+            cdf,
+        }
+    }
+}
+
+impl Transformable for WeightedHittables {}
+
+impl Hittable for WeightedHittables {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.objects.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.objects.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        self.objects
+            .iter()
+            .zip(self.probabilities.iter())
+            .map(|(object, p)| p * object.pdf_value(origin, v))
+            .sum()
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        // Rounding can leave the last sum a little under 1
+        let u = rng().gen::<f32>() * self.cdf[self.cdf.len() - 1];
+        let i = self.cdf.partition_point(|sum| *sum <= u);
+        self.objects[i.min(self.objects.len() - 1)].random(origin)
+    }
+}
+
 pub struct BVHNode {
     pub left: Arc<dyn Hittable>,
     pub right: Arc<dyn Hittable>,
@@ -158,12 +293,19 @@ impl Hittable for BVHNode {
 }
 
 impl BVHNode {
-    pub fn new(src_objects: Hittables, time0: f32, time1: f32) -> Arc<dyn Hittable> {
+    // The root of the tree over `src_objects`, as a hittable that can be nested in other worlds
+    pub fn build(src_objects: Hittables, time0: f32, time1: f32) -> Arc<dyn Hittable> {
         let mut objects = src_objects;
         let left: Arc<dyn Hittable>;
         let right: Arc<dyn Hittable>;
 
-        let axis = random_int(0, 2);
+        // Splitting along the longest extent keeps the tree identical from one run to the next
+        let axis = objects
+            .iter()
+            .map(|object| object.bounding_box(time0, time1).unwrap())
+            .reduce(surrounding_box)
+            .unwrap()
+            .longest_axis();
 
         let span = objects.len();
 
@@ -182,8 +324,8 @@ impl BVHNode {
             objects.sort_by(|a, b| box_compare(a, b, axis));
 
             let mid = span / 2;
-            left = BVHNode::new(objects[..mid].to_vec(), time0, time1);
-            right = BVHNode::new(objects[mid..].to_vec(), time0, time1);
+            left = BVHNode::build(objects[..mid].to_vec(), time0, time1);
+            right = BVHNode::build(objects[mid..].to_vec(), time0, time1);
         }
 
         let out: Arc<dyn Hittable> = Arc::new(BVHNode {
@@ -199,11 +341,6 @@ impl BVHNode {
     }
 }
 
-fn random_int(min: i32, max: i32) -> usize {
-    let mut rng = thread_rng();
-    rng.gen_range(min..max) as usize
-}
-
 fn box_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: usize) -> Ordering {
     if let Some(box_a) = a.bounding_box(0., 0.) {
         if let Some(box_b) = b.bounding_box(0., 0.) {
@@ -223,11 +360,7 @@ impl Hittable for FlipFace {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         if let Some(rec) = self.hittable.hit(ray, t_min, t_max) {
             let mut new_rec = rec;
-            new_rec.normal = Vec3A::new(
-                new_rec.normal[0],
-                -new_rec.normal[1].abs(),
-                new_rec.normal[2],
-            );
+            new_rec.normal = -new_rec.normal;
             return Some(new_rec);
         }
         None
@@ -236,4 +369,12 @@ impl Hittable for FlipFace {
     fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
         self.hittable.bounding_box(time0, time1)
     }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        self.hittable.pdf_value(origin, v)
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        self.hittable.random(origin)
+    }
 }
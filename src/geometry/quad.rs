@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use glam::{Affine3A, Vec3A};
+use rand::Rng;
+
+use crate::{
+    bvh::aabb::AABB,
+    material::{HitRecord, Material},
+    ray::Ray,
+    rng::rng,
+};
+
+use super::{Hittable, Transformable};
+
+// Parallelogram spanned by the edges `u` and `v` from the corner `q`, facing `u × v`. Hits report
+// that normal from either side, so that one-sided lights only shine from the front. Single-sided
+// quads can only be hit from the front.
+#[allow(unused)]
+pub struct Quad {
+    q: Vec3A,
+    u: Vec3A,
+    v: Vec3A,
+    material: Arc<dyn Material>,
+    double_sided: bool,
+    normal: Vec3A,
+    d: f32,
+    w: Vec3A,
+    area: f32,
+    aabb: AABB,
+}
+
+#[allow(unused)]
+impl Quad {
+    pub fn new(q: Vec3A, u: Vec3A, v: Vec3A, material: Arc<dyn Material>) -> Quad {
+        let mut quad = Quad {
+            q,
+            u,
+            v,
+            material,
+            double_sided: true,
+            normal: Vec3A::ZERO,
+            d: 0.,
+            w: Vec3A::ZERO,
+            area: 0.,
+            aabb: AABB {
+                min: Vec3A::ZERO,
+                max: Vec3A::ZERO,
+            },
+        };
+        quad.update();
+        quad
+    }
+
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        self.double_sided = double_sided;
+    }
+
+    // Plane equation, inverse basis and bounds derived from the corner and the edges
+    fn update(&mut self) {
+        let n = self.u.cross(self.v);
+        self.normal = n.normalize();
+        self.d = self.normal.dot(self.q);
+        self.w = n / n.length_squared();
+        self.area = n.length();
+
+        // Added padding for non-zero width AABB
+        let corners = [
+            self.q,
+            self.q + self.u,
+            self.q + self.v,
+            self.q + self.u + self.v,
+        ];
+        let min = corners
+            .iter()
+            .fold(Vec3A::splat(f32::INFINITY), |a, &c| a.min(c));
+        let max = corners
+            .iter()
+            .fold(Vec3A::splat(f32::NEG_INFINITY), |a, &c| a.max(c));
+        self.aabb = AABB {
+            min: min - Vec3A::splat(0.0001),
+            max: max + Vec3A::splat(0.0001),
+        };
+    }
+}
+
+impl Transformable for Quad {
+    fn apply_transform(&mut self, other: Affine3A) {
+        self.q = other.transform_point3a(self.q);
+        self.u = other.transform_vector3a(self.u);
+        self.v = other.transform_vector3a(self.v);
+
+        self.update();
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let denom = self.normal.dot(ray.direction());
+
+        // Parallel to the plane, or hitting the back of a single-sided quad
+        if denom.abs() < 1e-8 || (denom > 0. && !self.double_sided) {
+            return None;
+        }
+
+        let t = (self.d - self.normal.dot(ray.origin())) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // Coordinates of the hit in the (u, v) basis, which must both be in [0, 1]
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord {
+            p,
+            normal: self.normal,
+            t,
+            mat: self.material.clone(),
+            u: alpha,
+            v: beta,
+        })
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        Some(self.aabb)
+    }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
+            let dist_squared = rec.t * rec.t * v.length_squared();
+            let cosine = v.dot(rec.normal).abs() / v.length();
+
+            return dist_squared / (cosine * self.area);
+        }
+        0.
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let random_point = self.q + rng.gen::<f32>() * self.u + rng.gen::<f32>() * self.v;
+        random_point - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3a;
+
+    use super::*;
+    use crate::{material::DiffuseLight, vec3::Color};
+
+    #[test]
+    fn one_sided_lights_only_shine_from_the_front() {
+        let emitted = |two_sided: bool, from_z: f32| {
+            let mut light = DiffuseLight::from_color(Color::ONE);
+            light.set_two_sided(two_sided);
+            // Facing +z
+            let quad = Quad::new(
+                vec3a(-1., -1., 0.),
+                vec3a(2., 0., 0.),
+                vec3a(0., 2., 0.),
+                Arc::new(light),
+            );
+            let ray = Ray::new(vec3a(0., 0., from_z), vec3a(0., 0., -from_z), 0.);
+            let rec = quad.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.p)
+        };
+
+        assert_eq!(emitted(false, 1.), Color::ONE);
+        assert_eq!(emitted(false, -1.), Color::ZERO);
+        assert_eq!(emitted(true, -1.), Color::ONE);
+    }
+}
@@ -1,8 +1,8 @@
-use std::f32::{consts::PI, INFINITY};
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 use glam::{vec3a, Affine3A, Vec3A};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::bvh::Bounded;
 use crate::vec3::OrthNormBasis;
@@ -10,6 +10,7 @@ use crate::{
     bvh::aabb::{surrounding_box, AABB},
     material::{HitRecord, Material},
     ray::Ray,
+    rng::rng,
 };
 
 use super::{Hittable, Transformable};
@@ -31,12 +32,20 @@ impl Sphere {
         }
     }
 
-    fn get_sphere_uv(&self, p: Vec3A) -> (f32, f32) {
-        let theta = (-p[1]).acos();
-        let phi = (-p[2]).atan2(p[0]) + PI;
-
-        // (u, v)
-        (phi / (2. * PI), theta / PI)
+    // The normal points outward, whichever side the ray comes from
+    fn record(&self, ray: &Ray, t: f32) -> HitRecord {
+        let p = ray.at(t);
+        let normal = (p - self.center) / self.radius;
+        let (u, v) = get_sphere_uv(normal);
+
+        HitRecord {
+            p,
+            normal,
+            t,
+            mat: self.material.clone(),
+            u,
+            v,
+        }
     }
 }
 
@@ -60,35 +69,12 @@ impl Hittable for Sphere {
 
             let mut root = (-b - sqrtd) / a;
             if t_min <= root && root <= t_max {
-                let p = ray.at(root);
-
-                let normal = (p - self.center) / self.radius;
-
-                let (u, v) = self.get_sphere_uv(normal);
-
-                return Some(HitRecord {
-                    p,
-                    normal,
-                    t: root,
-                    mat: self.material.clone(),
-                    u,
-                    v,
-                });
+                return Some(self.record(ray, root));
             }
 
             root = (-b + sqrtd) / a;
             if t_min <= root && root <= t_max {
-                let p = ray.at(root);
-                let (u, v) = self.get_sphere_uv(p);
-
-                return Some(HitRecord {
-                    p,
-                    normal: (p - self.center) / self.radius,
-                    t: root,
-                    mat: self.material.clone(),
-                    u,
-                    v,
-                });
+                return Some(self.record(ray, root));
             }
         }
         None
@@ -98,9 +84,26 @@ impl Hittable for Sphere {
         Some(self.aabb)
     }
 
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let oc: Vec3A = ray.origin() - self.center;
+        let a = ray.direction().length_squared();
+        let b = oc.dot(ray.direction());
+        let c = oc.length_squared() - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0. {
+            return Some(Vec::new());
+        }
+
+        let sqrtd = discriminant.sqrt();
+        Some(vec![(
+            self.record(ray, (-b - sqrtd) / a),
+            self.record(ray, (-b + sqrtd) / a),
+        )])
+    }
+
     fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
         if self
-            .hit(&Ray::new(origin, v, 0.), 0.0001, INFINITY)
+            .hit(&Ray::new(origin, v, 0.), 0.0001, f32::INFINITY)
             .is_none()
         {
             return 0.;
@@ -125,6 +128,15 @@ impl Bounded for Sphere {
     }
 }
 
+// Latitude and longitude of a point `p` of the unit sphere, mapped to [0, 1]
+fn get_sphere_uv(p: Vec3A) -> (f32, f32) {
+    let theta = (-p[1]).acos();
+    let phi = (-p[2]).atan2(p[0]) + PI;
+
+    // (u, v)
+    (phi / (2. * PI), theta / PI)
+}
+
 fn compute_sphere_aabb(center: Vec3A, radius: f32) -> AABB {
     AABB {
         min: center - vec3a(radius, radius, radius),
@@ -146,14 +158,6 @@ impl MovingSphere {
         self.center0
             + ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
     }
-
-    fn get_sphere_uv(&self, p: Vec3A) -> (f32, f32) {
-        let theta = (-p[1]).acos();
-        let phi = (-p[2]).atan2(p[0]) + PI;
-
-        // (u, v)
-        ((phi / 2. * PI), theta / PI)
-    }
 }
 
 impl Transformable for MovingSphere {}
@@ -171,11 +175,14 @@ impl Hittable for MovingSphere {
             let mut root = (-b - sqrtd) / a;
             if t_min <= root && root <= t_max {
                 let p = ray.at(root);
-                let (u, v) = self.get_sphere_uv(p);
+
+                let normal = (p - self.center(ray.time())) / self.radius;
+
+                let (u, v) = get_sphere_uv(normal);
 
                 return Some(HitRecord {
                     p,
-                    normal: (p - self.center(ray.time())) / self.radius,
+                    normal,
                     t: root,
                     mat: self.material.clone(),
                     u,
@@ -186,11 +193,14 @@ impl Hittable for MovingSphere {
             root = (-b + sqrtd) / a;
             if t_min <= root && root <= t_max {
                 let p = ray.at(root);
-                let (u, v) = self.get_sphere_uv(p);
+
+                let normal = (p - self.center(ray.time())) / self.radius;
+
+                let (u, v) = get_sphere_uv(normal);
 
                 return Some(HitRecord {
                     p,
-                    normal: (p - self.center(ray.time())) / self.radius,
+                    normal,
                     t: root,
                     mat: self.material.clone(),
                     u,
@@ -215,7 +225,7 @@ impl Hittable for MovingSphere {
 }
 
 fn random_to_sphere(radius: f32, dist_squared: f32) -> Vec3A {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     let r1: f32 = rng.gen();
     let r2: f32 = rng.gen();
     let z = 1. + r2 * ((1. - radius * radius / dist_squared).sqrt() - 1.);
@@ -226,3 +236,78 @@ fn random_to_sphere(radius: f32, dist_squared: f32) -> Vec3A {
 
     vec3a(x, y, z)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, vec3::Color};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::from_color(Color::ONE))
+    }
+
+    #[test]
+    fn uvs_are_the_same_from_both_roots() {
+        let sphere = Sphere::new(vec3a(10., -4., 25.), 3., material());
+        let p = sphere.center + 3. * vec3a(0.36, 0.48, -0.8);
+
+        // `p` is the far root of a ray coming from the opposite side, and the near root of a ray
+        // coming toward it
+        let through = Ray::new(
+            p + 10. * vec3a(-0.36, -0.48, 0.8),
+            vec3a(0.36, 0.48, -0.8),
+            0.,
+        );
+        let far = sphere.hit(&through, 8., f32::INFINITY).unwrap();
+        let toward = Ray::new(
+            p + 2. * vec3a(0.36, 0.48, -0.8),
+            vec3a(-0.36, -0.48, 0.8),
+            0.,
+        );
+        let near = sphere.hit(&toward, 0.001, f32::INFINITY).unwrap();
+
+        assert!((far.p - p).length() < 1e-4 && (near.p - p).length() < 1e-4);
+        assert!((far.u - near.u).abs() < 1e-5 && (far.v - near.v).abs() < 1e-5);
+    }
+
+    #[test]
+    fn uvs_follow_latitude_and_longitude() {
+        let sphere = Sphere::new(vec3a(10., -4., 25.), 3., material());
+        let uv = |direction: Vec3A| {
+            let ray = Ray::new(sphere.center + 5. * direction, -direction, 0.);
+            let rec = sphere.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            (rec.u, rec.v)
+        };
+
+        let (u, v) = uv(vec3a(1., 0., 0.));
+        assert!((u - 0.5).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+        let (u, v) = uv(vec3a(0., 0., 1.));
+        assert!((u - 0.25).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+        let (_, v) = uv(vec3a(0., 1., 0.));
+        assert!((v - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn moving_sphere_uvs_match_the_static_sphere() {
+        let moving = MovingSphere {
+            center0: vec3a(0., 0., 0.),
+            center1: vec3a(4., 2., -6.),
+            time0: 0.,
+            time1: 1.,
+            radius: 1.5,
+            material: material(),
+        };
+        let time = 0.25;
+        let sphere = Sphere::new(moving.center(time), 1.5, material());
+
+        for direction in [vec3a(0., 0., -1.), vec3a(1., -2., 0.5), vec3a(-3., 1., 2.)] {
+            let origin = sphere.center - 4. * direction.normalize() + vec3a(0.1, 0.2, 0.);
+            let ray = Ray::new(origin, direction, time);
+            let a = moving.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            let b = sphere.hit(&ray, 0.001, f32::INFINITY).unwrap();
+
+            assert!((a.p - b.p).length() < 1e-5);
+            assert!((a.u - b.u).abs() < 1e-5 && (a.v - b.v).abs() < 1e-5);
+        }
+    }
+}
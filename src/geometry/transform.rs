@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use glam::{vec3a, Vec3A};
+use glam::{vec3a, Affine3A, Vec3A};
 
 use crate::{bvh::aabb::AABB, material::HitRecord, ray::Ray};
 
@@ -133,3 +133,224 @@ impl Hittable for RotateY {
         self.bbox
     }
 }
+
+// Arbitrary affine transform of `base`, which can scale it non-uniformly or shear it. Rays are
+// brought into object space, and hits back into world space with normals transformed by the
+// inverse transpose.
+pub struct Transform {
+    base: Arc<dyn Hittable>,
+    object_to_world: Affine3A,
+    world_to_object: Affine3A,
+    bbox: Option<AABB>,
+}
+
+impl Transform {
+    pub fn new(base: Arc<dyn Hittable>, object_to_world: Affine3A) -> Transform {
+        let bbox = transform_bbox(base.as_ref(), object_to_world);
+        Transform {
+            base,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+            bbox,
+        }
+    }
+
+    // The direction isn't normalized, so distances along the ray are the same in both spaces
+    fn to_object(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.world_to_object.transform_point3a(ray.origin()),
+            self.world_to_object.transform_vector3a(ray.direction()),
+            ray.time(),
+        )
+    }
+
+    fn to_world(&self, mut rec: HitRecord) -> HitRecord {
+        rec.p = self.object_to_world.transform_point3a(rec.p);
+        rec.normal = (self.world_to_object.matrix3.transpose() * rec.normal).normalize();
+        rec
+    }
+}
+
+// Instances share their base, so that many copies of a mesh only cost one set of triangles and
+// one BVH: rays are brought into the base's space instead of the geometry being moved
+pub type Instance = Transform;
+
+// Box around the transformed corners of the base's box
+fn transform_bbox(base: &dyn Hittable, transform: Affine3A) -> Option<AABB> {
+    let bbox = base.bounding_box(0., 1.)?;
+    let mut min = Vec3A::splat(f32::INFINITY);
+    let mut max = Vec3A::splat(f32::NEG_INFINITY);
+
+    for i in 0..8 {
+        let corner = vec3a(
+            if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+            if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+            if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+        );
+        let corner = transform.transform_point3a(corner);
+        min = min.min(corner);
+        max = max.max(corner);
+    }
+
+    Some(AABB { min, max })
+}
+
+impl Transformable for Transform {
+    fn apply_transform(&mut self, other: Affine3A) {
+        self.object_to_world = other * self.object_to_world;
+        self.world_to_object = self.object_to_world.inverse();
+        self.bbox = transform_bbox(self.base.as_ref(), self.object_to_world);
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let local = self.to_object(ray);
+        let rec = self.base.hit(&local, t_min, t_max)?;
+        Some(self.to_world(rec))
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        self.bbox
+    }
+
+    // The base's density over the directions of its own space, times the change of solid angle
+    // when a direction `w` of that space is mapped to the unit vector along M w. That is
+    // |M w|^3 / |det M| for a unit `w`, where |M w| = |v| / |w| for `w` = M^-1 v.
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        let local_origin = self.world_to_object.transform_point3a(origin);
+        let local_v = self.world_to_object.transform_vector3a(v);
+        let stretch = v.length() / local_v.length();
+        self.base.pdf_value(local_origin, local_v) * stretch.powi(3)
+            / self.object_to_world.matrix3.determinant().abs()
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let local_origin = self.world_to_object.transform_point3a(origin);
+        self.object_to_world
+            .transform_vector3a(self.base.random(local_origin))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Option<Vec<(HitRecord, HitRecord)>> {
+        let local = self.to_object(ray);
+        let intervals = self.base.intervals(&local)?;
+        Some(
+            intervals
+                .into_iter()
+                .map(|(enter, exit)| (self.to_world(enter), self.to_world(exit)))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::{
+        geometry::{sphere::Sphere, triangle_mesh::TriangleMesh},
+        material::Lambertian,
+        rng::set_seed,
+        vec3::random_in_unit_sphere,
+    };
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(
+            Vec3A::ZERO,
+            1.,
+            Arc::new(Lambertian::from_rgb(0.5, 0.5, 0.5)),
+        ))
+    }
+
+    #[test]
+    fn scaled_sphere_samples_like_a_bigger_sphere() {
+        let transform = Transform::new(
+            unit_sphere(),
+            Affine3A::from_scale_rotation_translation(
+                Vec3A::splat(2.).into(),
+                Default::default(),
+                vec3a(0., 1., -5.).into(),
+            ),
+        );
+        let sphere = Sphere::new(
+            vec3a(0., 1., -5.),
+            2.,
+            Arc::new(Lambertian::from_rgb(0.5, 0.5, 0.5)),
+        );
+
+        let origin = vec3a(0.5, 0., 0.);
+        for v in [vec3a(-0.5, 1., -5.), vec3a(0., 0., -1.), vec3a(1., 0., 0.)] {
+            let (expected, value) = (sphere.pdf_value(origin, v), transform.pdf_value(origin, v));
+            assert!(
+                (value - expected).abs() <= 1e-3 * expected,
+                "{} vs {}",
+                value,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn sheared_sphere_density_integrates_to_one() {
+        set_seed(1);
+        let transform = Transform::new(
+            unit_sphere(),
+            Affine3A::from_cols_array(&[3., 0., 0., 1., 0.5, 0., 0., 0., 1., 0., 0., -3.]),
+        );
+        let origin = Vec3A::ZERO;
+
+        // Directions drawn by `random` point at the object
+        for _ in 0..100 {
+            let v = transform.random(origin);
+            assert!(transform.pdf_value(origin, v) > 0.);
+        }
+
+        // Monte Carlo estimate of the integral over the sphere of directions
+        let n = 50000;
+        let sum: f32 = (0..n)
+            .map(|_| transform.pdf_value(origin, random_in_unit_sphere().normalize()))
+            .sum();
+        let integral = 4. * PI * sum / n as f32;
+        assert!((integral - 1.).abs() < 0.05, "{}", integral);
+    }
+
+    #[test]
+    fn instanced_meshes_can_be_lights() {
+        set_seed(2);
+        // Unit square in the xy plane, turned to face the origin from 2 units away along x
+        let square = TriangleMesh::new(
+            vec![
+                vec3a(0., 0., 0.),
+                vec3a(1., 0., 0.),
+                vec3a(1., 1., 0.),
+                vec3a(0., 1., 0.),
+            ],
+            None,
+            None,
+            vec![[0, 1, 2], [0, 2, 3]],
+            Arc::new(Lambertian::from_rgb(0.5, 0.5, 0.5)),
+        );
+        let instance = Instance::new(
+            Arc::new(square),
+            Affine3A::from_translation(vec3a(2., -0.5, -0.5).into())
+                * Affine3A::from_rotation_y(-PI / 2.),
+        );
+        let origin = Vec3A::ZERO;
+
+        for _ in 0..100 {
+            let v = instance.random(origin);
+            assert!(instance
+                .hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY)
+                .is_some());
+            assert!(instance.pdf_value(origin, v) > 0.);
+        }
+
+        let n = 50000;
+        let sum: f32 = (0..n)
+            .map(|_| instance.pdf_value(origin, random_in_unit_sphere().normalize()))
+            .sum();
+        let integral = 4. * PI * sum / n as f32;
+        assert!((integral - 1.).abs() < 0.05, "{}", integral);
+    }
+}
@@ -1,17 +1,20 @@
 use std::sync::Arc;
 
 use glam::{vec3a, Vec3A};
+use rand::Rng;
 
 use crate::{
     bvh::{aabb::AABB, Bounded},
     material::{HitRecord, Material},
     ray::Ray,
+    rng::rng,
 };
 
 use super::{Hittable, Transformable};
 
 pub struct Triangle {
     vertices: [Vec3A; 3],
+    normals: Option<[Vec3A; 3]>,
     material: Arc<dyn Material>,
     double_sided: bool,
     v0v1: Vec3A,
@@ -23,6 +26,7 @@ impl Triangle {
     pub fn new(v0: Vec3A, v1: Vec3A, v2: Vec3A, material: Arc<dyn Material>) -> Triangle {
         Triangle {
             vertices: [v0, v1, v2],
+            normals: None,
             material,
             double_sided: false,
             v0v1: v1 - v0,
@@ -30,47 +34,95 @@ impl Triangle {
             aabb: compute_triangle_aabb(&[v0, v1, v2]),
         }
     }
+
+    // Smooth shaded triangle whose shading normal is interpolated from its vertex normals
+    pub fn with_normals(
+        vertices: [Vec3A; 3],
+        normals: [Vec3A; 3],
+        material: Arc<dyn Material>,
+    ) -> Triangle {
+        let [v0, v1, v2] = vertices;
+        Triangle {
+            normals: Some(normals),
+            ..Triangle::new(v0, v1, v2, material)
+        }
+    }
+
+    // Triangle that is also hit from the back, see `set_double_sided`
+    pub fn new_double_sided(
+        v0: Vec3A,
+        v1: Vec3A,
+        v2: Vec3A,
+        material: Arc<dyn Material>,
+    ) -> Triangle {
+        Triangle {
+            double_sided: true,
+            ..Triangle::new(v0, v1, v2, material)
+        }
+    }
+
+    // Double-sided triangles are also hit from the back, with their normal flipped
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        self.double_sided = double_sided;
+    }
 }
 
 impl Transformable for Triangle {}
 
 impl Hittable for Triangle {
     fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let pvec = ray.direction().cross(self.v0v2);
-        let det = self.v0v1.dot(pvec);
-
-        if det > 1e-5 || (det < -1e-5 && self.double_sided) {
-            let inv_det = 1. / det;
-
-            let tvec = ray.origin() - self.vertices[0];
-            let u = tvec.dot(pvec) * inv_det;
-
-            if (0. ..=1.).contains(&u) {
-                let qvec = tvec.cross(self.v0v1);
-                let v = ray.direction().dot(qvec) * inv_det;
-
-                if (0. ..1. - u).contains(&v) {
-                    let t = self.v0v2.dot(qvec) * inv_det;
-
-                    if (t_min..=t_max).contains(&t) {
-                        return Some(HitRecord {
-                            p: ray.at(t),
-                            normal: self.v0v1.cross(self.v0v2).normalize() * det.signum(),
-                            t,
-                            mat: self.material.clone(),
-                            u,
-                            v,
-                        });
-                    }
-                }
-            }
-        }
-        None
+        let (t, u, v, det) = intersect_triangle(
+            ray,
+            self.vertices[0],
+            self.v0v1,
+            self.v0v2,
+            self.double_sided,
+            t_min,
+            t_max,
+        )?;
+
+        let normal = match self.normals {
+            Some([n0, n1, n2]) => ((1. - u - v) * n0 + u * n1 + v * n2).normalize(),
+            None => self.v0v1.cross(self.v0v2).normalize(),
+        };
+
+        Some(HitRecord {
+            p: ray.at(t),
+            normal: normal * det.signum(),
+            t,
+            mat: self.material.clone(),
+            u,
+            v,
+        })
     }
 
     fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
         Some(self.aabb)
     }
+
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        if let Some(rec) = self.hit(&Ray::new(origin, v, 0.), 0.001, f32::INFINITY) {
+            // The area measure only depends on the geometric normal, not on the shading one
+            let geometric_normal = self.v0v1.cross(self.v0v2);
+            let area = 0.5 * geometric_normal.length();
+            let dist_squared = rec.t * rec.t * v.length_squared();
+            let cosine = v.dot(geometric_normal).abs() / (v.length() * geometric_normal.length());
+
+            return dist_squared / (cosine * area);
+        }
+        0.
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let r1: f32 = rng.gen();
+        let r2: f32 = rng.gen();
+
+        // Taking the square root of r1 makes the barycentric coordinates uniform over the area
+        let s = r1.sqrt();
+        let random_point = self.vertices[0] + s * (1. - r2) * self.v0v1 + s * r2 * self.v0v2;
+        random_point - origin
+    }
 }
 
 impl Bounded for Triangle {
@@ -79,7 +131,128 @@ impl Bounded for Triangle {
     }
 }
 
-fn compute_triangle_aabb(vertices: &[Vec3A; 3]) -> AABB {
+// Whether the triangle (v0, v1, v2) has no area to speak of. The threshold is relative to the
+// length of its edges, so that the test doesn't depend on the scale of the model: it amounts to
+// the sine of the angle at `v0` being under 1e-6
+pub fn is_degenerate(v0: Vec3A, v1: Vec3A, v2: Vec3A) -> bool {
+    let (e1, e2) = (v1 - v0, v2 - v0);
+    e1.cross(e2).length_squared() <= 1e-12 * e1.length_squared() * e2.length_squared()
+}
+
+// Moller-Trumbore intersection of the triangle (v0, v0 + v0v1, v0 + v0v2), returning the hit
+// distance, the barycentric coordinates of the hit, and the determinant whose sign tells which
+// side was hit
+pub fn intersect_triangle(
+    ray: &Ray,
+    v0: Vec3A,
+    v0v1: Vec3A,
+    v0v2: Vec3A,
+    double_sided: bool,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32, f32)> {
+    let pvec = ray.direction().cross(v0v2);
+    let det = v0v1.dot(pvec);
+
+    if det > 1e-5 || (det < -1e-5 && double_sided) {
+        let inv_det = 1. / det;
+
+        let tvec = ray.origin() - v0;
+        let u = tvec.dot(pvec) * inv_det;
+
+        if (0. ..=1.).contains(&u) {
+            let qvec = tvec.cross(v0v1);
+            let v = ray.direction().dot(qvec) * inv_det;
+
+            if (0. ..1. - u).contains(&v) {
+                let t = v0v2.dot(qvec) * inv_det;
+
+                if (t_min..=t_max).contains(&t) {
+                    return Some((t, u, v, det));
+                }
+            }
+        }
+    }
+    None
+}
+
+// Watertight intersection of the triangle (v0, v1, v2) (Woop et al. 2013), with the same results
+// as `intersect_triangle`. The vertices are sheared into a space where the ray runs along +z from
+// the origin, so that the edge tests of neighboring triangles are computed from the same values
+// and rays through a shared edge can't slip between them.
+pub fn intersect_triangle_watertight(
+    ray: &Ray,
+    [v0, v1, v2]: [Vec3A; 3],
+    double_sided: bool,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32, f32)> {
+    let direction = ray.direction();
+
+    // Same sign as the Moller-Trumbore determinant: positive when the ray hits the front face
+    let det = -direction.dot((v1 - v0).cross(v2 - v0));
+    if det == 0. || (det < 0. && !double_sided) {
+        return None;
+    }
+
+    // The dominant axis of the direction becomes z, and x and y are swapped when it points
+    // backward to keep the winding
+    let abs = direction.abs();
+    let kz = if abs.x >= abs.y && abs.x >= abs.z {
+        0
+    } else if abs.y >= abs.z {
+        1
+    } else {
+        2
+    };
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if direction[kz] < 0. {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    let shear_x = direction[kx] / direction[kz];
+    let shear_y = direction[ky] / direction[kz];
+    let shear_z = 1. / direction[kz];
+
+    let [a, b, c] = [v0, v1, v2].map(|v| {
+        let p = v - ray.origin();
+        (
+            p[kx] - shear_x * p[kz],
+            p[ky] - shear_y * p[kz],
+            shear_z * p[kz],
+        )
+    });
+
+    // Scaled barycentric coordinates, recomputed in double precision when one of them falls on
+    // an edge
+    let mut e0 = c.0 * b.1 - c.1 * b.0;
+    let mut e1 = a.0 * c.1 - a.1 * c.0;
+    let mut e2 = b.0 * a.1 - b.1 * a.0;
+    if e0 == 0. || e1 == 0. || e2 == 0. {
+        let edge = |p: (f32, f32, f32), q: (f32, f32, f32)| {
+            (p.0 as f64 * q.1 as f64 - p.1 as f64 * q.0 as f64) as f32
+        };
+        e0 = edge(c, b);
+        e1 = edge(a, c);
+        e2 = edge(b, a);
+    }
+
+    if (e0 < 0. || e1 < 0. || e2 < 0.) && (e0 > 0. || e1 > 0. || e2 > 0.) {
+        return None;
+    }
+    let sum = e0 + e1 + e2;
+    if sum == 0. {
+        return None;
+    }
+
+    let t = (e0 * a.2 + e1 * b.2 + e2 * c.2) / sum;
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+
+    Some((t, e1 / sum, e2 / sum, det))
+}
+
+pub fn compute_triangle_aabb(vertices: &[Vec3A; 3]) -> AABB {
     let x_min = vertices[0][0].min(vertices[1][0].min(vertices[2][0])) - 0.0001;
     let y_min = vertices[0][1].min(vertices[1][1].min(vertices[2][1])) - 0.0001;
     let z_min = vertices[0][2].min(vertices[1][2].min(vertices[2][2])) - 0.0001;
@@ -92,3 +265,44 @@ fn compute_triangle_aabb(vertices: &[Vec3A; 3]) -> AABB {
         max: vec3a(x_max, y_max, z_max),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn back_faces_are_only_hit_when_double_sided() {
+        // Facing +z, with its vertices counterclockwise seen from there
+        let (v0, v1, v2) = (vec3a(0., 0., 0.), vec3a(1., 0., 0.), vec3a(0., 1., 0.));
+        let white = Arc::new(Lambertian::from_color(Vec3A::ONE));
+        let one_sided = Triangle::new(v0, v1, v2, white.clone());
+        let double_sided = Triangle::new_double_sided(v0, v1, v2, white);
+
+        let front = Ray::new(vec3a(0.25, 0.25, 1.), vec3a(0., 0., -1.), 0.);
+        let back = Ray::new(vec3a(0.25, 0.25, -1.), vec3a(0., 0., 1.), 0.);
+        let hit = |triangle: &Triangle, ray: &Ray| triangle.hit(ray, 0.001, f32::INFINITY);
+
+        assert_eq!(hit(&one_sided, &front).unwrap().normal, Vec3A::Z);
+        assert!(hit(&one_sided, &back).is_none());
+        assert_eq!(hit(&double_sided, &front).unwrap().normal, Vec3A::Z);
+        // Hit from the back, the normal faces the ray
+        assert_eq!(hit(&double_sided, &back).unwrap().normal, -Vec3A::Z);
+    }
+
+    #[test]
+    fn degenerate_test_does_not_depend_on_scale() {
+        let (v0, v1, v2) = (vec3a(0., 0., 0.), vec3a(1., 0., 0.), vec3a(0., 1., 0.));
+        for scale in [1e-4, 1., 1e4] {
+            assert!(!is_degenerate(scale * v0, scale * v1, scale * v2));
+        }
+    }
+
+    #[test]
+    fn flat_triangles_are_degenerate() {
+        let (v0, v1) = (vec3a(0., 0., 0.), vec3a(1., 2., 3.));
+        assert!(is_degenerate(v0, v1, 2. * v1));
+        assert!(is_degenerate(v0, v1, v1));
+        assert!(is_degenerate(v0, v0, v0));
+    }
+}
@@ -0,0 +1,350 @@
+use std::sync::Arc;
+
+use glam::{Affine3A, Vec2, Vec3A};
+use rand::Rng;
+
+use crate::{
+    bvh::{
+        aabb::{surrounding_box_vec, AABB},
+        BVH,
+    },
+    material::{HitRecord, Material},
+    ray::Ray,
+    rng::rng,
+};
+
+use super::{
+    triangle::{
+        compute_triangle_aabb, intersect_triangle, intersect_triangle_watertight, Triangle,
+    },
+    Hittable, Primitive, Transformable,
+};
+
+// Indexed triangle mesh sharing its vertices and material between all of its triangles, with
+// its own BVH over the triangle indices. Its triangles are intersected with Moller-Trumbore
+// unless it is made watertight, which is slower but leaves no gaps along the shared edges.
+// Single-sided meshes can only be hit from the front, where the triangles wind counterclockwise.
+pub struct TriangleMesh {
+    positions: Vec<Vec3A>,
+    normals: Option<Vec<Vec3A>>,
+    uvs: Option<Vec<Vec2>>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<dyn Material>,
+    watertight: bool,
+    double_sided: bool,
+    bvh: BVH,
+    aabb: AABB,
+    // Running sum of the triangle areas, to sample points uniformly over the surface
+    area_cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+    pub fn new(
+        positions: Vec<Vec3A>,
+        normals: Option<Vec<Vec3A>>,
+        uvs: Option<Vec<Vec2>>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> TriangleMesh {
+        let mut mesh = TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+            watertight: false,
+            double_sided: false,
+            bvh: BVH::new::<AABB>(&[]),
+            aabb: surrounding_box_vec(&[]),
+            area_cdf: Vec::new(),
+        };
+        mesh.build_bvh();
+        mesh
+    }
+
+    pub fn set_watertight(&mut self, watertight: bool) {
+        self.watertight = watertight;
+    }
+
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        self.double_sided = double_sided;
+    }
+
+    // The triangles as standalone primitives, e.g. for a `PrimitiveBVH`. They keep the vertex
+    // normals, the material and the sidedness, but not the texture coordinates.
+    pub fn primitives(&self) -> Vec<Primitive> {
+        self.indices
+            .iter()
+            .map(|triangle| {
+                let vertices = self.vertices(triangle);
+                let mut primitive = match &self.normals {
+                    Some(normals) => Triangle::with_normals(
+                        vertices,
+                        triangle.map(|i| normals[i]),
+                        self.material.clone(),
+                    ),
+                    None => {
+                        let [v0, v1, v2] = vertices;
+                        Triangle::new(v0, v1, v2, self.material.clone())
+                    }
+                };
+                primitive.set_double_sided(self.double_sided);
+                Primitive::Triangle(primitive)
+            })
+            .collect()
+    }
+
+    fn vertices(&self, triangle: &[usize; 3]) -> [Vec3A; 3] {
+        triangle.map(|i| self.positions[i])
+    }
+
+    fn build_bvh(&mut self) {
+        let bounds = self
+            .indices
+            .iter()
+            .map(|triangle| compute_triangle_aabb(&self.vertices(triangle)))
+            .collect::<Vec<_>>();
+
+        self.bvh = BVH::new(&bounds);
+        self.aabb = surrounding_box_vec(&bounds);
+
+        let mut area = 0.;
+        self.area_cdf = self
+            .indices
+            .iter()
+            .map(|triangle| {
+                let [v0, v1, v2] = self.vertices(triangle);
+                area += 0.5 * (v1 - v0).cross(v2 - v0).length();
+                area
+            })
+            .collect();
+    }
+
+    fn hit_triangle(
+        &self,
+        triangle: &[usize; 3],
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        let [v0, v1, v2] = self.vertices(triangle);
+        let (v0v1, v0v2) = (v1 - v0, v2 - v0);
+        let (t, u, v, det) = if self.watertight {
+            intersect_triangle_watertight(ray, [v0, v1, v2], self.double_sided, t_min, t_max)?
+        } else {
+            intersect_triangle(ray, v0, v0v1, v0v2, self.double_sided, t_min, t_max)?
+        };
+
+        // Vertex normals are interpolated across the face when the mesh has them
+        let normal = match &self.normals {
+            Some(normals) => {
+                let [n0, n1, n2] = triangle.map(|i| normals[i]);
+                ((1. - u - v) * n0 + u * n1 + v * n2).normalize()
+            }
+            None => v0v1.cross(v0v2).normalize(),
+        };
+
+        // Texture coordinates are interpolated the same way, and left at zero without them
+        let uv = match &self.uvs {
+            Some(uvs) => {
+                let [uv0, uv1, uv2] = triangle.map(|i| uvs[i]);
+                (1. - u - v) * uv0 + u * uv1 + v * uv2
+            }
+            None => Vec2::ZERO,
+        };
+
+        Some(HitRecord {
+            p: ray.at(t),
+            normal: normal * det.signum(),
+            t,
+            mat: self.material.clone(),
+            u: uv.x,
+            v: uv.y,
+        })
+    }
+}
+
+impl Transformable for TriangleMesh {
+    fn apply_transform(&mut self, other: Affine3A) {
+        for p in self.positions.iter_mut() {
+            *p = other.transform_point3a(*p);
+        }
+
+        if let Some(normals) = &mut self.normals {
+            let normal_matrix = other.matrix3.inverse().transpose();
+            for n in normals.iter_mut() {
+                *n = (normal_matrix * *n).normalize();
+            }
+        }
+
+        self.build_bvh();
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.bvh.traverse_nearest(
+            ray,
+            &self.indices,
+            |triangle, t_min, t_max| self.hit_triangle(triangle, ray, t_min, t_max),
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<AABB> {
+        Some(self.aabb)
+    }
+
+    // Directions are sampled through points anywhere on the surface, so every triangle crossed
+    // by the ray adds to the density, not only the visible one
+    fn pdf_value(&self, origin: Vec3A, v: Vec3A) -> f32 {
+        let area = match self.area_cdf.last() {
+            Some(&area) if area > 0. => area,
+            _ => return 0.,
+        };
+        let ray = Ray::new(origin, v, 0.);
+
+        let mut pdf = 0.;
+        for triangle in self.bvh.traverse(&ray, &self.indices, 0.001, f32::INFINITY) {
+            let [v0, v1, v2] = self.vertices(triangle);
+            let (v0v1, v0v2) = (v1 - v0, v2 - v0);
+            if let Some((t, ..)) =
+                intersect_triangle(&ray, v0, v0v1, v0v2, true, 0.001, f32::INFINITY)
+            {
+                let normal = v0v1.cross(v0v2).normalize();
+                let dist_squared = t * t * v.length_squared();
+                let cosine = v.dot(normal).abs() / v.length();
+                pdf += dist_squared / (cosine * area);
+            }
+        }
+        pdf
+    }
+
+    fn random(&self, origin: Vec3A) -> Vec3A {
+        let mut rng = rng();
+        let area = self.area_cdf.last().copied().unwrap_or(0.);
+        let target = rng.gen::<f32>() * area;
+        let index = self
+            .area_cdf
+            .partition_point(|&sum| sum <= target)
+            .min(self.indices.len().saturating_sub(1));
+        let [v0, v1, v2] = match self.indices.get(index) {
+            Some(triangle) => self.vertices(triangle),
+            None => return Vec3A::X,
+        };
+
+        // Same uniform sampling over the triangle as `Triangle::random`
+        let s = rng.gen::<f32>().sqrt();
+        let r2: f32 = rng.gen();
+        v0 + s * (1. - r2) * (v1 - v0) + s * r2 * (v2 - v0) - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3a;
+
+    use super::*;
+    use rand::Rng;
+
+    use crate::{
+        geometry::PrimitiveBVH,
+        material::Lambertian,
+        rng::{rng, set_seed},
+        vec3::{random_in_unit_sphere, Color},
+    };
+
+    #[test]
+    fn primitives_are_hit_like_the_mesh() {
+        // Octahedron with smooth normals, seen from both sides
+        let positions = vec![
+            vec3a(1., 0., 0.),
+            vec3a(-1., 0., 0.),
+            vec3a(0., 1., 0.),
+            vec3a(0., -1., 0.),
+            vec3a(0., 0., 1.),
+            vec3a(0., 0., -1.),
+        ];
+        let normals = positions.clone();
+        let indices = vec![
+            [0, 2, 4],
+            [2, 1, 4],
+            [1, 3, 4],
+            [3, 0, 4],
+            [2, 0, 5],
+            [1, 2, 5],
+            [3, 1, 5],
+            [0, 3, 5],
+        ];
+        let material = Arc::new(Lambertian::from_color(Color::ONE));
+        let mut mesh = TriangleMesh::new(positions, Some(normals), None, indices, material);
+        mesh.set_double_sided(true);
+        let primitives = PrimitiveBVH::new(mesh.primitives());
+
+        set_seed(4);
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let origin = 2. * random_in_unit_sphere();
+            let ray = Ray::new(origin, random_in_unit_sphere(), 0.);
+            match (
+                mesh.hit(&ray, 0.001, f32::INFINITY),
+                primitives.hit(&ray, 0.001, f32::INFINITY),
+            ) {
+                (Some(a), Some(b)) => {
+                    hits += 1;
+                    assert!((a.t - b.t).abs() < 1e-5);
+                    assert!((a.normal - b.normal).length() < 1e-5);
+                }
+                (None, None) => (),
+                (a, b) => panic!(
+                    "mesh hit: {}, primitives hit: {} for {:?}",
+                    a.is_some(),
+                    b.is_some(),
+                    ray
+                ),
+            }
+        }
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn watertight_meshes_have_no_gaps_along_shared_edges() {
+        set_seed(5);
+        let mut rng = rng();
+        let random_point = |rng: &mut crate::rng::PixelRng| {
+            vec3a(rng.gen(), rng.gen(), rng.gen()) * 2. - Vec3A::ONE
+        };
+
+        let mut misses = 0;
+        for _ in 0..20000 {
+            // Two triangles sharing the edge (a, b)
+            let [a, b, c, d] = [(); 4].map(|_| random_point(&mut rng));
+            let target = a + rng.gen::<f32>() * (b - a);
+            let origin = target + 3. * random_in_unit_sphere();
+
+            // Rays through the edge can only slip between them when they lie on either side of
+            // the plane through the edge and the ray origin
+            let plane = (a - origin).cross(b - a);
+            if (c - a).dot(plane) * (d - a).dot(plane) >= 0. {
+                continue;
+            }
+
+            let mut mesh = TriangleMesh::new(
+                vec![a, b, c, d],
+                None,
+                None,
+                vec![[0, 1, 2], [1, 0, 3]],
+                Arc::new(Lambertian::from_color(Color::ONE)),
+            );
+            mesh.set_double_sided(true);
+            mesh.set_watertight(true);
+
+            let ray = Ray::new(origin, target - origin, 0.);
+            if mesh.hit(&ray, 0.001, f32::INFINITY).is_none() {
+                misses += 1;
+            }
+        }
+        assert_eq!(misses, 0);
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use glam::vec3a;
+use rand::Rng;
+
+use crate::{
+    bvh::aabb::AABB,
+    material::{texture::Texture, HitRecord, Isotropic, Material},
+    ray::Ray,
+    rng::rng,
+    vec3::{luminance, Color},
+};
+
+use super::{constant_medium::spans, Hittable, Transformable};
+
+// Participating medium whose density varies in space, like clouds or wisps of smoke: at each point
+// it is `max_density` times the luminance of the `density` texture there, clamped to [0, 1]. A
+// `Noise` texture with the turbulence style gives Perlin fog, and a white `SolidColor` makes it a
+// `ConstantMedium`.
+pub struct VolumetricMedium {
+    boundary: Arc<dyn Hittable>,
+    density: Arc<dyn Texture>,
+    phase_function: Arc<dyn Material>,
+    neg_inv_max_density: f32,
+}
+
+impl VolumetricMedium {
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        density: Arc<dyn Texture>,
+        max_density: f32,
+        phase_function: Arc<dyn Material>,
+    ) -> VolumetricMedium {
+        VolumetricMedium {
+            boundary,
+            density,
+            phase_function,
+            neg_inv_max_density: -1. / max_density,
+        }
+    }
+
+    pub fn from_color(
+        boundary: Arc<dyn Hittable>,
+        density: Arc<dyn Texture>,
+        max_density: f32,
+        color: Color,
+    ) -> VolumetricMedium {
+        VolumetricMedium::new(
+            boundary,
+            density,
+            max_density,
+            Arc::new(Isotropic::from_color(color)),
+        )
+    }
+}
+
+impl Transformable for VolumetricMedium {}
+
+impl Hittable for VolumetricMedium {
+    // Delta tracking: collisions are drawn as in a medium that has the maximum density
+    // everywhere, and each one is real with the odds of the density there relative to it, the ray
+    // carrying on through the others as if nothing was there
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut rng = rng();
+        let ray_length = ray.direction().length();
+        // Drawn on reaching the medium and after each null collision
+        let mut hit_distance: Option<f32> = None;
+
+        for (mut t_enter, t_exit) in spans(self.boundary.as_ref(), ray, t_min, t_max) {
+            loop {
+                let dist_in_boundary = (t_exit - t_enter) * ray_length;
+                let distance = *hit_distance
+                    .get_or_insert_with(|| self.neg_inv_max_density * rng.gen::<f32>().ln());
+
+                if distance > dist_in_boundary {
+                    hit_distance = Some(distance - dist_in_boundary);
+                    break;
+                }
+
+                let t = t_enter + distance / ray_length;
+                let p = ray.at(t);
+
+                // Where the density is at its maximum, every collision is real
+                let density = luminance(self.density.value(0., 0., &p)).clamp(0., 1.);
+                if density >= 1. || rng.gen::<f32>() < density {
+                    return Some(HitRecord {
+                        t,
+                        p,
+                        normal: vec3a(1., 0., 0.),
+                        mat: self.phase_function.clone(),
+                        u: 0.,
+                        v: 0.,
+                    });
+                }
+
+                hit_distance = None;
+                t_enter = t;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
+        self.boundary.bounding_box(time0, time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3A;
+
+    use super::*;
+    use crate::{
+        geometry::{constant_medium::ConstantMedium, sphere::Sphere},
+        material::{texture::SolidColor, Lambertian},
+        rng::set_seed,
+    };
+
+    // Fraction of the rays through the middle of a unit sphere that scatter in `medium`, and the
+    // average distance they travel before that
+    fn scattering(medium: &dyn Hittable) -> (f32, f32) {
+        let ray = Ray::new(vec3a(0., 0., -5.), Vec3A::Z, 0.);
+        let n = 50000;
+        let hits: Vec<f32> = (0..n)
+            .filter_map(|_| medium.hit(&ray, 0.001, f32::INFINITY))
+            .map(|rec| rec.t - 4.)
+            .collect();
+        (
+            hits.len() as f32 / n as f32,
+            hits.iter().sum::<f32>() / hits.len() as f32,
+        )
+    }
+
+    #[test]
+    fn uniform_density_matches_a_constant_medium() {
+        set_seed(12);
+        let boundary: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            Vec3A::ZERO,
+            1.,
+            Arc::new(Lambertian::from_color(Color::ONE)),
+        ));
+        let constant = ConstantMedium::from_color(boundary.clone(), 0.5, Color::ONE);
+        let (hit_fraction, distance) = scattering(&constant);
+        // Beer-Lambert over the 2 units of the diameter
+        assert!((hit_fraction - (1. - (-1f32).exp())).abs() < 0.01);
+
+        // White everywhere, or grey with twice the maximum density
+        for (color, max_density) in [(Color::ONE, 0.5), (Color::splat(0.5), 1.)] {
+            let density = Arc::new(SolidColor::new(color));
+            let medium =
+                VolumetricMedium::from_color(boundary.clone(), density, max_density, Color::ONE);
+            let (fraction, mean) = scattering(&medium);
+            assert!((fraction - hit_fraction).abs() < 0.01, "{:?}", color);
+            assert!((mean - distance).abs() < 0.02, "{:?}", color);
+        }
+    }
+}
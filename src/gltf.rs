@@ -1,4 +1,7 @@
-use std::{convert::TryInto, error::Error, fs::read_to_string, path::Path, sync::Arc};
+use std::{
+    array::from_fn, convert::TryInto, error::Error, f32::consts::PI, fmt, fs::read, path::Path,
+    sync::Arc,
+};
 
 use base64::decode;
 use glam::{vec3a, Affine3A, Mat4, Vec2, Vec3A};
@@ -7,25 +10,156 @@ use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 
 use crate::{
-    camera::Camera,
-    geometry::{sphere::Sphere, triangle::Triangle, BVHNode, Hittables, Transformable},
-    material::{DiffuseLight, Lambertian, Material, Metal},
+    background::Background,
+    bvh::{
+        aabb::{surrounding_box_vec, AABB},
+        BVH,
+    },
+    camera::{Camera, Projection as CameraProjection},
+    geometry::{
+        sphere::Sphere, transform::Instance, triangle::is_degenerate, triangle_mesh::TriangleMesh,
+        Hittable, Hittables, Transformable,
+    },
+    material::{
+        pbr::Pbr,
+        texture::{ImageTexture, SolidColor, Texture, TintedTexture, WrapMode},
+        DiffuseLight, Material,
+    },
     scene::Scene,
     vec3::Color,
 };
 
-#[derive(Debug)]
+// Accessor component types: unsigned integers for indices and floats for the attributes
+const UNSIGNED_BYTE: u32 = 5121;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+
+// Sampler wrap modes
+const CLAMP_TO_EDGE: u32 = 33071;
+const MIRRORED_REPEAT: u32 = 33648;
+
+// Room left around scenes framed for lack of a camera, as a fraction of their size
+const FRAMING_MARGIN: f32 = 0.05;
+
+#[derive(Debug, PartialEq)]
 enum DataType {
     Vec3(Vec3A),
     Vec2(Vec2),
-    Scalar(u16),
+    Scalar(u32),
 }
 
+// Malformed references and truncated data found while building the scene, naming the glTF
+// element at fault
+#[derive(Debug)]
+pub enum GltfError {
+    MissingBuffer {
+        view: usize,
+        buffer: usize,
+    },
+    BufferViewOutOfRange {
+        view: usize,
+        buffer: usize,
+    },
+    MissingBufferView {
+        accessor: usize,
+        view: usize,
+    },
+    AccessorOutOfRange {
+        accessor: usize,
+        index: usize,
+    },
+    UnsupportedComponentType {
+        accessor: usize,
+        component_type: u32,
+    },
+    MissingAccessor {
+        mesh: usize,
+        accessor: usize,
+    },
+    MissingMaterial {
+        mesh: usize,
+        material: usize,
+    },
+    MissingVertex {
+        mesh: usize,
+        vertex: usize,
+    },
+    InvalidBaseColor {
+        material: usize,
+    },
+    MissingTexture {
+        material: usize,
+        texture: usize,
+    },
+    MissingImage {
+        texture: usize,
+        image: usize,
+    },
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            GltfError::MissingBuffer { view, buffer } => {
+                write!(f, "bufferView {} refers to missing buffer {}", view, buffer)
+            }
+            GltfError::BufferViewOutOfRange { view, buffer } => {
+                write!(f, "bufferView {} overruns buffer {}", view, buffer)
+            }
+            GltfError::MissingBufferView { accessor, view } => write!(
+                f,
+                "accessor {} refers to missing bufferView {}",
+                accessor, view
+            ),
+            GltfError::AccessorOutOfRange { accessor, index } => write!(
+                f,
+                "accessor {} overruns its bufferView at element {}",
+                accessor, index
+            ),
+            GltfError::UnsupportedComponentType {
+                accessor,
+                component_type,
+            } => write!(
+                f,
+                "accessor {} has unsupported component type {}",
+                accessor, component_type
+            ),
+            GltfError::MissingAccessor { mesh, accessor } => {
+                write!(f, "mesh {} refers to missing accessor {}", mesh, accessor)
+            }
+            GltfError::MissingMaterial { mesh, material } => {
+                write!(f, "mesh {} refers to missing material {}", mesh, material)
+            }
+            GltfError::MissingVertex { mesh, vertex } => {
+                write!(f, "mesh {} refers to missing vertex {}", mesh, vertex)
+            }
+            GltfError::InvalidBaseColor { material } => write!(
+                f,
+                "material {} has fewer than 3 base color components",
+                material
+            ),
+            GltfError::MissingTexture { material, texture } => {
+                write!(
+                    f,
+                    "material {} refers to missing texture {}",
+                    material, texture
+                )
+            }
+            GltfError::MissingImage { texture, image } => {
+                write!(f, "texture {} refers to missing image {}", texture, image)
+            }
+        }
+    }
+}
+
+impl Error for GltfError {}
+
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 struct GLTFBuffer {
     byteLength: usize,
-    uri: String,
+    uri: Option<String>,
 }
 
 #[allow(non_snake_case)]
@@ -33,37 +167,115 @@ struct GLTFBuffer {
 struct GLTFBufferView {
     buffer: usize,
     byteLength: usize,
+    #[serde(default)]
     byteOffset: usize,
+    // Distance between the starts of consecutive elements when they are interleaved with others,
+    // and otherwise the size of an element
+    byteStride: Option<usize>,
 }
 
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 struct GLTFAccessor {
     bufferView: usize,
+    #[serde(default)]
+    byteOffset: usize,
+    componentType: u32,
     count: usize,
+    // `type` is a reserved keyword in Rust
+    #[serde(rename = "type")]
     type_: String,
 }
 
+// Factors left out of a file take the glTF defaults: white, fully metallic and fully rough
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 struct PBRMetallicRoughness {
+    #[serde(default = "default_base_color")]
     baseColorFactor: Vec<f32>,
+    baseColorTexture: Option<GLTFTextureInfo>,
+    #[serde(default = "default_factor")]
     metallicFactor: f32,
+    #[serde(default = "default_factor")]
     roughnessFactor: f32,
 }
 
+impl Default for PBRMetallicRoughness {
+    fn default() -> Self {
+        PBRMetallicRoughness {
+            baseColorFactor: default_base_color(),
+            baseColorTexture: None,
+            metallicFactor: default_factor(),
+            roughnessFactor: default_factor(),
+        }
+    }
+}
+
+fn default_base_color() -> Vec<f32> {
+    vec![1., 1., 1., 1.]
+}
+
+fn default_factor() -> f32 {
+    1.
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GLTFTextureInfo {
+    index: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GLTFTexture {
+    sampler: Option<usize>,
+    source: Option<usize>,
+}
+
+// Images are stored in a file or data URI, or in a bufferView of GLB files
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct GLTFImage {
+    uri: Option<String>,
+    bufferView: Option<usize>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct GLTFSampler {
+    wrapS: Option<u32>,
+}
+
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 struct GLTFMaterial {
+    #[serde(default)]
     doubleSided: bool,
+    #[serde(default)]
+    emissiveFactor: [f32; 3],
+    #[serde(default)]
+    extensions: GLTFMaterialExtensions,
     name: String,
+    #[serde(default)]
     pbrMetallicRoughness: PBRMetallicRoughness,
 }
 
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct GLTFMaterialExtensions {
+    KHR_materials_emissive_strength: Option<GLTFEmissiveStrength>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct GLTFEmissiveStrength {
+    emissiveStrength: f32,
+}
+
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
 struct GLTFMeshPrimitiveAttributes {
     POSITION: usize,
+    NORMAL: Option<usize>,
+    TEXCOORD_0: Option<usize>,
 }
 
 #[allow(non_snake_case)]
@@ -99,100 +311,330 @@ struct GLTFFile {
     scenes: Vec<GLTFScene>,
     nodes: Vec<GLTFNode>,
     materials: Vec<GLTFMaterial>,
+    #[serde(default)]
+    textures: Vec<GLTFTexture>,
+    #[serde(default)]
+    images: Vec<GLTFImage>,
+    #[serde(default)]
+    samplers: Vec<GLTFSampler>,
     meshes: Vec<GLTFMesh>,
     accessors: Vec<GLTFAccessor>,
     bufferViews: Vec<GLTFBufferView>,
     buffers: Vec<GLTFBuffer>,
 }
 
-fn read_gltf_from_file<P: AsRef<Path>>(path: P) -> Result<GLTFFile, Box<dyn Error>> {
-    let str = read_to_string(path)?;
-    // Replace all `type` keys with `type_` because `type` is a reserved keyword in Rust
-    let cleaned = str.replace("type", "type_");
+// Magic number and chunk types of binary glTF (.glb) containers
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+
+// Binary chunk of a .glb file, absent for text .gltf files
+type GLBBinary = Option<Vec<u8>>;
+
+// Splits a .glb container into its JSON chunk and its optional binary chunk
+fn parse_glb(bytes: &[u8]) -> Result<(String, GLBBinary), Box<dyn Error>> {
+    let read_u32 = |offset: usize| -> Result<u32, Box<dyn Error>> {
+        let word = bytes
+            .get(offset..offset + 4)
+            .ok_or("truncated GLB container")?;
+        Ok(u32::from_le_bytes(word.try_into()?))
+    };
+
+    let length = (read_u32(8)? as usize).min(bytes.len());
+    let mut json: Option<String> = None;
+    let mut bin: GLBBinary = None;
 
-    let gltf: GLTFFile = from_str(&cleaned)?;
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(offset)? as usize;
+        let chunk_type = read_u32(offset + 4)?;
+        let data = bytes
+            .get(offset + 8..offset + 8 + chunk_length)
+            .ok_or("truncated GLB chunk")?;
 
-    Ok(gltf)
+        match chunk_type {
+            GLB_CHUNK_JSON => json = Some(String::from_utf8(data.to_vec())?),
+            GLB_CHUNK_BIN if bin.is_none() => bin = Some(data.to_vec()),
+            _ => (),
+        }
+        offset += 8 + chunk_length;
+    }
+
+    Ok((json.ok_or("GLB container has no JSON chunk")?, bin))
 }
 
-fn gltf_buffers_to_bytes(buffers: &[GLTFBuffer]) -> Vec<Vec<u8>> {
-    let mut out: Vec<Vec<u8>> = Vec::new();
+// Reads a .gltf or .glb file, returning the parsed document along with the binary chunk of .glb
+// files
+fn read_gltf_from_file<P: AsRef<Path>>(path: P) -> Result<(GLTFFile, GLBBinary), Box<dyn Error>> {
+    let bytes = read(path)?;
+    let (str, bin) = if bytes.starts_with(GLB_MAGIC) {
+        parse_glb(&bytes)?
+    } else {
+        (String::from_utf8(bytes)?, None)
+    };
+    let gltf: GLTFFile = from_str(&str)?;
 
-    for buf in buffers.iter() {
-        let split: Vec<&str> = buf.uri.split(',').collect();
-        let data = split[1];
-        if let Ok(bytes) = decode(data) {
-            out.push(bytes);
+    Ok((gltf, bin))
+}
+
+// Buffers are either embedded as base64 data URIs, stored in files next to the glTF file, or,
+// without a URI, the binary chunk of a .glb file
+fn load_buffer(
+    uri: Option<&str>,
+    base_dir: &Path,
+    glb_bin: Option<&[u8]>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let uri = match uri {
+        Some(uri) => uri,
+        None => {
+            let bin = glb_bin.ok_or("buffer has no URI and the file has no GLB binary chunk")?;
+            return Ok(bin.to_vec());
         }
+    };
+
+    if let Some(data_uri) = uri.strip_prefix("data:") {
+        let (_, data) = data_uri
+            .split_once(',')
+            .ok_or("malformed data URI: missing `,`")?;
+        return Ok(decode(data)?);
     }
 
-    out
+    let file = base_dir.join(uri);
+    read(&file).map_err(|e| format!("could not read {}: {}", file.display(), e).into())
+}
+
+fn gltf_buffers_to_bytes(
+    buffers: &[GLTFBuffer],
+    base_dir: &Path,
+    glb_bin: Option<&[u8]>,
+) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    buffers
+        .iter()
+        .map(|buf| load_buffer(buf.uri.as_deref(), base_dir, glb_bin))
+        .collect()
 }
 
 fn gltf_buffer_views_to_bytes(
     buffer_views: &[GLTFBufferView],
     buffers: &[Vec<u8>],
-) -> Vec<Vec<u8>> {
+) -> Result<Vec<Vec<u8>>, GltfError> {
     let mut out: Vec<Vec<u8>> = Vec::new();
 
-    for bv in buffer_views.iter() {
-        out.push(
-            buffers[bv.buffer].as_slice()[bv.byteOffset..(bv.byteOffset + bv.byteLength)].to_vec(),
-        )
+    for (view, bv) in buffer_views.iter().enumerate() {
+        let buffer = buffers.get(bv.buffer).ok_or(GltfError::MissingBuffer {
+            view,
+            buffer: bv.buffer,
+        })?;
+        let bytes = buffer
+            .get(bv.byteOffset..bv.byteOffset + bv.byteLength)
+            .ok_or(GltfError::BufferViewOutOfRange {
+                view,
+                buffer: bv.buffer,
+            })?;
+        out.push(bytes.to_vec())
     }
 
-    out
+    Ok(out)
 }
 
-fn gltf_materials_to_materials(materials: &[GLTFMaterial]) -> Vec<Arc<dyn Material>> {
-    let mut out: Vec<Arc<dyn Material>> = Vec::new();
+// Decodes the image of each texture as an sRGB color texture, wrapped as its sampler says
+fn gltf_textures_to_textures(
+    gltf: &GLTFFile,
+    base_dir: &Path,
+    glb_bin: Option<&[u8]>,
+    buffer_views: &[Vec<u8>],
+) -> Result<Vec<Arc<dyn Texture>>, Box<dyn Error>> {
+    let mut out: Vec<Arc<dyn Texture>> = Vec::new();
 
-    for mat in materials.iter() {
-        let color: Color = Color::new(
-            mat.pbrMetallicRoughness.baseColorFactor[0],
-            mat.pbrMetallicRoughness.baseColorFactor[1],
-            mat.pbrMetallicRoughness.baseColorFactor[2],
-        );
+    for (i, texture) in gltf.textures.iter().enumerate() {
+        let source = texture
+            .source
+            .ok_or(format!("texture {} has no image", i))?;
+        let image = gltf.images.get(source).ok_or(GltfError::MissingImage {
+            texture: i,
+            image: source,
+        })?;
+        let bytes = match (&image.uri, image.bufferView) {
+            (Some(uri), _) => load_buffer(Some(uri), base_dir, glb_bin)?,
+            (None, Some(view)) => buffer_views
+                .get(view)
+                .ok_or(format!(
+                    "image {} refers to missing bufferView {}",
+                    source, view
+                ))?
+                .clone(),
+            (None, None) => return Err(format!("image {} has no data", source).into()),
+        };
+        let img =
+            image::load_from_memory(&bytes).map_err(|e| format!("image {}: {}", source, e))?;
+
+        let mut image_texture = ImageTexture::from_image(&img);
+        image_texture.set_srgb(true);
+        let wrap_s = texture
+            .sampler
+            .and_then(|sampler| gltf.samplers.get(sampler))
+            .and_then(|sampler| sampler.wrapS);
+        image_texture.set_wrap(match wrap_s {
+            Some(CLAMP_TO_EDGE) => WrapMode::Clamp,
+            Some(MIRRORED_REPEAT) => WrapMode::Mirror,
+            _ => WrapMode::Repeat,
+        });
+        out.push(Arc::new(image_texture));
+    }
+
+    Ok(out)
+}
 
-        if mat.pbrMetallicRoughness.metallicFactor.abs() < 1e-5 {
-            out.push(Arc::new(Lambertian::from_color(color)));
+// A material with whether the triangles using it can be hit from the back, and whether they are
+// lights
+struct MeshMaterial {
+    material: Arc<dyn Material>,
+    double_sided: bool,
+    emissive: bool,
+}
+
+fn gltf_materials_to_materials(
+    materials: &[GLTFMaterial],
+    textures: &[Arc<dyn Texture>],
+) -> Result<Vec<MeshMaterial>, GltfError> {
+    let mut out: Vec<MeshMaterial> = Vec::new();
+
+    for (i, mat) in materials.iter().enumerate() {
+        let color = match mat.pbrMetallicRoughness.baseColorFactor.as_slice() {
+            [r, g, b, ..] => Color::new(*r, *g, *b),
+            _ => return Err(GltfError::InvalidBaseColor { material: i }),
+        };
+
+        // Emissive materials become lights, their base color is lost since lights don't scatter
+        let emission = Color::from(mat.emissiveFactor);
+        let emissive = emission.max_element() > 0.;
+        // The base color texture is multiplied by the base color factor
+        let base_color: Arc<dyn Texture> = match &mat.pbrMetallicRoughness.baseColorTexture {
+            Some(info) => {
+                let texture = textures.get(info.index).ok_or(GltfError::MissingTexture {
+                    material: i,
+                    texture: info.index,
+                })?;
+                if color == Color::ONE {
+                    texture.clone()
+                } else {
+                    Arc::new(TintedTexture::new(texture.clone(), color))
+                }
+            }
+            None => Arc::new(SolidColor::new(color)),
+        };
+
+        let material: Arc<dyn Material> = if emissive {
+            let strength = mat
+                .extensions
+                .KHR_materials_emissive_strength
+                .as_ref()
+                .map_or(1., |ext| ext.emissiveStrength);
+            let mut light = DiffuseLight::new(Arc::new(SolidColor::new(emission)), strength);
+            light.set_two_sided(mat.doubleSided);
+            Arc::new(light)
         } else {
-            out.push(Arc::new(Metal {
-                albedo: color,
-                fuzziness: mat.pbrMetallicRoughness.roughnessFactor,
-            }));
-        }
+            Arc::new(Pbr::new(
+                base_color,
+                mat.pbrMetallicRoughness.metallicFactor,
+                mat.pbrMetallicRoughness.roughnessFactor,
+            ))
+        };
+        out.push(MeshMaterial {
+            material,
+            double_sided: mat.doubleSided,
+            emissive,
+        });
     }
 
-    out
+    Ok(out)
 }
 
+// Little-endian floats at the start of `bytes`, which must hold at least `N` of them
+fn read_f32s<const N: usize>(bytes: &[u8]) -> [f32; N] {
+    from_fn(|k| {
+        f32::from_le_bytes([
+            bytes[4 * k],
+            bytes[4 * k + 1],
+            bytes[4 * k + 2],
+            bytes[4 * k + 3],
+        ])
+    })
+}
+
+// Whether the vector elements of the accessor can be decoded: texcoords may also be normalized
+// bytes or shorts, but quantized positions and normals aren't supported
+fn vector_components_supported(acc: &GLTFAccessor) -> bool {
+    match acc.type_.as_str() {
+        "VEC2" => [FLOAT, UNSIGNED_BYTE, UNSIGNED_SHORT].contains(&acc.componentType),
+        "VEC3" => acc.componentType == FLOAT,
+        _ => true,
+    }
+}
+
+// Components at the start of `bytes` as floats, mapping normalized unsigned bytes and shorts to
+// [0, 1]
+fn read_normalized<const N: usize>(bytes: &[u8], component_type: u32) -> [f32; N] {
+    match component_type {
+        UNSIGNED_BYTE => std::array::from_fn(|k| bytes[k] as f32 / 255.),
+        UNSIGNED_SHORT => std::array::from_fn(|k| {
+            u16::from_le_bytes([bytes[2 * k], bytes[2 * k + 1]]) as f32 / 65535.
+        }),
+        _ => read_f32s(bytes),
+    }
+}
+
+// Decodes the elements of each accessor from `view_bytes`, the bytes of the bufferViews
 fn gltf_accessors_to_data(
     accessors: &[GLTFAccessor],
-    buffer_views: &[Vec<u8>],
-) -> Vec<Vec<DataType>> {
+    buffer_views: &[GLTFBufferView],
+    view_bytes: &[Vec<u8>],
+) -> Result<Vec<Vec<DataType>>, GltfError> {
     let mut out: Vec<Vec<DataType>> = Vec::new();
 
-    for acc in accessors.iter() {
-        let bv = buffer_views[acc.bufferView].as_slice();
+    for (accessor, acc) in accessors.iter().enumerate() {
+        let missing_view = || GltfError::MissingBufferView {
+            accessor,
+            view: acc.bufferView,
+        };
+        let view = buffer_views.get(acc.bufferView).ok_or_else(missing_view)?;
+        let bv = view_bytes
+            .get(acc.bufferView)
+            .ok_or_else(missing_view)?
+            .as_slice();
+
+        if !vector_components_supported(acc) {
+            return Err(GltfError::UnsupportedComponentType {
+                accessor,
+                component_type: acc.componentType,
+            });
+        }
 
         let mut buf: Vec<DataType> = Vec::new();
 
+        let size = element_size(acc);
+        let stride = view.byteStride.unwrap_or(size);
         for i in 0..acc.count {
+            let start = acc.byteOffset + stride * i;
+            let element = bv
+                .get(start..start + size)
+                .ok_or(GltfError::AccessorOutOfRange { accessor, index: i })?;
+
             match acc.type_.as_str() {
                 "VEC3" => {
-                    let x = f32::from_le_bytes(bv[(12 * i)..(12 * i + 4)].try_into().unwrap());
-                    let y = f32::from_le_bytes(bv[(12 * i + 4)..(12 * i + 8)].try_into().unwrap());
-                    let z = f32::from_le_bytes(bv[(12 * i + 8)..(12 * i + 12)].try_into().unwrap());
+                    let [x, y, z] = read_f32s(element);
                     buf.push(DataType::Vec3(vec3a(x, y, z)));
                 }
                 "VEC2" => {
-                    let x = f32::from_le_bytes(bv[(8 * i)..(8 * i + 4)].try_into().unwrap());
-                    let y = f32::from_le_bytes(bv[(8 * i + 4)..(8 * i + 8)].try_into().unwrap());
+                    let [x, y] = read_normalized(element, acc.componentType);
                     buf.push(DataType::Vec2(Vec2::new(x, y)));
                 }
                 "SCALAR" => {
-                    let x = u16::from_le_bytes(bv[(2 * i)..(2 * i + 2)].try_into().unwrap());
+                    let x = match acc.componentType {
+                        UNSIGNED_BYTE => element[0] as u32,
+                        UNSIGNED_SHORT => u16::from_le_bytes([element[0], element[1]]) as u32,
+                        _ => u32::from_le_bytes([element[0], element[1], element[2], element[3]]),
+                    };
                     buf.push(DataType::Scalar(x));
                 }
                 _ => (),
@@ -202,151 +644,986 @@ fn gltf_accessors_to_data(
         out.push(buf);
     }
 
-    out
+    Ok(out)
 }
 
-fn gltf_meshes_to_hittables(
-    meshes: &[GLTFMesh],
+// Vertex indices and positions of a primitive of the glTF mesh number `index`, checking that the
+// indices all point to a vertex
+fn gltf_primitive_geometry(
+    index: usize,
+    primitive: &GLTFMeshPrimitive,
     accessors: &[Vec<DataType>],
-    materials: &[Arc<dyn Material>],
-) -> Hittables {
-    let mut objects: Hittables = Vec::new();
+) -> Result<(Vec<usize>, Vec<Vec3A>), GltfError> {
+    let accessor = |accessor: usize| {
+        accessors.get(accessor).ok_or(GltfError::MissingAccessor {
+            mesh: index,
+            accessor,
+        })
+    };
 
-    for mesh in meshes.iter() {
-        let indices: Vec<usize> = (&accessors[mesh.primitives[0].indices])
-            .iter()
-            .map(|x| match x {
-                DataType::Scalar(k) => *k as usize,
-                _ => 0,
-            })
-            .collect();
-        let positions: Vec<Vec3A> = (&accessors[mesh.primitives[0].attributes.POSITION])
-            .iter()
-            .map(|x| match x {
-                DataType::Vec3(v) => *v,
-                _ => vec3a(0., 0., 0.),
-            })
-            .collect();
+    let indices: Vec<usize> = accessor(primitive.indices)?
+        .iter()
+        .map(|x| match x {
+            DataType::Scalar(k) => *k as usize,
+            _ => 0,
+        })
+        .collect();
+    let positions: Vec<Vec3A> = accessor(primitive.attributes.POSITION)?
+        .iter()
+        .map(|x| match x {
+            DataType::Vec3(v) => *v,
+            _ => vec3a(0., 0., 0.),
+        })
+        .collect();
 
-        for i in 0..(indices.len() / 3) {
-            let v0 = positions[indices[3 * i]];
-            let v1 = positions[indices[3 * i + 1]];
-            let v2 = positions[indices[3 * i + 2]];
+    if let Some(&vertex) = indices.iter().find(|&&k| k >= positions.len()) {
+        return Err(GltfError::MissingVertex {
+            mesh: index,
+            vertex,
+        });
+    }
+    Ok((indices, positions))
+}
 
-            let triangle =
-                Triangle::new(v0, v1, v2, materials[mesh.primitives[0].material].clone());
+// Builds one triangle mesh per primitive of the glTF mesh number `index`, in its object space,
+// keeping only the primitives whose material is emissive or only the other ones
+fn gltf_mesh_to_triangle_meshes(
+    index: usize,
+    mesh: &GLTFMesh,
+    accessors: &[Vec<DataType>],
+    materials: &[MeshMaterial],
+    emissive: bool,
+) -> Result<Vec<TriangleMesh>, GltfError> {
+    let mut out: Vec<TriangleMesh> = Vec::new();
+    let accessor = |accessor: usize| {
+        accessors.get(accessor).ok_or(GltfError::MissingAccessor {
+            mesh: index,
+            accessor,
+        })
+    };
+
+    for primitive in mesh.primitives.iter() {
+        let material = materials
+            .get(primitive.material)
+            .ok_or(GltfError::MissingMaterial {
+                mesh: index,
+                material: primitive.material,
+            })?;
+        if material.emissive != emissive {
+            continue;
+        }
 
-            objects.push(Arc::new(triangle));
+        let (indices, positions) = gltf_primitive_geometry(index, primitive, accessors)?;
+        // Attributes that don't match the vertex count are dropped, falling back to
+        // geometric normals and zeroed UVs
+        let normals: Option<Vec<Vec3A>> = primitive
+            .attributes
+            .NORMAL
+            .map(|normal| {
+                Ok(accessor(normal)?
+                    .iter()
+                    .map(|x| match x {
+                        DataType::Vec3(v) => *v,
+                        _ => vec3a(0., 0., 0.),
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .transpose()?
+            .filter(|normals| normals.len() == positions.len());
+        let uvs: Option<Vec<Vec2>> = primitive
+            .attributes
+            .TEXCOORD_0
+            .map(|texcoord| {
+                Ok(accessor(texcoord)?
+                    .iter()
+                    // glTF texture coordinates start from the top of the image, ours from the
+                    // bottom
+                    .map(|x| match x {
+                        DataType::Vec2(v) => Vec2::new(v.x, 1. - v.y),
+                        _ => Vec2::ZERO,
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .transpose()?
+            .filter(|uvs| uvs.len() == positions.len());
+
+        let triangles: Vec<[usize; 3]> = indices
+            .chunks_exact(3)
+            .map(|tri| [tri[0], tri[1], tri[2]])
+            .collect();
+        if triangles.is_empty() {
+            continue;
         }
+
+        let mut triangle_mesh = TriangleMesh::new(
+            positions,
+            normals,
+            uvs,
+            triangles,
+            material.material.clone(),
+        );
+        triangle_mesh.set_double_sided(material.double_sided);
+        out.push(triangle_mesh);
     }
 
-    objects
+    Ok(out)
 }
 
 enum NodeType {
     Camera(Camera),
     Light(Sphere),
-    // Mesh(Hittables),
-}
-
-impl Transformable for NodeType {
-    fn apply_transform(&mut self, other: Affine3A) {
-        match self {
-            NodeType::Camera(camera) => camera.apply_transform(other),
-            NodeType::Light(light) => light.apply_transform(other),
-            // NodeType::Mesh(mesh) => mesh.transform(other),
-        }
-    }
+    // Index of the mesh and its transform to world space
+    Mesh(usize, Affine3A),
 }
 
 fn transform_to_affine3a(transform: Transform) -> Affine3A {
     Affine3A::from_mat4(Mat4::from_cols_array_2d(&transform.matrix()))
 }
 
-// TODO: handle mesh importing
-fn handle_gltf_node(node: Node) -> Option<NodeType> {
+// Collects the cameras, lights and meshes of a node and of all its descendants, placed in world
+// space by the transforms accumulated from the root
+fn handle_gltf_node(node: Node, parent_to_world: Affine3A) -> Vec<NodeType> {
+    let node_to_world = parent_to_world * transform_to_affine3a(node.transform());
+    let mut out: Vec<NodeType> = Vec::new();
+
     if let Some(camera) = node.camera() {
-        match camera.projection() {
-            Projection::Perspective(perspective) => {
-                let camera_to_world = transform_to_affine3a(node.transform());
-
-                return Some(NodeType::Camera(Camera::new(
-                    perspective.aspect_ratio().unwrap_or(1.),
-                    perspective.yfov().to_degrees(),
-                    perspective.znear(),
-                    perspective.zfar().unwrap_or(100.),
-                    camera_to_world,
-                    0.,
-                    1.,
-                )));
-            }
-            _ => {}
-        }
+        let (aspect_ratio, projection, znear, zfar) = match camera.projection() {
+            Projection::Perspective(perspective) => (
+                perspective.aspect_ratio().unwrap_or(1.),
+                CameraProjection::Perspective {
+                    vfov: perspective.yfov().to_degrees(),
+                },
+                perspective.znear(),
+                perspective.zfar().unwrap_or(100.),
+            ),
+            // xmag and ymag are half the width and height of the view
+            Projection::Orthographic(orthographic) => (
+                orthographic.xmag() / orthographic.ymag(),
+                CameraProjection::Orthographic {
+                    height: 2. * orthographic.ymag(),
+                },
+                orthographic.znear(),
+                orthographic.zfar(),
+            ),
+        };
+
+        out.push(NodeType::Camera(Camera::with_projection(
+            aspect_ratio,
+            projection,
+            0.,
+            1.,
+            znear,
+            zfar,
+            node_to_world,
+        )));
     }
 
     if let Some(light) = node.light() {
         let mut sphere_light = Sphere::new(
             Vec3A::ZERO,
             0.2,
-            Arc::new(DiffuseLight::from_color(
-                Vec3A::from(light.color()) * light.intensity(),
+            Arc::new(DiffuseLight::new(
+                Arc::new(SolidColor::new(Vec3A::from(light.color()))),
+                light.intensity(),
             )),
         );
-        let light_to_world = transform_to_affine3a(node.transform());
-        sphere_light.apply_transform(light_to_world);
+        sphere_light.apply_transform(node_to_world);
 
-        return Some(NodeType::Light(sphere_light));
+        out.push(NodeType::Light(sphere_light));
     }
 
-    if node.children().count() == 1 {
-        let transform_mat = transform_to_affine3a(node.transform());
-        let child = node.children().next().unwrap();
+    if let Some(mesh) = node.mesh() {
+        out.push(NodeType::Mesh(mesh.index(), node_to_world));
+    }
 
-        if let Some(mut res) = handle_gltf_node(child) {
-            res.apply_transform(transform_mat);
-            return Some(res);
-        }
+    for child in node.children() {
+        out.append(&mut handle_gltf_node(child, node_to_world));
     }
 
-    None
+    out
 }
 
 impl Scene {
     pub fn from_gltf_file<P: AsRef<Path>>(path: P) -> Result<Scene, Box<dyn Error>> {
-        let gltf_old = read_gltf_from_file(path)?;
-        let gltf = Gltf::open("assets/suzanne.gltf")?;
+        let (gltf_old, glb_bin) = read_gltf_from_file(&path)?;
+        let gltf = Gltf::open(&path)?;
 
-        let mut camera = Camera::default();
-        let buffers = gltf_buffers_to_bytes(&gltf_old.buffers);
-        let buffer_views = gltf_buffer_views_to_bytes(&gltf_old.bufferViews, &buffers);
-        let accessors = gltf_accessors_to_data(&gltf_old.accessors, &buffer_views);
+        // Nodes from the `gltf` crate index into the meshes parsed by hand, so both must agree
+        if gltf.meshes().count() != gltf_old.meshes.len()
+            || gltf.accessors().count() != gltf_old.accessors.len()
+        {
+            return Err(format!(
+                "{}: the glTF document and its buffers disagree on the number of meshes or accessors",
+                path.as_ref().display()
+            )
+            .into());
+        }
+
+        let mut camera: Option<Camera> = None;
+        let base_dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        let buffers = gltf_buffers_to_bytes(&gltf_old.buffers, base_dir, glb_bin.as_deref())?;
+        let error = |err: GltfError| format!("{}: {}", path.as_ref().display(), err);
+        let buffer_views =
+            gltf_buffer_views_to_bytes(&gltf_old.bufferViews, &buffers).map_err(error)?;
+        let accessors =
+            gltf_accessors_to_data(&gltf_old.accessors, &gltf_old.bufferViews, &buffer_views)
+                .map_err(error)?;
 
-        let materials = gltf_materials_to_materials(&gltf_old.materials);
-        let mut objects = gltf_meshes_to_hittables(&gltf_old.meshes, &accessors, &materials);
+        let textures =
+            gltf_textures_to_textures(&gltf_old, base_dir, glb_bin.as_deref(), &buffer_views)
+                .map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        let materials =
+            gltf_materials_to_materials(&gltf_old.materials, &textures).map_err(error)?;
+        // Each mesh is built once in its own space, and then shared by all the nodes using it.
+        // Emissive primitives are kept apart so that they can also be listed as lights.
+        let build_meshes = |emissive: bool| {
+            gltf_old
+                .meshes
+                .iter()
+                .enumerate()
+                .map(|(i, mesh)| {
+                    let mut primitives =
+                        gltf_mesh_to_triangle_meshes(i, mesh, &accessors, &materials, emissive)?;
+                    Ok(match primitives.len() {
+                        0 => None,
+                        1 => Some(Arc::new(primitives.remove(0)) as Arc<dyn Hittable>),
+                        _ => {
+                            let primitives: Hittables = primitives
+                                .into_iter()
+                                .map(|primitive| Arc::new(primitive) as Arc<dyn Hittable>)
+                                .collect();
+                            Some(Arc::new(primitives) as Arc<dyn Hittable>)
+                        }
+                    })
+                })
+                .collect::<Result<Vec<Option<Arc<dyn Hittable>>>, GltfError>>()
+        };
+        let meshes = build_meshes(false).map_err(error)?;
+        let emitters = build_meshes(true).map_err(error)?;
+        let mut objects: Hittables = Vec::new();
         let mut lights: Hittables = Vec::new();
+        // Bounding boxes of the meshes placed in the scene, punctual lights left out
+        let mut bounds: Vec<AABB> = Vec::new();
 
         for scene in gltf.scenes() {
             for node in scene.nodes() {
-                if let Some(out) = handle_gltf_node(node.clone()) {
+                for out in handle_gltf_node(node, Affine3A::IDENTITY) {
                     match out {
-                        NodeType::Camera(cam) => camera = cam,
+                        NodeType::Camera(cam) => camera = Some(cam),
                         NodeType::Light(light) => {
                             let light_arc = Arc::new(light);
                             objects.push(light_arc.clone());
                             lights.push(light_arc.clone());
                         }
+                        NodeType::Mesh(index, node_to_world) => {
+                            // Meshes that are already in world space don't need to transform rays
+                            let instance = |mesh: &Option<Arc<dyn Hittable>>| match mesh {
+                                Some(mesh) if node_to_world == Affine3A::IDENTITY => {
+                                    Some(mesh.clone())
+                                }
+                                Some(mesh) => {
+                                    Some(Arc::new(Instance::new(mesh.clone(), node_to_world))
+                                        as Arc<dyn Hittable>)
+                                }
+                                None => None,
+                            };
+                            if let Some(mesh) = instance(&meshes[index]) {
+                                bounds.extend(mesh.bounding_box(0., 1.));
+                                objects.push(mesh);
+                            }
+                            if let Some(emitter) = instance(&emitters[index]) {
+                                bounds.extend(emitter.bounding_box(0., 1.));
+                                objects.push(emitter.clone());
+                                lights.push(emitter);
+                            }
+                        }
                     }
                 }
             }
         }
 
-        let world = BVHNode::new(objects, 0., 1.);
+        // Files without a camera are seen from the +z side, like the default camera
+        let camera = match camera {
+            Some(camera) => camera,
+            None if bounds.is_empty() => Camera::default(),
+            None => Camera::frame(&surrounding_box_vec(&bounds), 1., 30., FRAMING_MARGIN),
+        };
 
-        Ok(Scene {
-            world,
+        Ok(Scene::new(
             camera,
-            background: Color::new(0.051, 0.051, 0.051),
+            objects,
             lights,
-        })
+            Background::Solid(Color::new(0.051, 0.051, 0.051)),
+            0.,
+            1.,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Note => write!(f, "note: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+// Size in bytes of an element of the accessor, without any padding
+fn element_size(acc: &GLTFAccessor) -> usize {
+    let components = match acc.type_.as_str() {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" => 4,
+        _ => 0,
+    };
+    let component_size = match acc.componentType {
+        UNSIGNED_BYTE => 1,
+        UNSIGNED_SHORT => 2,
+        _ => 4,
+    };
+    components * component_size
+}
+
+// Checks a glTF file for problems that would make it fail or render wrongly, without rendering it.
+// When `verbose` is set, it also notes statistics about the BVH over the triangles.
+pub fn validate_gltf_file<P: AsRef<Path>>(path: P, verbose: bool) -> Vec<ValidationIssue> {
+    let mut issues: Vec<ValidationIssue> = Vec::new();
+    let mut report =
+        |severity: Severity, message: String| issues.push(ValidationIssue { severity, message });
+
+    let (gltf_old, glb_bin) = match read_gltf_from_file(&path) {
+        Ok(gltf) => gltf,
+        Err(e) => {
+            report(Severity::Error, format!("could not parse file: {}", e));
+            return issues;
+        }
+    };
+
+    // Cameras and lights
+    let mut light_count = 0;
+    match read(&path)
+        .map_err(gltf::Error::Io)
+        .and_then(|s| Gltf::from_slice(&s))
+    {
+        Ok(gltf) => {
+            for camera in gltf.cameras() {
+                let name = camera.name().unwrap_or("unnamed").to_owned();
+                match camera.projection() {
+                    Projection::Perspective(perspective) => {
+                        let yfov = perspective.yfov();
+                        if !(yfov > 0. && yfov < PI) {
+                            report(
+                                Severity::Error,
+                                format!(
+                                    "camera `{}` has a degenerate vertical FOV ({})",
+                                    name, yfov
+                                ),
+                            );
+                        }
+                        if perspective.aspect_ratio().is_some_and(|a| a <= 0.) {
+                            report(
+                                Severity::Error,
+                                format!("camera `{}` has a non-positive aspect ratio", name),
+                            );
+                        }
+                    }
+                    Projection::Orthographic(orthographic) => {
+                        if orthographic.xmag() == 0. || orthographic.ymag() == 0. {
+                            report(
+                                Severity::Error,
+                                format!("camera `{}` has a zero orthographic extent", name),
+                            );
+                        }
+                    }
+                }
+            }
+
+            if let Some(lights) = gltf.lights() {
+                light_count = lights.count();
+            }
+            // Emissive materials are lights too
+            light_count += gltf_old
+                .materials
+                .iter()
+                .filter(|mat| mat.emissiveFactor.iter().any(|&c| c > 0.))
+                .count();
+        }
+        Err(e) => report(Severity::Error, format!("invalid glTF document: {}", e)),
+    }
+
+    // Buffers
+    let base_dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+    let mut buffers: Vec<Vec<u8>> = Vec::new();
+    for (i, buf) in gltf_old.buffers.iter().enumerate() {
+        match load_buffer(buf.uri.as_deref(), base_dir, glb_bin.as_deref()) {
+            Ok(bytes) if bytes.len() >= buf.byteLength => buffers.push(bytes),
+            Ok(bytes) => {
+                report(
+                    Severity::Error,
+                    format!(
+                        "buffer {} holds {} bytes but declares {}",
+                        i,
+                        bytes.len(),
+                        buf.byteLength
+                    ),
+                );
+                buffers.push(bytes);
+            }
+            Err(e) => {
+                report(
+                    Severity::Error,
+                    format!("buffer {} failed to load: {}", i, e),
+                );
+                buffers.push(Vec::new());
+            }
+        }
+    }
+
+    let mut buffer_views: Vec<Vec<u8>> = Vec::new();
+    for (i, bv) in gltf_old.bufferViews.iter().enumerate() {
+        match buffers
+            .get(bv.buffer)
+            .and_then(|b| b.get(bv.byteOffset..(bv.byteOffset + bv.byteLength)))
+        {
+            Some(bytes) => buffer_views.push(bytes.to_vec()),
+            None => {
+                report(
+                    Severity::Error,
+                    format!("bufferView {} is out of its buffer's range", i),
+                );
+                buffer_views.push(Vec::new());
+            }
+        }
+    }
+
+    // Accessors that can't be read are left empty so that the geometry checks can still run
+    let mut accessors: Vec<Vec<DataType>> = Vec::new();
+    for (i, acc) in gltf_old.accessors.iter().enumerate() {
+        if acc.type_ == "SCALAR"
+            && ![UNSIGNED_BYTE, UNSIGNED_SHORT, UNSIGNED_INT].contains(&acc.componentType)
+        {
+            report(
+                Severity::Error,
+                format!(
+                    "accessor {} has unsupported index component type {}",
+                    i, acc.componentType
+                ),
+            );
+            accessors.push(Vec::new());
+            continue;
+        }
+        if !vector_components_supported(acc) {
+            report(
+                Severity::Error,
+                format!(
+                    "accessor {} has unsupported {} component type {}",
+                    i, acc.type_, acc.componentType
+                ),
+            );
+            accessors.push(Vec::new());
+            continue;
+        }
+
+        match gltf_accessors_to_data(
+            std::slice::from_ref(acc),
+            &gltf_old.bufferViews,
+            &buffer_views,
+        ) {
+            Ok(mut data) => accessors.append(&mut data),
+            Err(_) => {
+                report(
+                    Severity::Error,
+                    format!("accessor {} reads past the end of its bufferView", i),
+                );
+                accessors.push(Vec::new());
+            }
+        }
+    }
+
+    let mut triangle_count = 0;
+    let mut triangle_bounds: Vec<AABB> = Vec::new();
+    for (index, mesh) in gltf_old.meshes.iter().enumerate() {
+        for primitive in mesh.primitives.iter() {
+            // Decoded as the loader does, so that what is reported is what would be rendered
+            let (indices, positions) = match gltf_primitive_geometry(index, primitive, &accessors) {
+                Ok(geometry) => geometry,
+                Err(GltfError::MissingVertex { .. }) => {
+                    report(
+                        Severity::Error,
+                        format!("mesh `{}` has out of range vertex indices", mesh.name),
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    report(Severity::Error, e.to_string());
+                    continue;
+                }
+            };
+
+            let attributes = [
+                ("NORMAL", primitive.attributes.NORMAL),
+                ("TEXCOORD_0", primitive.attributes.TEXCOORD_0),
+            ];
+            for (name, accessor) in attributes {
+                if let Some(data) = accessor.and_then(|i| accessors.get(i)) {
+                    if data.len() != positions.len() {
+                        report(
+                            Severity::Warning,
+                            format!(
+                                "mesh `{}` has {} {} values for {} vertices, they will be ignored",
+                                mesh.name,
+                                data.len(),
+                                name,
+                                positions.len()
+                            ),
+                        );
+                    }
+                }
+            }
+
+            let nan_count = positions.iter().filter(|v| v.is_nan()).count();
+            if nan_count > 0 {
+                report(
+                    Severity::Error,
+                    format!("mesh `{}` has {} NaN vertices", mesh.name, nan_count),
+                );
+            }
+
+            let degenerate_count = indices
+                .chunks_exact(3)
+                .filter(|tri| {
+                    is_degenerate(positions[tri[0]], positions[tri[1]], positions[tri[2]])
+                })
+                .count();
+            if degenerate_count > 0 {
+                report(
+                    Severity::Warning,
+                    format!(
+                        "mesh `{}` has {} degenerate triangles",
+                        mesh.name, degenerate_count
+                    ),
+                );
+            }
+
+            triangle_count += indices.len() / 3;
+            triangle_bounds.extend(
+                indices
+                    .chunks_exact(3)
+                    .map(|tri| [positions[tri[0]], positions[tri[1]], positions[tri[2]]])
+                    .filter(|tri| tri.iter().all(|v| v.is_finite()))
+                    .map(|[v0, v1, v2]| AABB {
+                        min: v0.min(v1).min(v2),
+                        max: v0.max(v1).max(v2),
+                    }),
+            );
+        }
+    }
+
+    if triangle_count == 0 && light_count == 0 {
+        report(Severity::Error, "the scene is empty".to_owned());
+    } else if light_count == 0 {
+        report(
+            Severity::Warning,
+            "the scene has no lights and will only be lit by the background".to_owned(),
+        );
+    }
+
+    if !triangle_bounds.is_empty() {
+        let bvh = BVH::new(&triangle_bounds);
+        if let Err(e) = bvh.validate(&triangle_bounds) {
+            report(
+                Severity::Error,
+                format!("invalid BVH over the triangles: {}", e),
+            );
+        }
+        if verbose {
+            report(
+                Severity::Note,
+                format!(
+                    "BVH over {} triangles: {}",
+                    triangle_bounds.len(),
+                    bvh.stats()
+                ),
+            );
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        material::{HitRecord, Lambertian},
+        ray::Ray,
+    };
+
+    fn accessor(component_type: u32, count: usize, type_: &str) -> GLTFAccessor {
+        GLTFAccessor {
+            bufferView: 0,
+            byteOffset: 0,
+            componentType: component_type,
+            count,
+            type_: type_.to_owned(),
+        }
+    }
+
+    fn view(byte_length: usize, byte_stride: Option<usize>) -> GLTFBufferView {
+        GLTFBufferView {
+            buffer: 0,
+            byteLength: byte_length,
+            byteOffset: 0,
+            byteStride: byte_stride,
+        }
+    }
+
+    // Decodes accessors from the tightly packed bytes of a single bufferView
+    fn decode(accessors: &[GLTFAccessor], bytes: Vec<u8>) -> Result<Vec<Vec<DataType>>, GltfError> {
+        gltf_accessors_to_data(accessors, &[view(bytes.len(), None)], &[bytes])
+    }
+
+    fn scalars(data: &[DataType]) -> Vec<u32> {
+        data.iter()
+            .map(|x| match x {
+                DataType::Scalar(k) => *k,
+                _ => panic!("{:?} isn't a scalar", x),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_u32_indices() {
+        // Two triangles, one of them past the range of 16 bit indices
+        let indices: [u32; 6] = [0, 1, 2, 70000, 65536, 2];
+        let bytes: Vec<u8> = indices.iter().flat_map(|k| k.to_le_bytes()).collect();
+
+        let data = decode(&[accessor(UNSIGNED_INT, 6, "SCALAR")], bytes).unwrap();
+        assert_eq!(scalars(&data[0]), indices);
+    }
+
+    #[test]
+    fn decodes_u8_indices() {
+        // Packed one byte after the other, with no padding
+        let bytes = vec![3, 0, 255, 7, 1, 2];
+
+        let data = decode(&[accessor(UNSIGNED_BYTE, 6, "SCALAR")], bytes).unwrap();
+        assert_eq!(scalars(&data[0]), [3, 0, 255, 7, 1, 2]);
+    }
+
+    #[test]
+    fn decodes_u16_indices() {
+        let indices: [u16; 3] = [2, 0, 65535];
+        let bytes: Vec<u8> = indices.iter().flat_map(|k| k.to_le_bytes()).collect();
+
+        let data = decode(&[accessor(UNSIGNED_SHORT, 3, "SCALAR")], bytes).unwrap();
+        assert_eq!(scalars(&data[0]), [2, 0, 65535]);
+    }
+
+    fn vec2s(data: &[DataType]) -> Vec<Vec2> {
+        data.iter()
+            .map(|x| match x {
+                DataType::Vec2(v) => *v,
+                _ => panic!("{:?} isn't a vec2", x),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_normalized_u16_texcoords() {
+        let texcoords: [u16; 4] = [0, 65535, 32768, 0];
+        let bytes: Vec<u8> = texcoords.iter().flat_map(|k| k.to_le_bytes()).collect();
+
+        let data = decode(&[accessor(UNSIGNED_SHORT, 2, "VEC2")], bytes).unwrap();
+        let uvs = vec2s(&data[0]);
+        assert_eq!(uvs[0], Vec2::new(0., 1.));
+        assert!((uvs[1] - Vec2::new(0.5, 0.)).abs().max_element() < 1e-4);
+    }
+
+    #[test]
+    fn decodes_normalized_u8_texcoords() {
+        let bytes = vec![255, 0, 51, 255];
+
+        let data = decode(&[accessor(UNSIGNED_BYTE, 2, "VEC2")], bytes).unwrap();
+        assert_eq!(vec2s(&data[0]), [Vec2::new(1., 0.), Vec2::new(0.2, 1.)]);
+    }
+
+    #[test]
+    fn quantized_positions_are_rejected() {
+        // u16 positions, which would overrun their 6 byte elements if read as floats
+        let positions: [u16; 3] = [1, 2, 3];
+        let bytes: Vec<u8> = positions.iter().flat_map(|k| k.to_le_bytes()).collect();
+
+        let err = decode(&[accessor(UNSIGNED_SHORT, 1, "VEC3")], bytes).unwrap_err();
+        assert!(matches!(
+            err,
+            GltfError::UnsupportedComponentType {
+                accessor: 0,
+                component_type: UNSIGNED_SHORT
+            }
+        ));
+    }
+
+    #[test]
+    fn u32_indices_connect_the_right_vertices() {
+        // A triangle whose corners are past the range of 16 bit indices
+        let mut positions = vec![Vec3A::splat(-5.); 70001];
+        positions[0] = vec3a(0., 0., 0.);
+        positions[70000] = vec3a(1., 0., 0.);
+        positions[65536] = vec3a(0., 1., 0.);
+        let accessors = vec![
+            [0, 70000, 65536].map(DataType::Scalar).into(),
+            positions.into_iter().map(DataType::Vec3).collect(),
+        ];
+        let mesh = GLTFMesh {
+            name: "triangle".to_owned(),
+            primitives: vec![GLTFMeshPrimitive {
+                attributes: GLTFMeshPrimitiveAttributes {
+                    POSITION: 1,
+                    NORMAL: None,
+                    TEXCOORD_0: None,
+                },
+                indices: 0,
+                material: 0,
+            }],
+        };
+        let materials = [MeshMaterial {
+            material: Arc::new(Lambertian::from_color(Color::ONE)),
+            double_sided: true,
+            emissive: false,
+        }];
+
+        let meshes = gltf_mesh_to_triangle_meshes(0, &mesh, &accessors, &materials, false).unwrap();
+        assert_eq!(meshes.len(), 1);
+        let hit = |x, y| {
+            let ray = Ray::new(vec3a(x, y, 1.), vec3a(0., 0., -1.), 0.);
+            meshes[0].hit(&ray, 0.001, f32::INFINITY).is_some()
+        };
+        assert!(hit(0.25, 0.25));
+        assert!(!hit(0.75, 0.75));
+    }
+
+    #[test]
+    fn truncated_buffers_are_errors() {
+        // Five bytes where a view declares eight
+        assert!(matches!(
+            gltf_buffer_views_to_bytes(&[view(8, None)], &[vec![0; 5]]),
+            Err(GltfError::BufferViewOutOfRange { view: 0, buffer: 0 })
+        ));
+
+        // Two and a half positions where an accessor declares three
+        let accessors = [accessor(FLOAT, 3, "VEC3")];
+        assert!(matches!(
+            decode(&accessors, vec![0; 30]),
+            Err(GltfError::AccessorOutOfRange {
+                accessor: 0,
+                index: 2
+            })
+        ));
+        assert!(matches!(
+            gltf_accessors_to_data(&accessors, &[], &[]),
+            Err(GltfError::MissingBufferView {
+                accessor: 0,
+                view: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn truncated_glb_containers_are_errors() {
+        let json = br#"{"asset":{"version":"2.0"}}"#;
+        let mut glb = Vec::new();
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(20 + json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(&GLB_CHUNK_JSON.to_le_bytes());
+        glb.extend_from_slice(json);
+
+        assert_eq!(parse_glb(&glb).unwrap().0.as_bytes(), json);
+        assert!(parse_glb(&glb[..glb.len() - 4]).is_err());
+        assert!(parse_glb(&glb[..10]).is_err());
+    }
+
+    #[test]
+    fn accessors_start_at_their_offset_and_follow_the_stride() {
+        // Positions interleaved with normals, each vertex taking 24 bytes, after 4 bytes of
+        // padding
+        let mut bytes = vec![0; 4];
+        for k in 0..3 {
+            let position = [k as f32, 1., 2.];
+            let normal = [0., 0., -(k as f32)];
+            bytes.extend(
+                position
+                    .iter()
+                    .chain(normal.iter())
+                    .flat_map(|x| x.to_le_bytes()),
+            );
+        }
+        let views = [view(bytes.len(), Some(24))];
+        let positions = GLTFAccessor {
+            byteOffset: 4,
+            ..accessor(FLOAT, 3, "VEC3")
+        };
+        let normals = GLTFAccessor {
+            byteOffset: 16,
+            ..accessor(FLOAT, 3, "VEC3")
+        };
+
+        let data = gltf_accessors_to_data(&[positions, normals], &views, &[bytes]).unwrap();
+        assert_eq!(
+            data[0],
+            (0..3)
+                .map(|k| DataType::Vec3(vec3a(k as f32, 1., 2.)))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            data[1],
+            (0..3)
+                .map(|k| DataType::Vec3(vec3a(0., 0., -(k as f32))))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn accessor_offset_defaults_to_zero() {
+        let json = r#"{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}"#;
+        let acc: GLTFAccessor = from_str(json).unwrap();
+
+        assert_eq!(acc.byteOffset, 0);
+    }
+
+    #[test]
+    fn strings_containing_type_are_left_alone() {
+        // One triangle, with its indices padded to 8 bytes before the positions
+        let dir = std::env::temp_dir().join("gltf_type_in_names");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut bytes: Vec<u8> = [0u16, 1, 2, 0]
+            .iter()
+            .flat_map(|k| k.to_le_bytes())
+            .collect();
+        for x in [0., 0., 0., 1., 0., 0., 0., 1., 0.] {
+            bytes.extend((x as f32).to_le_bytes());
+        }
+        std::fs::write(dir.join("prototype.bin"), &bytes).unwrap();
+
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "scene": 0,
+            "scenes": [{"name": "prototype", "nodes": [0]}],
+            "nodes": [{"name": "prototype", "mesh": 0}],
+            "materials": [{"name": "prototype"}],
+            "meshes": [{
+                "name": "prototype",
+                "primitives": [{"attributes": {"POSITION": 1}, "indices": 0, "material": 0}]
+            }],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5123, "count": 3, "type": "SCALAR"},
+                {"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3",
+                 "min": [0, 0, 0], "max": [1, 1, 0]}
+            ],
+            "bufferViews": [
+                {"buffer": 0, "byteLength": 6},
+                {"buffer": 0, "byteOffset": 8, "byteLength": 36}
+            ],
+            "buffers": [{"byteLength": 44, "uri": "prototype.bin"}]
+        }"#;
+        let path = dir.join("prototype.gltf");
+        std::fs::write(&path, json).unwrap();
+
+        let scene = Scene::from_gltf_file(&path).unwrap();
+        let ray = Ray::new(vec3a(0.25, 0.25, 1.), vec3a(0., 0., -1.), 0.);
+        assert!(scene.world.hit(&ray, 0.001, f32::INFINITY).is_some());
+    }
+
+    #[test]
+    fn validation_reports_the_broken_fixture() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/validation/broken.gltf");
+        let issues = validate_gltf_file(path, false);
+        let errors: Vec<&str> = issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(|issue| issue.message.as_str())
+            .collect();
+
+        let reported = |message: &str| errors.iter().any(|error| error.contains(message));
+        assert!(reported("degenerate vertical FOV"), "{:?}", errors);
+        assert!(reported("has 1 NaN vertices"), "{:?}", errors);
+        assert!(reported("out of range vertex indices"), "{:?}", errors);
+    }
+
+    #[test]
+    fn buffer_view_offset_defaults_to_zero() {
+        let view: GLTFBufferView = from_str(r#"{"buffer": 0, "byteLength": 4}"#).unwrap();
+        assert_eq!(view.byteOffset, 0);
+
+        let views = gltf_buffer_views_to_bytes(&[view], &[vec![1, 2, 3, 4, 5]]).unwrap();
+        assert_eq!(views[0], [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn material_factors_default_to_the_gltf_values() {
+        for json in [
+            r#"{"name": "empty", "pbrMetallicRoughness": {}}"#,
+            r#"{"name": "no pbr"}"#,
+        ] {
+            let material: GLTFMaterial = from_str(json).unwrap();
+            let pbr = &material.pbrMetallicRoughness;
+
+            assert_eq!(pbr.baseColorFactor, [1., 1., 1., 1.]);
+            assert_eq!(pbr.metallicFactor, 1.);
+            assert_eq!(pbr.roughnessFactor, 1.);
+        }
+
+        let json = r#"{"name": "red", "pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1]}}"#;
+        let material: GLTFMaterial = from_str(json).unwrap();
+        assert_eq!(
+            material.pbrMetallicRoughness.baseColorFactor,
+            [1., 0., 0., 1.]
+        );
+        assert_eq!(material.pbrMetallicRoughness.roughnessFactor, 1.);
+    }
+
+    #[test]
+    fn double_sided_emissive_materials_emit_from_both_sides() {
+        let json = r#"[
+            {"name": "front", "emissiveFactor": [1, 1, 1]},
+            {"name": "both", "emissiveFactor": [1, 1, 1], "doubleSided": true}
+        ]"#;
+        let materials: Vec<GLTFMaterial> = from_str(json).unwrap();
+        let materials = gltf_materials_to_materials(&materials, &[]).unwrap();
+
+        // Seen from behind the surface, along its normal
+        let ray = Ray::new(vec3a(0., 0., -1.), vec3a(0., 0., 1.), 0.);
+        let emitted = |material: &MeshMaterial| {
+            let rec = HitRecord {
+                p: Vec3A::ZERO,
+                normal: Vec3A::Z,
+                t: 1.,
+                mat: material.material.clone(),
+                u: 0.,
+                v: 0.,
+            };
+            material.material.emitted(&ray, &rec, 0., 0., &rec.p)
+        };
+
+        assert!(materials.iter().all(|material| material.emissive));
+        assert_eq!(emitted(&materials[0]), Color::ZERO);
+        assert_eq!(emitted(&materials[1]), Color::ONE);
     }
 }
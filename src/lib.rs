@@ -0,0 +1,27 @@
+//! Ray-tracing based rendering engine. The `rust-ray-tracer` binary is a command line front end
+//! to this library: build a `scene::Scene`, by hand or with `scene::get_scene`, and pass it to
+//! `render::render` to get an image back.
+
+pub mod background;
+pub mod bench;
+pub mod bloom;
+pub mod bvh;
+pub mod camera;
+pub mod denoise;
+pub mod filter;
+pub mod geometry;
+pub mod gltf;
+pub mod material;
+pub mod metrics;
+pub mod obj;
+pub mod output;
+pub mod pdf;
+pub mod ray;
+pub mod render;
+pub mod rng;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod stl;
+pub mod vec3;
+pub mod vignette;
@@ -1,26 +1,27 @@
-mod bvh;
-mod camera;
-mod geometry;
-mod gltf;
-mod material;
-mod pdf;
-mod ray;
-mod scene;
-mod vec3;
-
-use clap::App;
-use image::{ImageBuffer, RgbImage};
+use clap::{App, AppSettings, SubCommand};
+use glam::Affine3A;
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::{prelude::*, ThreadPoolBuilder};
-use std::sync::Mutex;
+use rayon::ThreadPoolBuilder;
+use std::{error::Error, path::Path, process::exit, sync::Arc, time::Instant};
 
-use rand::{thread_rng, Rng};
-use vec3::Color;
-
-use crate::{
-    ray::ray_color,
-    scene::{get_scene, Scene, SceneType},
-    vec3::get_color,
+use rust_ray_tracer::{
+    background::{Background, EnvironmentMap},
+    bench::run_benchmark,
+    bloom::bloom,
+    camera::Projection,
+    denoise::denoise,
+    filter::Filter,
+    geometry::Transformable,
+    gltf::{validate_gltf_file, Severity},
+    metrics::{compute_metrics, load_reference},
+    output::{read_png_metadata, save_aovs, save_render},
+    pdf::MisHeuristic,
+    render::{render_frame, render_progressive, tiles, to_image, RenderSettings},
+    rng,
+    sampler::SamplerType,
+    scene::{get_scene, LightSampling, Scene, SceneType},
+    vec3::ToneMap,
+    vignette::vignette,
 };
 
 fn main() {
@@ -31,88 +32,422 @@ fn main() {
         .about("Ray-tracing based rendering engine")
         .args_from_usage(
             "-t, --threads=[NUM_THREADS] 'Sets the desired number of threads'
-            -o, --output=[FILE]          'Sets the output image file name'
-            -g --gltf=[FILE]             'Sets the input glTF scene file'
+            -o, --output=[FILE]          'Sets the output image file name (.exr and .hdr keep the linear radiance)'
+            -e --environment=[FILE]      'Lights the scene with an equirectangular environment map (.hdr)'
+            -g --gltf=[FILE]             'Sets the input glTF scene file (.gltf or .glb)'
+            --obj=[FILE]                 'Sets the input Wavefront OBJ mesh file (.obj)'
+            --stl=[FILE]                 'Sets the input STL mesh file (.stl)'
+            --json=[FILE]                'Sets the input JSON scene description file (.json)'
+            --anisotropy=[G]             'Has the fog of the final scenes scatter light forward (up to 1) or backward (down to -1)'
+            --terrain=[FILE]             'Renders a grayscale heightmap image as terrain'
+            -s --scene=[SCENE]           'Renders a built-in scene instead of a glTF file (defaults to cornell)'
             -a --aspect_ratio=[FILE]     'Sets the camera aspect ratio'
+            -d --depth=[DEPTH]           'Sets the maximum number of bounces per path (defaults to 12)'
+            --bloom=[INTENSITY]          'Adds a glow of this intensity around the brightest parts of the image'
+            --clamp=[LUMINANCE]          'Clamps the luminance of each sample to remove fireflies'
+            --denoise=[STRENGTH]         'Smooths the noise of the render, guided by the first hits (try 1)'
+            --roll=[DEGREES]             'Rolls the camera around its viewing axis'
+            --panorama                   'Renders a 360° equirectangular panorama from the camera'
+            --aovs                       'Also saves the albedo, normal and depth of the first hits'
+            --ascii                      'Writes .ppm outputs as ASCII (P3) rather than binary (P6)'
+            --alpha                      'Makes the background transparent (PNG output)'
+            --progressive                'Refines the image in passes, saving a preview after each one'
+            --frames=[FRAMES]            'Renders an image sequence of this many frames'
+            --fps=[FPS]                  'Sets the frame rate of image sequences (defaults to 24)'
+            --orbit=[DEGREES]            'Orbits the camera around the vertical axis over the sequence'
+            -r --reference=[FILE]        'Compares the render against a reference image'
+            --seed=[SEED]                'Seeds the random numbers, the same seed gives the same image'
+            --lights=[STRATEGY]          'Picks the light to sample uniformly or by its power (uniform or power)'
+            --nee                        'Samples a light with a shadow ray at each diffuse or glossy bounce'
+            --mis=[HEURISTIC]            'Sets the multiple importance sampling heuristic (balance or power)'
+            --sampler=[SAMPLER]          'Sets the sampler (random, stratified, halton or sobol)'
+            --filter=[FILTER]            'Sets the pixel reconstruction filter (box, tent or gaussian)'
+            --tonemap=[TONEMAP]          'Sets the tone mapping (none, reinhard or aces)'
+            --vignette=[STRENGTH]        'Darkens the corners of the image (0 to 1)'
             <HEIGHT>                     'Sets the image height'
             <SAMPLES>                    'Sets the number of samples per pixel'",
         )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Checks a glTF scene file for problems without rendering it")
+                .arg_from_usage("<FILE> 'Sets the glTF scene file to check'")
+                .arg_from_usage("-v --verbose 'Also prints statistics about the BVH over the triangles'"),
+        )
+        .subcommand(
+            SubCommand::with_name("print-meta")
+                .about("Prints the render settings recorded in a PNG render")
+                .arg_from_usage("<FILE> 'Sets the PNG file to read'"),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Compares the dynamic and statically typed worlds on small scenes")
+                .args_from_usage(
+                    "[HEIGHT]  'Sets the image height (defaults to 100)'
+                    [SAMPLES] 'Sets the number of samples per pixel (defaults to 16)'",
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("validate") {
+        let file = matches.value_of("FILE").unwrap();
+        let issues = validate_gltf_file(file, matches.is_present("verbose"));
+
+        for issue in issues.iter() {
+            println!("{}", issue);
+        }
+
+        if issues.iter().any(|issue| issue.severity == Severity::Error) {
+            exit(1);
+        }
+        println!("{}: no errors found", file);
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("print-meta") {
+        let file = matches.value_of("FILE").unwrap();
+        match read_png_metadata(file) {
+            Ok(metadata) => {
+                for (keyword, text) in metadata {
+                    println!("{}: {}", keyword, text);
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not read {}: {}", file, e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        let height: u32 = matches.value_of("HEIGHT").unwrap_or("100").parse().unwrap();
+        let samples: u32 = matches.value_of("SAMPLES").unwrap_or("16").parse().unwrap();
+        run_benchmark(height, samples);
+        return;
+    }
+
     let threads: i32 = matches.value_of("threads").unwrap_or("-1").parse().unwrap();
 
     if threads > 0 {
         ThreadPoolBuilder::new()
-            .num_threads(threads.abs() as usize)
+            .num_threads(threads as usize)
             .build_global()
             .unwrap();
     }
 
     // Configuration
-    let gltf_file = matches.value_of("gltf").unwrap_or("assets/default.gltf");
     let output_file = matches.value_of("output").unwrap_or("output/render.png");
     let height: u32 = matches.value_of("HEIGHT").unwrap().parse().unwrap();
     let samples: u32 = matches.value_of("SAMPLES").unwrap().parse().unwrap();
-    const MAX_DEPTH: u32 = 4;
+    let sampler_type: SamplerType = matches
+        .value_of("sampler")
+        .unwrap_or("random")
+        .parse()
+        .unwrap();
+    let filter: Filter = matches.value_of("filter").unwrap_or("box").parse().unwrap();
+    let tone_map: ToneMap = matches
+        .value_of("tonemap")
+        .unwrap_or("none")
+        .parse()
+        .unwrap();
+    let max_depth: u32 = matches.value_of("depth").unwrap_or("12").parse().unwrap();
+    let heuristic: MisHeuristic = matches
+        .value_of("mis")
+        .unwrap_or("balance")
+        .parse()
+        .unwrap();
+    let clamp: Option<f32> = matches
+        .value_of("clamp")
+        .map(|clamp| clamp.parse().unwrap());
+    let bloom_intensity: Option<f32> = matches
+        .value_of("bloom")
+        .map(|intensity| intensity.parse().unwrap());
+    let vignette_strength: f32 = matches.value_of("vignette").unwrap_or("0").parse().unwrap();
+    let denoise_strength: Option<f32> = matches
+        .value_of("denoise")
+        .map(|strength| strength.parse().unwrap());
 
-    // Progress bar
-    let bar = ProgressBar::new(height.into());
-    bar.set_style(
-        ProgressStyle::default_bar()
-        .template("{percent}% {bar:80.cyan/blue} [Elapsed: {elapsed_precise} | Remaining: {eta_precise}]")
-        .progress_chars("██⎯"),
-    );
+    // Each pixel reseeds its thread's generator, so that the image doesn't depend on scheduling.
+    // Randomly generated scenes are built from the same seed.
+    let seed: u64 = matches
+        .value_of("seed")
+        .map(|seed| seed.parse().unwrap())
+        .unwrap_or_else(rand::random);
+    rng::set_seed(seed);
 
     // Scene
-    const USE_GLTF: bool = true;
-    let scene: Scene;
+    let aspect_ratio: Option<f32> = matches
+        .value_of("aspect_ratio")
+        .map(|aspect_ratio| aspect_ratio.parse().unwrap());
+
+    // Kept off ±1, where the phase function collapses into a single direction
+    let anisotropy: Option<f32> = matches
+        .value_of("anisotropy")
+        .map(|g| g.parse::<f32>().unwrap().clamp(-0.99, 0.99));
+    if anisotropy.is_some()
+        && !matches!(
+            matches.value_of("scene"),
+            Some("final" | "hazy-final" | "cloud")
+        )
+    {
+        eprintln!("--anisotropy only applies to the final scenes");
+    }
 
-    if USE_GLTF {
-        scene = Scene::from_gltf_file(gltf_file).unwrap();
+    // Scene files that fail to load are reported without a panic
+    let loaded = |scene: Result<Scene, Box<dyn Error>>| {
+        scene.unwrap_or_else(|e| {
+            eprintln!("Could not load the scene: {}", e);
+            exit(1);
+        })
+    };
+    let mut scene: Scene = if let Some(name) = matches.value_of("scene") {
+        get_scene(
+            name.parse().unwrap(),
+            aspect_ratio.unwrap_or(1.),
+            anisotropy,
+        )
+    } else if let Some(gltf_file) = matches.value_of("gltf") {
+        loaded(Scene::from_gltf_file(gltf_file))
+    } else if let Some(obj_file) = matches.value_of("obj") {
+        loaded(Scene::from_obj_file(obj_file))
+    } else if let Some(stl_file) = matches.value_of("stl") {
+        loaded(Scene::from_stl_file(stl_file))
+    } else if let Some(json_file) = matches.value_of("json") {
+        loaded(Scene::from_file(json_file))
+    } else if let Some(heightmap_file) = matches.value_of("terrain") {
+        loaded(Scene::from_heightmap_file(heightmap_file))
     } else {
-        scene = get_scene(SceneType::CornellBox, 1.);
+        get_scene(
+            SceneType::CornellBox,
+            aspect_ratio.unwrap_or(1.),
+            anisotropy,
+        )
+    };
+
+    if let Some(environment_file) = matches.value_of("environment") {
+        let environment_map = EnvironmentMap::from_file(environment_file).unwrap();
+        scene.background = Background::Environment(Arc::new(environment_map));
     }
 
-    let aspect_ratio: f32 = matches
-        .value_of("aspect_ratio")
-        .unwrap_or(&scene.camera.aspect_ratio.to_string())
+    let roll: f32 = matches.value_of("roll").unwrap_or("0").parse().unwrap();
+    scene.camera.roll(roll);
+
+    let light_sampling: LightSampling = matches
+        .value_of("lights")
+        .unwrap_or("uniform")
         .parse()
         .unwrap();
+    if light_sampling == LightSampling::Power {
+        scene.weight_lights_by_power();
+    }
+
+    // Panoramas cover 360° horizontally and 180° vertically
+    let panorama = matches.is_present("panorama");
+    if panorama {
+        scene.camera.set_projection(Projection::Equirectangular);
+    }
+
+    let aspect_ratio = aspect_ratio.unwrap_or(if panorama {
+        2.
+    } else {
+        scene.camera.aspect_ratio
+    });
     let width = ((height as f32) * aspect_ratio) as u32;
 
-    // Render
-    let img: Mutex<RgbImage> = Mutex::new(ImageBuffer::new(width, height));
-
-    (0..height).into_par_iter().for_each(|y| {
-        for x in 0..width {
-            let mut rng = thread_rng();
-            let mut color = Color::new(0., 0., 0.);
-
-            for _ in 0..samples {
-                let u = x as f32 + rng.gen::<f32>();
-                let v = y as f32 + rng.gen::<f32>();
-                let ray = scene.camera.get_ray(u, v, width, height);
-                color += ray_color(
-                    &ray,
-                    &scene.background,
-                    scene.world.clone(),
-                    scene.lights.clone(),
-                    MAX_DEPTH,
+    // Animation: each frame gets its own shutter interval, starting from the scene's, while the
+    // world and its BVH are reused as is
+    let frames: u32 = matches.value_of("frames").unwrap_or("1").parse().unwrap();
+    let fps: f32 = matches.value_of("fps").unwrap_or("24").parse().unwrap();
+    let orbit: f32 = matches.value_of("orbit").unwrap_or("0").parse().unwrap();
+    let animated = frames > 1;
+
+    let frame_duration = 1. / fps;
+    if animated && scene.time0 + frames as f32 * frame_duration > scene.time1 {
+        eprintln!(
+            "The animation ends after the scene's time range [{}, {}], moving objects may be clipped past it",
+            scene.time0, scene.time1
+        );
+    }
+
+    let settings = RenderSettings {
+        width,
+        height,
+        samples,
+        sampler_type,
+        filter,
+        tone_map,
+        max_depth,
+        heuristic,
+        next_event: matches.is_present("nee"),
+        clamp,
+        seed,
+        // The denoiser is guided by the AOVs
+        aovs: matches.is_present("aovs") || denoise_strength.is_some(),
+        alpha: matches.is_present("alpha"),
+    };
+
+    // Progressive renders don't compute the AOVs nor the alpha
+    let progressive = matches.is_present("progressive");
+    if progressive && matches.is_present("aovs") {
+        eprintln!("--aovs is ignored by progressive renders");
+    }
+    if progressive && settings.alpha {
+        eprintln!("--alpha is ignored by progressive renders");
+    }
+    if denoise_strength.is_some() && settings.alpha {
+        eprintln!("--denoise is ignored by --alpha renders");
+    }
+    if bloom_intensity.is_some() && settings.alpha {
+        eprintln!("--bloom is ignored by --alpha renders");
+    }
+
+    // Progress bar, counting the tiles of all the frames
+    let tile_count = tiles(width, height).len() as u64;
+    let bar = ProgressBar::new(tile_count * frames.max(1) as u64);
+    bar.set_style(
+        ProgressStyle::default_bar()
+        .template("{percent}% {bar:80.cyan/blue} [Elapsed: {elapsed_precise} | Remaining: {eta_precise}]")
+        .progress_chars("██⎯"),
+    );
+
+    // Recorded in PNG outputs, so that old renders can be reproduced
+    let command_line: Vec<String> = std::env::args().collect();
+    let command_line = command_line.join(" ");
+    let scene_name = ["scene", "gltf", "obj", "stl", "json", "terrain"]
+        .iter()
+        .find_map(|arg| matches.value_of(arg))
+        .unwrap_or("cornell");
+
+    let scene_time0 = scene.time0;
+    let mut frame = None;
+    for i in 0..frames.max(1) {
+        let file = if animated {
+            frame_path(output_file, i + 1)
+        } else {
+            output_file.to_owned()
+        };
+
+        if animated {
+            let time0 = scene_time0 + i as f32 * frame_duration;
+            scene.camera.set_shutter(time0, time0 + frame_duration);
+            // Orbit around the world's vertical axis, by the same angle between all frames
+            if i > 0 {
+                let angle = (orbit / frames as f32).to_radians();
+                scene
+                    .camera
+                    .apply_transform(Affine3A::from_rotation_y(angle));
+            }
+        }
+
+        // Frames of a sequence get different noise
+        let frame_settings = RenderSettings {
+            seed: seed.wrapping_add(i as u64),
+            ..settings
+        };
+        let progress = |fraction: f32| {
+            bar.set_position(i as u64 * tile_count + (fraction * tile_count as f32).round() as u64)
+        };
+        let start = Instant::now();
+        let mut rendered = if progressive {
+            let preview_file = preview_path(&file);
+            render_progressive(&scene, &frame_settings, progress, |image, _| {
+                if let Err(e) = image.save(&preview_file) {
+                    eprintln!("Could not save {}: {}", preview_file, e);
+                }
+            })
+        } else {
+            render_frame(&scene, &frame_settings, progress)
+        };
+
+        // Post-processing works on the accumulated radiance, so the image is tone mapped again
+        // after it. Denoising comes first so that the noise doesn't bloom.
+        if !settings.alpha && (denoise_strength.is_some() || bloom_intensity.is_some()) {
+            if let Some(strength) = denoise_strength {
+                rendered.radiance = denoise(
+                    width,
+                    height,
+                    &rendered.radiance,
+                    rendered.first_hits.as_deref(),
+                    strength,
                 );
             }
+            if let Some(intensity) = bloom_intensity {
+                rendered.radiance = bloom(width, height, &rendered.radiance, intensity);
+            }
+            rendered.image = to_image(width, height, &rendered.radiance, tone_map);
+        }
+        // The vignette only darkens the tone mapped images, not the radiance
+        vignette(&mut rendered.image, vignette_strength);
+        if let Some(alpha) = &mut rendered.alpha {
+            vignette(alpha, vignette_strength);
+        }
 
-            let pixel = get_color(color, samples);
-            {
-                img.lock()
-                    .unwrap()
-                    .put_pixel(x as u32, height - 1 - y, pixel);
+        let metadata = [
+            (
+                "Software",
+                format!("rust-ray-tracer {}", env!("CARGO_PKG_VERSION")),
+            ),
+            ("Command line", command_line.clone()),
+            ("Scene", scene_name.to_owned()),
+            ("Resolution", format!("{}x{}", width, height)),
+            ("Samples", samples.to_string()),
+            ("Max depth", max_depth.to_string()),
+            ("Seed", frame_settings.seed.to_string()),
+            (
+                "Render time",
+                format!("{:.3} s", start.elapsed().as_secs_f32()),
+            ),
+        ]
+        .map(|(keyword, text)| (keyword.to_owned(), text));
+        if let Err(e) = save_render(&file, &rendered, matches.is_present("ascii"), &metadata) {
+            eprintln!("Could not save {}: {}", file, e);
+        }
+        if let (Some(first_hits), true) = (&rendered.first_hits, matches.is_present("aovs")) {
+            if let Err(e) = save_aovs(&file, width, height, first_hits) {
+                eprintln!("Could not save the passes of {}: {}", file, e);
             }
         }
-        bar.inc(1);
-    });
+
+        frame = Some(rendered);
+    }
     bar.finish();
 
-    {
-        let _ = img.lock().unwrap().save(output_file);
+    // The last frame is the one compared against the reference
+    if let Some(reference_file) = matches.value_of("reference") {
+        match load_reference(reference_file) {
+            Ok((ref_width, ref_height, reference)) => {
+                if ref_width != width || ref_height != height {
+                    eprintln!(
+                        "Reference image is {}x{} but the render is {}x{}",
+                        ref_width, ref_height, width, height
+                    );
+                } else {
+                    let radiance = &frame.as_ref().unwrap().radiance;
+                    println!("{}", compute_metrics(radiance, &reference));
+                }
+            }
+            Err(e) => eprintln!("Could not load reference image {}: {}", reference_file, e),
+        }
     }
 }
+
+// `render.png` becomes `render_0001.png` for the first frame
+fn frame_path(path: &str, frame: u32) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+        None => format!("{}_{:04}", stem, frame),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// `render.png` gets its previews in `render.preview.png`
+fn preview_path(path: &str) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.preview.png", stem))
+        .to_string_lossy()
+        .into_owned()
+}
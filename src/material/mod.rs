@@ -5,15 +5,19 @@ pub mod texture;
 use std::{f32::consts::PI, sync::Arc};
 
 use glam::Vec3A;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use crate::{
-    pdf::{CosinePDF, PDF},
+    pdf::{ggx_d, smith_g1, CosinePDF, GgxPDF, HenyeyGreensteinPDF, PDF},
     ray::Ray,
-    vec3::{random_in_unit_sphere, Color},
+    rng::rng,
+    vec3::{random_in_unit_sphere, Color, OrthNormBasis},
 };
 
-use self::texture::{SolidColor, Texture};
+use self::{
+    pbr::{fresnel_schlick, sample_ggx_normal, shading_frame, MIN_ALPHA},
+    texture::{SolidColor, Texture},
+};
 
 pub struct HitRecord {
     pub p: Vec3A,
@@ -38,12 +42,32 @@ pub trait Material: Send + Sync {
     fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
         0.
     }
+    // BSDF times cosine toward `scattered`, for materials whose response isn't simply the
+    // scattering pdf tinted by the attenuation
+    fn scattering_color(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scattered: &Ray,
+        attenuation: Color,
+    ) -> Color {
+        attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
     fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Vec3A) -> Color {
         Color::new(0., 0., 0.)
     }
 }
 
-// TODO: Implement PBR Metallic Roughness material
+// Surfaces report their geometric normal, which can point away from the incoming ray when they
+// are hit from the back
+fn facing_normal(r_in: &Ray, rec: &HitRecord) -> Vec3A {
+    if r_in.direction().dot(rec.normal) < 0. {
+        rec.normal
+    } else {
+        -rec.normal
+    }
+}
+
 pub struct Lambertian {
     pub albedo: Arc<dyn Texture>,
 }
@@ -69,20 +93,78 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
         Some(Scatter {
             specular_ray: None,
             attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
-            pdf: Some(Arc::new(CosinePDF::new(rec.normal))),
+            pdf: Some(Arc::new(CosinePDF::new(facing_normal(r_in, rec)))),
         })
     }
 
-    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
-        let cosine = rec.normal.dot(scattered.direction().normalize()) / PI;
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let cosine = facing_normal(r_in, rec).dot(scattered.direction().normalize()) / PI;
         cosine.max(0.)
     }
 }
 
+// Rough diffuse surface made of V-shaped facets whose slopes have a standard deviation of
+// `roughness` radians (Oren-Nayar). Rough surfaces scatter more light back toward the light
+// source than Lambertian ones, which flattens their shading, and a roughness of 0 is Lambertian.
+pub struct OrenNayar {
+    pub albedo: Arc<dyn Texture>,
+    pub roughness: f32,
+}
+
+impl OrenNayar {
+    pub fn new(texture: Arc<dyn Texture>, roughness: f32) -> OrenNayar {
+        OrenNayar {
+            albedo: texture,
+            roughness: roughness.max(0.),
+        }
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        Some(Scatter {
+            specular_ray: None,
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: Some(Arc::new(CosinePDF::new(facing_normal(r_in, rec)))),
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let n = facing_normal(r_in, rec);
+        let wi = scattered.direction().normalize();
+        let wo = -r_in.direction().normalize();
+
+        let cos_i = n.dot(wi);
+        if cos_i <= 0. {
+            return 0.;
+        }
+        let cos_o = n.dot(wo).max(0.);
+
+        let sigma2 = self.roughness * self.roughness;
+        let a = 1. - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        // Cosine of the azimuth between the two directions, in the tangent plane
+        let cos_phi = (wi - cos_i * n)
+            .normalize_or_zero()
+            .dot((wo - cos_o * n).normalize_or_zero())
+            .max(0.);
+
+        // sin(alpha) tan(beta), alpha and beta being the larger and smaller polar angles
+        let sin_i = (1. - cos_i * cos_i).max(0.).sqrt();
+        let sin_o = (1. - cos_o * cos_o).max(0.).sqrt();
+        let sin_alpha_tan_beta = sin_i.max(sin_o) * sin_i.min(sin_o) / cos_i.max(cos_o);
+
+        cos_i / PI * (a + b * cos_phi * sin_alpha_tan_beta)
+    }
+}
+
+const MAX_FUZZ_ATTEMPTS: usize = 16;
+
 pub struct Metal {
     pub albedo: Color,
     pub fuzziness: f32,
@@ -90,44 +172,158 @@ pub struct Metal {
 
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
-        let reflected = reflect(r_in.direction().normalize(), rec.normal);
-        let scattered = Ray::new(
-            rec.p,
-            reflected + self.fuzziness * random_in_unit_sphere(),
-            r_in.time(),
-        );
-        if scattered.direction().dot(rec.normal) > 0. {
-            return Some(Scatter {
-                specular_ray: Some(scattered),
-                attenuation: self.albedo,
-                pdf: None,
-            });
+        let normal = facing_normal(r_in, rec);
+        let reflected = reflect(r_in.direction().normalize(), normal);
+        if reflected.dot(normal) <= 0. {
+            return None;
         }
-        None
+
+        // Perturbed directions below the surface are drawn again, giving up after a few tries at
+        // grazing angles
+        let direction = if self.fuzziness > 0. {
+            (0..MAX_FUZZ_ATTEMPTS)
+                .map(|_| (reflected + self.fuzziness * random_in_unit_sphere()).normalize())
+                .find(|direction| direction.dot(normal) > 0.)?
+        } else {
+            reflected
+        };
+
+        Some(Scatter {
+            specular_ray: Some(Ray::new(rec.p, direction, r_in.time())),
+            attenuation: self.albedo,
+            pdf: None,
+        })
+    }
+}
+
+// Metal with a GGX microfacet lobe, importance sampled through a PDF so that it can be mixed
+// with light sampling
+pub struct RoughMetal {
+    pub albedo: Color,
+    pub roughness: f32,
+}
+
+impl RoughMetal {
+    pub fn new(albedo: Color, roughness: f32) -> RoughMetal {
+        RoughMetal {
+            albedo,
+            roughness: roughness.clamp(0., 1.),
+        }
+    }
+
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+}
+
+impl Material for RoughMetal {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let (n, wo) = shading_frame(r_in, rec);
+        Some(Scatter {
+            specular_ray: None,
+            attenuation: self.albedo,
+            pdf: Some(Arc::new(GgxPDF::new(n, wo, self.alpha()))),
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let (n, wo) = shading_frame(r_in, rec);
+        GgxPDF::new(n, wo, self.alpha()).value(scattered.direction())
+    }
+
+    fn scattering_color(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scattered: &Ray,
+        albedo: Color,
+    ) -> Color {
+        let (n, wo) = shading_frame(r_in, rec);
+        let wi = scattered.direction().normalize();
+
+        let n_dot_wi = n.dot(wi);
+        let n_dot_wo = n.dot(wo);
+        if n_dot_wi <= 0. || n_dot_wo <= 0. {
+            return Color::ZERO;
+        }
+
+        let h = (wi + wo).normalize();
+        let alpha = self.alpha();
+
+        // Cook-Torrance specular BRDF times the cosine
+        fresnel_schlick(albedo, wi.dot(h))
+            * ggx_d(n.dot(h), alpha)
+            * smith_g1(n_dot_wi, alpha)
+            * smith_g1(n_dot_wo, alpha)
+            / (4. * n_dot_wo)
     }
 }
 
 pub struct Dielectric {
     pub ir: f32, // Indice of refraction
+    // Beer-Lambert absorption coefficients per unit of distance travelled inside
+    pub absorption: Color,
+    // Frosts the surface by scattering around GGX microfacet normals, 0 being smooth
+    pub roughness: f32,
+}
+
+impl Dielectric {
+    pub fn new(ir: f32) -> Dielectric {
+        Dielectric {
+            ir,
+            absorption: Color::ZERO,
+            roughness: 0.,
+        }
+    }
+
+    pub fn with_absorption(ir: f32, absorption: Color) -> Dielectric {
+        Dielectric {
+            ir,
+            absorption,
+            roughness: 0.,
+        }
+    }
+
+    pub fn set_roughness(&mut self, roughness: f32) {
+        self.roughness = roughness.clamp(0., 1.);
+    }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
-        let mut rng = thread_rng();
+        let mut rng = rng();
         let mut refraction_ratio = 1. / self.ir;
         let mut n = rec.normal;
+        let mut attenuation = Color::new(1., 1., 1.);
 
         if r_in.direction().dot(rec.normal) > 0. {
             refraction_ratio = self.ir;
             n = -rec.normal;
+
+            // The incoming ray travelled inside the medium up to this hit
+            if self.absorption != Color::ZERO {
+                let distance = rec.t * r_in.direction().length();
+                attenuation = (-self.absorption * distance).exp();
+            }
         }
         let unit_direction = r_in.direction().normalize();
-        let cos_theta = -unit_direction.dot(n).min(1.);
 
-        let attenuation = Color::new(1., 1., 1.);
+        // Rough surfaces refract and reflect off a sampled microfacet instead of the surface
+        let rough = self.roughness > 0.;
+        let m = if rough {
+            let alpha = self.roughness * self.roughness;
+            sample_ggx_normal(&OrthNormBasis::from_w(n), alpha)
+        } else {
+            n
+        };
+        let cos_theta = -unit_direction.dot(m).min(1.);
 
-        if let Some(refracted) = refract(unit_direction, n, refraction_ratio) {
+        if let Some(refracted) = refract(unit_direction, m, refraction_ratio) {
             if reflectance(cos_theta, self.ir) < rng.gen() {
+                // Microfacets can send the refracted ray back to the side it came from
+                if rough && refracted.dot(n) >= 0. {
+                    return None;
+                }
                 return Some(Scatter {
                     specular_ray: Some(Ray::new(rec.p, refracted, r_in.time())),
                     attenuation,
@@ -135,12 +331,110 @@ impl Material for Dielectric {
                 });
             }
         }
+
+        let reflected = reflect(unit_direction, m);
+        if rough && reflected.dot(n) <= 0. {
+            return None;
+        }
+        Some(Scatter {
+            specular_ray: Some(Ray::new(rec.p, reflected, r_in.time())),
+            attenuation,
+            pdf: None,
+        })
+    }
+}
+
+// Smooth dielectric clearcoat of index `ior` over a `base` material (car paint, varnished
+// wood). Each hit either reflects off the coat, with the Fresnel reflectance as odds, or is left
+// to the base: the odds already weigh both layers, so neither is attenuated further.
+pub struct Coated {
+    pub base: Arc<dyn Material>,
+    pub ior: f32,
+}
+
+impl Coated {
+    pub fn new(base: Arc<dyn Material>, ior: f32) -> Coated {
+        Coated { base, ior }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let normal = facing_normal(r_in, rec);
+        let unit_direction = r_in.direction().normalize();
+        let cos_theta = -unit_direction.dot(normal).min(1.);
+
+        if reflectance(cos_theta, self.ior) < rng().gen() {
+            return self.base.scatter(r_in, rec);
+        }
+
         Some(Scatter {
             specular_ray: Some(Ray::new(
                 rec.p,
-                reflect(unit_direction, rec.normal),
+                reflect(unit_direction, normal),
                 r_in.time(),
             )),
+            attenuation: Color::ONE,
+            pdf: None,
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        self.base.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scattering_color(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scattered: &Ray,
+        attenuation: Color,
+    ) -> Color {
+        self.base
+            .scattering_color(r_in, rec, scattered, attenuation)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Vec3A) -> Color {
+        self.base.emitted(r_in, rec, u, v, p)
+    }
+}
+
+// Thin sheet that either diffusely reflects off its lit side or diffusely transmits to the other
+pub struct Translucent {
+    pub reflect: Color,
+    pub transmit: Color,
+    pub transmittance: f32,
+}
+
+impl Translucent {
+    pub fn new(reflect: Color, transmit: Color, transmittance: f32) -> Translucent {
+        Translucent {
+            reflect,
+            transmit,
+            transmittance: transmittance.clamp(0., 1.),
+        }
+    }
+}
+
+impl Material for Translucent {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let mut rng = rng();
+
+        let front = if r_in.direction().dot(rec.normal) < 0. {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+
+        // Picking each lobe with its own weight keeps the estimator equal to the lobe color
+        let (n, attenuation) = if rng.gen::<f32>() < self.transmittance {
+            (-front, self.transmit)
+        } else {
+            (front, self.reflect)
+        };
+
+        Some(Scatter {
+            specular_ray: Some(Ray::new(rec.p, CosinePDF::new(n).generate(), r_in.time())),
             attenuation,
             pdf: None,
         })
@@ -168,16 +462,30 @@ fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
     r0s + (1. - r0s) * (1. - cosine).powi(5)
 }
 
+// Emits `emit` scaled by `intensity` toward the side its surface normal points to, or toward
+// both sides when two-sided
 pub struct DiffuseLight {
     pub emit: Arc<dyn Texture>,
+    pub intensity: f32,
+    pub two_sided: bool,
 }
 
 impl DiffuseLight {
-    pub fn from_color(color: Color) -> DiffuseLight {
+    pub fn new(emit: Arc<dyn Texture>, intensity: f32) -> DiffuseLight {
         DiffuseLight {
-            emit: Arc::new(SolidColor::new(color)),
+            emit,
+            intensity,
+            two_sided: false,
         }
     }
+
+    pub fn from_color(color: Color) -> DiffuseLight {
+        DiffuseLight::new(Arc::new(SolidColor::new(color)), 1.)
+    }
+
+    pub fn set_two_sided(&mut self, two_sided: bool) {
+        self.two_sided = two_sided;
+    }
 }
 
 impl Material for DiffuseLight {
@@ -186,13 +494,48 @@ impl Material for DiffuseLight {
     }
 
     fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Vec3A) -> Color {
-        if r_in.direction().dot(rec.normal) < 0. {
-            return self.emit.value(u, v, p);
+        if self.two_sided || r_in.direction().dot(rec.normal) < 0. {
+            return self.emit.value(u, v, p) * self.intensity;
         }
         Color::new(0., 0., 0.)
     }
 }
 
+// Light emitted within a cone around `axis`: full intensity up to `inner_angle` degrees off the
+// axis, fading smoothly to nothing at `outer_angle`. Sampling still goes through the geometry it
+// is attached to, so it can be listed in the scene lights like a `DiffuseLight`.
+pub struct SpotLight {
+    pub emit: Arc<dyn Texture>,
+    pub axis: Vec3A,
+    cos_inner: f32,
+    cos_outer: f32,
+}
+
+impl SpotLight {
+    pub fn new(color: Color, axis: Vec3A, inner_angle: f32, outer_angle: f32) -> SpotLight {
+        SpotLight {
+            emit: Arc::new(SolidColor::new(color)),
+            axis: axis.normalize(),
+            cos_inner: inner_angle.to_radians().cos(),
+            cos_outer: outer_angle.max(inner_angle).to_radians().cos(),
+        }
+    }
+}
+
+impl Material for SpotLight {
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f32, v: f32, p: &Vec3A) -> Color {
+        if r_in.direction().dot(rec.normal) >= 0. {
+            return Color::new(0., 0., 0.);
+        }
+
+        // Smoothstep between the cosines of the outer and inner angles
+        let cosine = -self.axis.dot(r_in.direction().normalize());
+        let width = (self.cos_inner - self.cos_outer).max(1e-6);
+        let x = ((cosine - self.cos_outer) / width).clamp(0., 1.);
+        self.emit.value(u, v, p) * (x * x * (3. - 2. * x))
+    }
+}
+
 pub struct Isotropic {
     pub albedo: Arc<dyn Texture>,
 }
@@ -214,3 +557,227 @@ impl Material for Isotropic {
         })
     }
 }
+
+// Participating media scattering light forward (`g` > 0) or backward (`g` < 0) with a
+// Henyey-Greenstein phase function, like haze and smoke that glow around the lights behind them.
+// A `g` of 0 scatters evenly like `Isotropic`, and unlike it the lights are sampled.
+pub struct HenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Arc<dyn Texture>, g: f32) -> HenyeyGreenstein {
+        HenyeyGreenstein { albedo, g }
+    }
+
+    pub fn from_color(c: Color, g: f32) -> HenyeyGreenstein {
+        HenyeyGreenstein::new(Arc::new(SolidColor::new(c)), g)
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        Some(Scatter {
+            specular_ray: None,
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: Some(Arc::new(HenyeyGreensteinPDF::new(r_in.direction(), self.g))),
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, _rec: &HitRecord, scattered: &Ray) -> f32 {
+        HenyeyGreensteinPDF::new(r_in.direction(), self.g).value(scattered.direction())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3a;
+
+    use super::*;
+
+    // Record of a hit at the origin of a surface facing +y
+    fn hit(mat: Arc<dyn Material>) -> HitRecord {
+        HitRecord {
+            p: Vec3A::ZERO,
+            normal: Vec3A::Y,
+            t: 1.,
+            mat,
+            u: 0.,
+            v: 0.,
+        }
+    }
+
+    #[test]
+    fn fuzzy_metal_never_reflects_into_the_surface() {
+        for fuzziness in [0.1, 0.5, 1., 3.] {
+            let metal = Metal {
+                albedo: Color::ONE,
+                fuzziness,
+            };
+            let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+
+            // Down to grazing angles, where most perturbed directions go below the surface
+            for incoming in [vec3a(1., -1., 0.), vec3a(1., -0.05, 0.3)] {
+                let r_in = Ray::new(-incoming, incoming, 0.);
+                for _ in 0..1000 {
+                    if let Some(scatter) = metal.scatter(&r_in, &rec) {
+                        let direction = scatter.specular_ray.unwrap().direction();
+                        assert!(direction.dot(Vec3A::Y) > 0.);
+                        assert!((direction.length() - 1.).abs() < 1e-5);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn metal_without_fuzziness_is_a_mirror() {
+        let metal = Metal {
+            albedo: Color::ONE,
+            fuzziness: 0.,
+        };
+        let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+        let r_in = Ray::new(vec3a(-1., 1., 0.), vec3a(1., -1., 0.), 0.);
+
+        let direction = metal
+            .scatter(&r_in, &rec)
+            .unwrap()
+            .specular_ray
+            .unwrap()
+            .direction();
+        assert!((direction - vec3a(1., 1., 0.).normalize()).length() < 1e-6);
+    }
+
+    #[test]
+    fn dielectric_absorption_follows_beer_lambert() {
+        let absorption = Color::new(0.1, 0.5, 2.);
+        let glass = Dielectric::with_absorption(1.5, absorption);
+        let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+
+        // Leaving the glass after 2 units inside it, since the direction is 2 units long
+        let leaving = Ray::new(vec3a(0., -2., 0.), vec3a(0., 2., 0.), 0.);
+        let attenuation = glass.scatter(&leaving, &rec).unwrap().attenuation;
+        assert!((attenuation - (-2. * absorption).exp()).length() < 1e-6);
+
+        // Nothing is absorbed before entering it
+        let entering = Ray::new(vec3a(0., 2., 0.), vec3a(0., -2., 0.), 0.);
+        assert_eq!(
+            glass.scatter(&entering, &rec).unwrap().attenuation,
+            Color::ONE
+        );
+    }
+
+    #[test]
+    fn rough_dielectrics_spread_around_the_smooth_directions() {
+        crate::rng::set_seed(7);
+        let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+        let r_in = Ray::new(vec3a(-1., 1., 0.), vec3a(1., -1., 0.), 0.);
+        let directions = |roughness: f32| {
+            let mut glass = Dielectric::new(1.5);
+            glass.set_roughness(roughness);
+            (0..1000)
+                .filter_map(|_| glass.scatter(&r_in, &rec))
+                .map(|scatter| scatter.specular_ray.unwrap().direction().normalize())
+                .collect::<Vec<_>>()
+        };
+
+        // Smooth glass only reflects or refracts along one direction each
+        let smooth = directions(0.);
+        let reflected = vec3a(1., 1., 0.).normalize();
+        let refracted = *smooth.iter().find(|d| d.y < 0.).unwrap();
+        assert!(smooth
+            .iter()
+            .all(|&d| (d - reflected).length() < 1e-5 || (d - refracted).length() < 1e-5));
+
+        // Frosted glass scatters around them, on the same sides of the surface
+        let rough = directions(0.3);
+        let transmitted: Vec<Vec3A> = rough.iter().copied().filter(|d| d.y < 0.).collect();
+        assert!(transmitted.len() > 500);
+        assert!(transmitted.iter().any(|&d| (d - refracted).length() > 0.05));
+        let mean = transmitted.iter().sum::<Vec3A>().normalize();
+        assert!(mean.dot(refracted) > 0.95);
+    }
+
+    #[test]
+    fn rough_metal_lobes_widen_with_roughness() {
+        crate::rng::set_seed(8);
+        let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+        let r_in = Ray::new(vec3a(-1., 1., 0.), vec3a(1., -1., 0.), 0.);
+        let mirror = vec3a(1., 1., 0.).normalize();
+
+        // Average cosine between the sampled directions and the mirror reflection
+        let spread = |roughness: f32| {
+            let metal = RoughMetal::new(Color::ONE, roughness);
+            let pdf = metal.scatter(&r_in, &rec).unwrap().pdf.unwrap();
+            (0..10000)
+                .map(|_| pdf.generate().normalize().dot(mirror))
+                .sum::<f32>()
+                / 10000.
+        };
+
+        let (smooth, rough) = (spread(0.), spread(0.6));
+        assert!(smooth > 0.999, "{}", smooth);
+        assert!(rough < 0.95, "{}", rough);
+    }
+
+    #[test]
+    fn rough_diffuse_surfaces_scatter_back_toward_the_light() {
+        let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+        let r_in = Ray::new(vec3a(-1., 1., 0.), vec3a(1., -1., 0.), 0.);
+        let back = Ray::new(Vec3A::ZERO, vec3a(-1., 1., 0.), 0.);
+        let forward = Ray::new(Vec3A::ZERO, vec3a(1., 1., 0.), 0.);
+        let albedo: Arc<dyn Texture> = Arc::new(SolidColor::new(Color::ONE));
+
+        // A roughness of 0 is Lambertian
+        let smooth = OrenNayar::new(albedo.clone(), 0.);
+        let lambertian = Lambertian::new(albedo.clone());
+        for scattered in [&back, &forward] {
+            let expected = lambertian.scattering_pdf(&r_in, &rec, scattered);
+            assert!((smooth.scattering_pdf(&r_in, &rec, scattered) - expected).abs() < 1e-6);
+        }
+
+        let rough = OrenNayar::new(albedo, 0.5);
+        assert!(
+            rough.scattering_pdf(&r_in, &rec, &back) > rough.scattering_pdf(&r_in, &rec, &forward)
+        );
+    }
+
+    #[test]
+    fn coats_reflect_with_the_fresnel_odds() {
+        crate::rng::set_seed(9);
+        let rec = hit(Arc::new(Lambertian::from_color(Color::ONE)));
+        let coated = Coated::new(Arc::new(Lambertian::from_color(Color::ONE)), 1.5);
+
+        for incoming in [vec3a(0., -1., 0.), vec3a(1., -0.2, 0.)] {
+            let r_in = Ray::new(-incoming, incoming, 0.);
+            let n = 20000;
+            let reflected = (0..n)
+                .filter(|_| coated.scatter(&r_in, &rec).unwrap().specular_ray.is_some())
+                .count();
+            let expected = reflectance(incoming.normalize().y.abs(), 1.5);
+            let fraction = reflected as f32 / n as f32;
+            assert!(
+                (fraction - expected).abs() < 0.01,
+                "{} {}",
+                fraction,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_scatters_with_an_average_cosine_of_g() {
+        crate::rng::set_seed(11);
+        let r_in = Ray::new(Vec3A::ZERO, vec3a(0., 0., 2.), 0.);
+        for g in [-0.4, 0., 0.6] {
+            let phase = HenyeyGreenstein::from_color(Color::ONE, g);
+            let rec = hit(Arc::new(Isotropic::from_color(Color::ONE)));
+            let pdf = phase.scatter(&r_in, &rec).unwrap().pdf.unwrap();
+
+            let n = 50000;
+            let mean = (0..n).map(|_| pdf.generate().normalize().z).sum::<f32>() / n as f32;
+            assert!((mean - g).abs() < 0.02, "{} {}", g, mean);
+        }
+    }
+}
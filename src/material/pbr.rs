@@ -1,9 +1,196 @@
-use glam::Vec3A;
-
-pub struct Hit {
-    pub point: Vec3A,
-    pub normal: Vec3A,
-    pub t: f32,
-    pub u: f32,
-    pub v: f32,
+use std::{f32::consts::PI, sync::Arc};
+
+use glam::{vec3a, Vec3A};
+use rand::Rng;
+
+use crate::{
+    pdf::{ggx_d, smith_g1, CosinePDF, PDF},
+    ray::Ray,
+    rng::rng,
+    vec3::{Color, OrthNormBasis},
+};
+
+use super::{reflect, texture::Texture, HitRecord, Material, Scatter};
+
+// Below this GGX alpha the specular lobe is too sharp to be sampled reliably
+pub(super) const MIN_ALPHA: f32 = 1e-3;
+
+// Specular reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: f32 = 0.04;
+
+// glTF metallic-roughness material: a Cook-Torrance GGX specular lobe over a Lambertian diffuse
+// lobe, blended toward a colored conductor by `metallic`
+pub struct Pbr {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+impl Pbr {
+    pub fn new(base_color: Arc<dyn Texture>, metallic: f32, roughness: f32) -> Pbr {
+        Pbr {
+            base_color,
+            metallic: metallic.clamp(0., 1.),
+            roughness: roughness.clamp(0., 1.),
+        }
+    }
+
+    fn alpha(&self) -> f32 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    fn specular_probability(&self) -> f32 {
+        0.5 + 0.5 * self.metallic
+    }
+
+    fn is_mirror(&self) -> bool {
+        self.metallic >= 1. && self.roughness * self.roughness < MIN_ALPHA
+    }
+}
+
+// Shading normal facing the incoming ray, and the unit direction back toward it
+pub(super) fn shading_frame(r_in: &Ray, rec: &HitRecord) -> (Vec3A, Vec3A) {
+    let wo = -r_in.direction().normalize();
+    let n = if wo.dot(rec.normal) < 0. {
+        -rec.normal
+    } else {
+        rec.normal
+    };
+    (n, wo)
+}
+
+impl Material for Pbr {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Scatter> {
+        let base_color = self.base_color.value(rec.u, rec.v, &rec.p);
+        let (n, wo) = shading_frame(r_in, rec);
+
+        // A smooth conductor is a perfect mirror tinted by its Fresnel reflectance
+        if self.is_mirror() {
+            let reflected = reflect(-wo, n);
+            return Some(Scatter {
+                specular_ray: Some(Ray::new(rec.p, reflected, r_in.time())),
+                attenuation: fresnel_schlick(base_color, wo.dot(n)),
+                pdf: None,
+            });
+        }
+
+        Some(Scatter {
+            specular_ray: None,
+            attenuation: base_color,
+            pdf: Some(Arc::new(PbrPDF::new(
+                n,
+                wo,
+                self.alpha(),
+                self.specular_probability(),
+            ))),
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f32 {
+        let (n, wo) = shading_frame(r_in, rec);
+        PbrPDF::new(n, wo, self.alpha(), self.specular_probability()).value(scattered.direction())
+    }
+
+    fn scattering_color(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scattered: &Ray,
+        base_color: Color,
+    ) -> Color {
+        let (n, wo) = shading_frame(r_in, rec);
+        let wi = scattered.direction().normalize();
+
+        let n_dot_wi = n.dot(wi);
+        let n_dot_wo = n.dot(wo);
+        if n_dot_wi <= 0. || n_dot_wo <= 0. {
+            return Color::ZERO;
+        }
+
+        let h = (wi + wo).normalize();
+        let alpha = self.alpha();
+
+        let f0 = Color::splat(DIELECTRIC_F0).lerp(base_color, self.metallic);
+        let fresnel = fresnel_schlick(f0, wi.dot(h));
+
+        let specular = fresnel
+            * ggx_d(n.dot(h), alpha)
+            * smith_g1(n_dot_wi, alpha)
+            * smith_g1(n_dot_wo, alpha)
+            / (4. * n_dot_wi * n_dot_wo);
+        let diffuse = (Color::ONE - fresnel) * (1. - self.metallic) * base_color / PI;
+
+        (specular + diffuse) * n_dot_wi
+    }
+}
+
+// Samples half vectors from the GGX distribution for the specular lobe, mixed with a cosine
+// lobe for the diffuse part
+pub struct PbrPDF {
+    uvw: OrthNormBasis,
+    wo: Vec3A,
+    alpha: f32,
+    specular_probability: f32,
+}
+
+impl PbrPDF {
+    pub fn new(n: Vec3A, wo: Vec3A, alpha: f32, specular_probability: f32) -> Self {
+        Self {
+            uvw: OrthNormBasis::from_w(n),
+            wo,
+            alpha,
+            specular_probability,
+        }
+    }
+}
+
+impl PDF for PbrPDF {
+    fn value(&self, direction: Vec3A) -> f32 {
+        let wi = direction.normalize();
+        let diffuse = (wi.dot(self.uvw.w) / PI).max(0.);
+
+        let h = (wi + self.wo).normalize();
+        let wo_dot_h = self.wo.dot(h);
+        let specular = if wo_dot_h > 0. {
+            let n_dot_h = self.uvw.w.dot(h);
+            ggx_d(n_dot_h, self.alpha) * n_dot_h / (4. * wo_dot_h)
+        } else {
+            0.
+        };
+
+        self.specular_probability * specular + (1. - self.specular_probability) * diffuse
+    }
+
+    fn generate(&self) -> Vec3A {
+        let mut rng = rng();
+
+        if rng.gen::<f32>() >= self.specular_probability {
+            return CosinePDF::new(self.uvw.w).generate();
+        }
+
+        let h = sample_ggx_normal(&self.uvw, self.alpha);
+        reflect(-self.wo, h)
+    }
+}
+
+// Samples a microfacet normal around `uvw.w` from the GGX distribution
+pub(super) fn sample_ggx_normal(uvw: &OrthNormBasis, alpha: f32) -> Vec3A {
+    let mut rng = rng();
+    let r1: f32 = rng.gen();
+    let r2: f32 = rng.gen();
+    let a2 = alpha * alpha;
+
+    let cos_theta = ((1. - r1) / (1. + (a2 - 1.) * r1)).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * r2;
+
+    uvw.local(vec3a(
+        phi.cos() * sin_theta,
+        phi.sin() * sin_theta,
+        cos_theta,
+    ))
+}
+
+pub(super) fn fresnel_schlick(f0: Color, cosine: f32) -> Color {
+    f0 + (Color::ONE - f0) * (1. - cosine.clamp(0., 1.)).powi(5)
 }
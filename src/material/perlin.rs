@@ -1,7 +1,7 @@
 use glam::{vec3a, Vec3A};
-use rand::{prelude::SliceRandom, thread_rng};
+use rand::prelude::SliceRandom;
 
-use crate::vec3::random_vector;
+use crate::{rng::rng, vec3::random_vector};
 
 pub struct Perlin<const N: usize> {
     ranfloat: [Vec3A; N],
@@ -71,7 +71,7 @@ impl<const N: usize> Perlin<N> {
 }
 
 fn generate_perm<const N: usize>() -> [usize; N] {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     let mut p: [usize; N] = [0; N];
 
     for (el, i) in p.iter_mut().zip(0..N) {
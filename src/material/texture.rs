@@ -1,10 +1,15 @@
 use std::sync::Arc;
 
-use glam::Vec3A;
+use glam::{vec2, vec3a, Vec2, Vec3A};
 use image::io::Reader as ImageReader;
-use image::{GenericImageView, Pixel, RgbImage};
+use image::{DynamicImage, GenericImageView, Pixel, RgbImage};
+use rand::Rng;
 
-use crate::vec3::Color;
+use crate::{
+    rng::rng,
+    sampler::{hash, to_unit_float},
+    vec3::{srgb_to_linear, Color},
+};
 
 use super::perlin::Perlin;
 
@@ -28,38 +33,122 @@ impl Texture for SolidColor {
     }
 }
 
+// Space in which the checkers are laid out, `scale` being the number of squares per unit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckerMapping {
+    // Solid 3D checkers, which distort on curved surfaces
+    World { scale: f32 },
+    // Squares following the surface parameterization
+    UV { scale: f32 },
+}
+
 pub struct Checker {
     even: Arc<dyn Texture>,
     odd: Arc<dyn Texture>,
+    pub mapping: CheckerMapping,
 }
 
 impl Checker {
     pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Checker {
-        Checker { even, odd }
+        Checker {
+            even,
+            odd,
+            mapping: CheckerMapping::World { scale: 10. },
+        }
     }
 
     pub fn from_colors(c1: Color, c2: Color) -> Checker {
-        Checker {
-            even: Arc::new(SolidColor::new(c1)),
-            odd: Arc::new(SolidColor::new(c2)),
-        }
+        Checker::new(Arc::new(SolidColor::new(c1)), Arc::new(SolidColor::new(c2)))
+    }
+
+    pub fn set_mapping(&mut self, mapping: CheckerMapping) {
+        self.mapping = mapping;
     }
 }
 
 impl Texture for Checker {
     fn value(&self, u: f32, v: f32, p: &Vec3A) -> Color {
-        let sines = (10. * p[0]).sin() * (10. * p[1]).sin() * (10. * p[2]).sin();
+        let odd = match self.mapping {
+            CheckerMapping::World { scale } => {
+                let sines = (scale * p[0]).sin() * (scale * p[1]).sin() * (scale * p[2]).sin();
+                sines < 0.
+            }
+            CheckerMapping::UV { scale } => {
+                let parity = (u * scale).floor() as i64 + (v * scale).floor() as i64;
+                parity.rem_euclid(2) == 1
+            }
+        };
 
-        if sines < 0. {
+        if odd {
             return self.odd.value(u, v, p);
         }
         self.even.value(u, v, p)
     }
 }
 
+// Texture coordinate along which a `Gradient` varies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientAxis {
+    U,
+    V,
+}
+
+// Color ramp through `(stop, color)` control points, interpolated linearly between the stops
+// and holding the first and last colors outside of them
+pub struct Gradient {
+    stops: Vec<(f32, Color)>,
+    pub axis: GradientAxis,
+}
+
+impl Gradient {
+    pub fn new(mut stops: Vec<(f32, Color)>) -> Gradient {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Gradient {
+            stops,
+            axis: GradientAxis::V,
+        }
+    }
+
+    pub fn set_axis(&mut self, axis: GradientAxis) {
+        self.axis = axis;
+    }
+}
+
+impl Texture for Gradient {
+    fn value(&self, u: f32, v: f32, _p: &Vec3A) -> Color {
+        let t = match self.axis {
+            GradientAxis::U => u,
+            GradientAxis::V => v,
+        };
+
+        // Index of the first stop after t
+        let next = self.stops.partition_point(|&(stop, _)| stop <= t);
+        match (self.stops.get(next.wrapping_sub(1)), self.stops.get(next)) {
+            (Some(&(stop0, color0)), Some(&(stop1, color1))) => {
+                color0.lerp(color1, (t - stop0) / (stop1 - stop0))
+            }
+            (Some(&(_, color)), None) | (None, Some(&(_, color))) => color,
+            (None, None) => Color::ZERO,
+        }
+    }
+}
+
+// Pattern drawn by a `Noise` texture, `scale` setting its frequency
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseStyle {
+    // Sum of noise octaves, as in clouds or smoke
+    Turbulence,
+    // Stripes along z bent by turbulence
+    Marble,
+    // Rings around the y axis, perturbed by turbulence
+    Wood,
+}
+
 pub struct Noise {
     noise: Perlin<256>,
     scale: f32,
+    pub style: NoiseStyle,
 }
 
 impl Noise {
@@ -67,35 +156,214 @@ impl Noise {
         Noise {
             noise: Perlin::new(),
             scale,
+            style: NoiseStyle::Marble,
         }
     }
+
+    pub fn set_style(&mut self, style: NoiseStyle) {
+        self.style = style;
+    }
 }
 
 impl Texture for Noise {
     fn value(&self, _u: f32, _v: f32, p: &Vec3A) -> Color {
-        // Color::new(1., 1., 1.) * 0.5 * (1. + self.noise.noise(self.scale * *p))
-        // Color::new(1., 1., 1.) * self.noise.turb(self.scale * *p, 4)
-        Color::new(1., 1., 1.)
-            * 0.5
-            * (1. + (self.scale * p[2] + 10. * self.noise.turb(*p, 7)).sin())
+        match self.style {
+            NoiseStyle::Turbulence => Color::new(1., 1., 1.) * self.noise.turb(self.scale * *p, 7),
+            NoiseStyle::Marble => {
+                Color::new(1., 1., 1.)
+                    * 0.5
+                    * (1. + (self.scale * p[2] + 10. * self.noise.turb(*p, 7)).sin())
+            }
+            NoiseStyle::Wood => {
+                let radius = (p[0] * p[0] + p[2] * p[2]).sqrt();
+                let rings = (self.scale * radius + 2. * self.noise.turb(*p, 7)).fract();
+                Color::new(0.36, 0.2, 0.09).lerp(Color::new(0.8, 0.6, 0.38), rings)
+            }
+        }
+    }
+}
+
+// Distance used between a point and the feature points of a `Worley` texture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorleyMetric {
+    Euclidean,
+    Manhattan,
+}
+
+// What a `Worley` texture shows: the distance to the nearest feature point (F1), which gives
+// round cells, or its difference with the distance to the second nearest (F2 - F1), which is
+// zero along the edges between cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorleyFeature {
+    F1,
+    F2MinusF1,
+}
+
+// Cellular noise with one feature point at a random position in each cell of a grid, `scale`
+// being the number of cells per unit
+pub struct Worley {
+    scale: f32,
+    seed: u32,
+    pub metric: WorleyMetric,
+    pub feature: WorleyFeature,
+}
+
+impl Worley {
+    pub fn new(scale: f32) -> Worley {
+        Worley {
+            scale,
+            seed: rng().gen(),
+            metric: WorleyMetric::Euclidean,
+            feature: WorleyFeature::F1,
+        }
+    }
+
+    pub fn set_metric(&mut self, metric: WorleyMetric) {
+        self.metric = metric;
+    }
+
+    pub fn set_feature(&mut self, feature: WorleyFeature) {
+        self.feature = feature;
+    }
+
+    fn feature_point(&self, cell: Vec3A) -> Vec3A {
+        let h = hash(
+            self.seed
+                ^ hash(
+                    cell.x as i32 as u32 ^ hash(cell.y as i32 as u32 ^ hash(cell.z as i32 as u32)),
+                ),
+        );
+        cell + vec3a(
+            to_unit_float(hash(h)),
+            to_unit_float(hash(h ^ 1)),
+            to_unit_float(hash(h ^ 2)),
+        )
+    }
+}
+
+impl Texture for Worley {
+    fn value(&self, _u: f32, _v: f32, p: &Vec3A) -> Color {
+        let p = self.scale * *p;
+        let cell = p.floor();
+
+        // The two nearest feature points are always in the 3x3x3 block of cells around p
+        let (mut f1, mut f2) = (f32::INFINITY, f32::INFINITY);
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let offset = vec3a(i as f32, j as f32, k as f32);
+                    let d = self.feature_point(cell + offset) - p;
+                    let distance = match self.metric {
+                        WorleyMetric::Euclidean => d.length(),
+                        WorleyMetric::Manhattan => d.abs().dot(Vec3A::ONE),
+                    };
+
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+
+        let value = match self.feature {
+            WorleyFeature::F1 => f1,
+            WorleyFeature::F2MinusF1 => f2 - f1,
+        };
+        Color::splat(value.min(1.))
     }
 }
 
+// Scales, rotates by `rotation` degrees and then offsets the texture coordinates before looking
+// up `base`, so that one texture can be tiled or turned differently on several surfaces
+pub struct TextureTransform {
+    pub base: Arc<dyn Texture>,
+    pub scale: Vec2,
+    pub offset: Vec2,
+    pub rotation: f32,
+}
+
+impl TextureTransform {
+    pub fn new(base: Arc<dyn Texture>) -> TextureTransform {
+        TextureTransform {
+            base,
+            scale: Vec2::ONE,
+            offset: Vec2::ZERO,
+            rotation: 0.,
+        }
+    }
+}
+
+impl Texture for TextureTransform {
+    fn value(&self, u: f32, v: f32, p: &Vec3A) -> Color {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let uv = self.scale * vec2(u, v);
+        let uv = vec2(cos * uv.x - sin * uv.y, sin * uv.x + cos * uv.y) + self.offset;
+        self.base.value(uv.x, uv.y, p)
+    }
+}
+
+// Base texture multiplied by a color, e.g. a glTF base color texture by its base color factor
+pub struct TintedTexture {
+    pub base: Arc<dyn Texture>,
+    pub tint: Color,
+}
+
+impl TintedTexture {
+    pub fn new(base: Arc<dyn Texture>, tint: Color) -> TintedTexture {
+        TintedTexture { base, tint }
+    }
+}
+
+impl Texture for TintedTexture {
+    fn value(&self, u: f32, v: f32, p: &Vec3A) -> Color {
+        self.tint * self.base.value(u, v, p)
+    }
+}
+
+// How texture coordinates outside of [0, 1] are brought back into the image
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+impl WrapMode {
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            WrapMode::Clamp => t.clamp(0., 1.),
+            WrapMode::Repeat => t.rem_euclid(1.),
+            WrapMode::Mirror => {
+                let t = t.rem_euclid(2.);
+                if t > 1. {
+                    2. - t
+                } else {
+                    t
+                }
+            }
+        }
+    }
+}
+
+// Images whose pixels are sRGB encoded, as color textures usually are, are decoded to linear
+// values when `srgb` is set
 pub struct ImageTexture {
     data: Option<RgbImage>,
     width: u32,
     height: u32,
+    pub wrap: WrapMode,
+    pub srgb: bool,
 }
 
 impl ImageTexture {
     pub fn from_file(path: &str) -> ImageTexture {
         if let Ok(reader) = ImageReader::open(path) {
             if let Ok(img) = reader.decode() {
-                return ImageTexture {
-                    data: Some(img.to_rgb8()),
-                    width: img.width(),
-                    height: img.height(),
-                };
+                return ImageTexture::from_image(&img);
             }
         }
 
@@ -103,15 +371,36 @@ impl ImageTexture {
             data: None,
             width: 0,
             height: 0,
+            wrap: WrapMode::Clamp,
+            srgb: false,
+        }
+    }
+
+    pub fn from_image(img: &DynamicImage) -> ImageTexture {
+        ImageTexture {
+            data: Some(img.to_rgb8()),
+            width: img.width(),
+            height: img.height(),
+            wrap: WrapMode::Clamp,
+            srgb: false,
         }
     }
+
+    #[allow(unused)]
+    pub fn set_wrap(&mut self, wrap: WrapMode) {
+        self.wrap = wrap;
+    }
+
+    pub fn set_srgb(&mut self, srgb: bool) {
+        self.srgb = srgb;
+    }
 }
 
 impl Texture for ImageTexture {
     fn value(&self, u: f32, v: f32, _p: &Vec3A) -> Color {
         if let Some(data) = &self.data {
-            let cu = u.clamp(0., 1.);
-            let cv = 1. - v.clamp(0., 1.);
+            let cu = self.wrap.apply(u);
+            let cv = 1. - self.wrap.apply(v);
 
             let x = ((cu * self.width as f32) as u32).clamp(0, self.width - 1);
             let y = ((cv * self.height as f32) as u32).clamp(0, self.height - 1);
@@ -119,13 +408,144 @@ impl Texture for ImageTexture {
             let color_scale = 1. / 255.;
 
             let pixel = data.get_pixel(x, y).channels();
+            let channel = |c: u8| {
+                if self.srgb {
+                    srgb_to_linear(color_scale * c as f32)
+                } else {
+                    color_scale * c as f32
+                }
+            };
 
-            return Color::new(
-                color_scale * pixel[0] as f32,
-                color_scale * pixel[1] as f32,
-                color_scale * pixel[2] as f32,
-            );
+            return Color::new(channel(pixel[0]), channel(pixel[1]), channel(pixel[2]));
         }
         Color::new(1., 1., 0.)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    #[test]
+    fn solid_color_is_the_same_everywhere() {
+        let texture = SolidColor::new(Color::new(0.1, 0.2, 0.3));
+
+        for (u, v, p) in [(0., 0., Vec3A::ZERO), (0.7, 0.2, vec3a(5., -3., 8.))] {
+            assert_eq!(texture.value(u, v, &p), Color::new(0.1, 0.2, 0.3));
+        }
+    }
+
+    #[test]
+    fn checker_alternates_between_its_textures() {
+        let (even, odd) = (Color::ONE, Color::ZERO);
+        let mut checker = Checker::from_colors(even, odd);
+
+        // Solid checkers flip across the planes where one of the sines changes sign
+        let p = vec3a(0.05, 0.05, 0.05);
+        assert_eq!(checker.value(0., 0., &p), even);
+        assert_eq!(checker.value(0., 0., &(p * vec3a(-1., 1., 1.))), odd);
+
+        checker.set_mapping(CheckerMapping::UV { scale: 4. });
+        assert_eq!(checker.value(0.1, 0.1, &p), even);
+        assert_eq!(checker.value(0.3, 0.1, &p), odd);
+        assert_eq!(checker.value(0.3, 0.3, &p), even);
+        assert_eq!(checker.value(-0.1, 0.1, &p), odd);
+    }
+
+    #[test]
+    fn image_texture_decodes_srgb_and_wraps() {
+        // Left pixel mid grey in sRGB, right pixel white
+        let img = RgbImage::from_fn(
+            2,
+            1,
+            |x, _| if x == 0 { Rgb([188; 3]) } else { Rgb([255; 3]) },
+        );
+        let mut texture = ImageTexture::from_image(&DynamicImage::ImageRgb8(img));
+
+        assert!((texture.value(0.25, 0.5, &Vec3A::ZERO).x - 188. / 255.).abs() < 1e-6);
+        texture.set_srgb(true);
+        assert!((texture.value(0.25, 0.5, &Vec3A::ZERO).x - 0.5).abs() < 1e-2);
+
+        // Clamped by default, so past the right edge is the right pixel
+        assert_eq!(texture.value(1.25, 0.5, &Vec3A::ZERO), Color::ONE);
+        texture.set_wrap(WrapMode::Repeat);
+        assert!((texture.value(1.25, 0.5, &Vec3A::ZERO).x - 0.5).abs() < 1e-2);
+        texture.set_wrap(WrapMode::Mirror);
+        assert_eq!(texture.value(1.25, 0.5, &Vec3A::ZERO), Color::ONE);
+    }
+
+    #[test]
+    fn gradient_interpolates_between_its_stops() {
+        let (red, blue) = (Color::new(1., 0., 0.), Color::new(0., 0., 1.));
+        // Stops are sorted whatever their order
+        let mut gradient = Gradient::new(vec![(0.75, blue), (0.25, red)]);
+
+        assert_eq!(gradient.value(0., 0.5, &Vec3A::ZERO), red.lerp(blue, 0.5));
+        assert_eq!(gradient.value(0., 0., &Vec3A::ZERO), red);
+        assert_eq!(gradient.value(0., 1., &Vec3A::ZERO), blue);
+
+        gradient.set_axis(GradientAxis::U);
+        assert_eq!(
+            gradient.value(0.625, 0., &Vec3A::ZERO),
+            red.lerp(blue, 0.75)
+        );
+        assert_eq!(
+            Gradient::new(vec![]).value(0.5, 0.5, &Vec3A::ZERO),
+            Color::ZERO
+        );
+    }
+
+    #[test]
+    fn texture_transforms_map_uvs_before_the_lookup() {
+        let ramp: Arc<dyn Texture> =
+            Arc::new(Gradient::new(vec![(0., Color::ZERO), (1., Color::ONE)]));
+        let mut transform = TextureTransform::new(ramp);
+        let value =
+            |transform: &TextureTransform, u: f32, v: f32| transform.value(u, v, &Vec3A::ZERO).x;
+
+        transform.scale = vec2(1., 2.);
+        transform.offset = vec2(0., 0.25);
+        assert!((value(&transform, 0., 0.25) - 0.75).abs() < 1e-6);
+
+        // A quarter turn maps u onto v
+        transform.scale = Vec2::ONE;
+        transform.offset = Vec2::ZERO;
+        transform.rotation = 90.;
+        assert!((value(&transform, 0.4, 0.) - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn identity_transforms_leave_textures_unchanged() {
+        for axis in [GradientAxis::U, GradientAxis::V] {
+            let mut ramp = Gradient::new(vec![(0., Color::ZERO), (1., Color::new(1., 0.5, 0.25))]);
+            ramp.set_axis(axis);
+            let ramp: Arc<dyn Texture> = Arc::new(ramp);
+            let transform = TextureTransform::new(ramp.clone());
+
+            for (u, v) in [(0., 0.), (0.3, 0.8), (0.75, 0.1), (1., 1.)] {
+                let p = vec3a(u, v, 1.);
+                assert_eq!(transform.value(u, v, &p), ramp.value(u, v, &p));
+            }
+        }
+    }
+
+    #[test]
+    fn worley_noise_vanishes_at_its_feature_points() {
+        let mut worley = Worley::new(2.);
+        // Somewhere in the cell [1, 1.5) x [0, 0.5) x [-0.5, 0)
+        let p = worley.feature_point(vec3a(2., 0., -1.)) / 2.;
+
+        assert!(worley.value(0., 0., &p).x < 1e-6);
+        worley.set_feature(WorleyFeature::F2MinusF1);
+        assert!(worley.value(0., 0., &p).x > 0.);
+
+        // Manhattan distances are never shorter than Euclidean ones
+        worley.set_feature(WorleyFeature::F1);
+        let q = vec3a(0.3, 0.7, -1.2);
+        let euclidean = worley.value(0., 0., &q);
+        worley.set_metric(WorleyMetric::Manhattan);
+        assert!(worley.value(0., 0., &q).x >= euclidean.x);
+    }
+}
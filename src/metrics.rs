@@ -0,0 +1,102 @@
+use std::{error::Error, fmt, fs::File, io::BufReader, path::Path};
+
+use glam::vec3a;
+use image::codecs::hdr::HdrDecoder;
+
+use crate::vec3::{decode_color, Color};
+
+pub struct ErrorMetrics {
+    pub mse: f32,
+    pub rmse: f32,
+    pub psnr: f32,
+    pub rel_mse: f32,
+}
+
+impl fmt::Display for ErrorMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MSE: {:.6} | RMSE: {:.6} | PSNR: {:.2} dB | relMSE: {:.6}",
+            self.mse, self.rmse, self.psnr, self.rel_mse
+        )
+    }
+}
+
+/// Compares two linear radiance buffers of the same size, channel by channel.
+pub fn compute_metrics(image: &[Color], reference: &[Color]) -> ErrorMetrics {
+    let mut squared_error: f64 = 0.;
+    let mut relative_error: f64 = 0.;
+
+    for (a, b) in image.iter().zip(reference.iter()) {
+        let diff = *a - *b;
+        let diff_squared = diff * diff;
+        squared_error += (diff_squared.x + diff_squared.y + diff_squared.z) as f64;
+
+        // Small epsilon so that black reference pixels don't dominate the relative error
+        let rel = diff_squared / (*b * *b + Color::splat(1e-2));
+        relative_error += (rel.x + rel.y + rel.z) as f64;
+    }
+
+    let count = 3. * image.len().max(1) as f64;
+    let mse = (squared_error / count) as f32;
+
+    ErrorMetrics {
+        mse,
+        rmse: mse.sqrt(),
+        psnr: -10. * mse.log10(),
+        rel_mse: (relative_error / count) as f32,
+    }
+}
+
+/// Loads a reference image as a linear buffer. Radiance `.hdr` files are read as-is, other
+/// formats are assumed to be encoded like our own 8-bit output.
+pub fn load_reference<P: AsRef<Path>>(path: P) -> Result<(u32, u32, Vec<Color>), Box<dyn Error>> {
+    let path = path.as_ref();
+
+    if path.extension().is_some_and(|ext| ext == "hdr") {
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+        let metadata = decoder.metadata();
+        let data = decoder
+            .read_image_hdr()?
+            .iter()
+            .map(|p| vec3a(p[0], p[1], p[2]))
+            .collect();
+
+        return Ok((metadata.width, metadata.height, data));
+    }
+
+    let img = image::open(path)?.to_rgb8();
+    let data = img.pixels().map(|p| decode_color(*p)).collect();
+
+    Ok((img.width(), img.height(), data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images_have_no_error() {
+        let image: Vec<Color> = (0..16)
+            .map(|i| Color::new(i as f32 / 16., 0.5, 2.))
+            .collect();
+
+        let metrics = compute_metrics(&image, &image);
+        assert_eq!(metrics.mse, 0.);
+        assert_eq!(metrics.rmse, 0.);
+        assert_eq!(metrics.rel_mse, 0.);
+        assert_eq!(metrics.psnr, f32::INFINITY);
+    }
+
+    #[test]
+    fn error_is_averaged_over_channels() {
+        let image = [Color::new(1., 0., 0.), Color::ZERO];
+        let reference = [Color::ZERO, Color::ZERO];
+
+        // One channel of the six is off by 1
+        let metrics = compute_metrics(&image, &reference);
+        assert!((metrics.mse - 1. / 6.).abs() < 1e-6);
+        assert!((metrics.rmse - (1f32 / 6.).sqrt()).abs() < 1e-6);
+        assert!((metrics.psnr - 10. * 6f32.log10()).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::HashMap, error::Error, fs::read_to_string, path::Path, sync::Arc};
+
+use glam::{vec2, vec3a, Vec2, Vec3A};
+
+use crate::{
+    geometry::triangle_mesh::TriangleMesh,
+    material::{Lambertian, Material},
+    scene::Scene,
+};
+
+// Position, texture coordinates and normal indices of a face vertex, from 0
+type ObjVertex = (usize, Option<usize>, Option<usize>);
+
+// OBJ indices start at 1, and negative ones count back from the last element read so far
+fn parse_index(token: &str, count: usize) -> Result<usize, String> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| format!("invalid index `{}`", token))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index `{}` is out of range", token));
+    }
+    Ok(resolved as usize)
+}
+
+// `v`, `v/vt`, `v//vn` or `v/vt/vn`
+fn parse_face_vertex(
+    token: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<ObjVertex, String> {
+    let mut parts = token.split('/');
+    let position = parse_index(parts.next().unwrap_or_default(), positions)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(parse_index(index, uvs)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(parse_index(index, normals)?),
+    };
+    Ok((position, uv, normal))
+}
+
+fn parse_floats<const N: usize>(tokens: &[&str]) -> Result<[f32; N], String> {
+    let mut out = [0.; N];
+    for (i, value) in out.iter_mut().enumerate() {
+        let token = tokens.get(i).ok_or("missing coordinate")?;
+        *value = token
+            .parse()
+            .map_err(|_| format!("invalid coordinate `{}`", token))?;
+    }
+    Ok(out)
+}
+
+// `u [v [w]]`, `v` defaulting to 0 and `w` being of no use to 2D textures
+fn parse_uv(tokens: &[&str]) -> Result<Vec2, String> {
+    match tokens.len() {
+        0 | 1 => parse_floats::<1>(tokens).map(|[u]| vec2(u, 0.)),
+        2 => parse_floats::<2>(tokens).map(|[u, v]| vec2(u, v)),
+        _ => parse_floats::<3>(tokens).map(|[u, v, _]| vec2(u, v)),
+    }
+}
+
+/// Parses the `v`, `vt`, `vn` and `f` statements of a Wavefront OBJ file into a single triangle
+/// mesh, triangulating polygons as fans. Normals and texture coordinates are only kept when every
+/// face vertex has them. Objects, groups and materials are ignored.
+pub fn parse_obj(source: &str, material: Arc<dyn Material>) -> Result<TriangleMesh, String> {
+    let mut positions: Vec<Vec3A> = Vec::new();
+    let mut uvs: Vec<Vec2> = Vec::new();
+    let mut normals: Vec<Vec3A> = Vec::new();
+    let mut faces: Vec<Vec<ObjVertex>> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let result = match tokens.split_first() {
+            Some((&"v", args)) => parse_floats::<3>(args).map(|[x, y, z]| {
+                positions.push(vec3a(x, y, z));
+            }),
+            Some((&"vt", args)) => parse_uv(args).map(|uv| uvs.push(uv)),
+            Some((&"vn", args)) => parse_floats::<3>(args).map(|[x, y, z]| {
+                normals.push(vec3a(x, y, z).normalize_or_zero());
+            }),
+            Some((&"f", args)) if args.len() < 3 => {
+                Err("faces need at least 3 vertices".to_string())
+            }
+            Some((&"f", args)) => args
+                .iter()
+                .map(|token| parse_face_vertex(token, positions.len(), uvs.len(), normals.len()))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|face| faces.push(face)),
+            _ => Ok(()),
+        };
+        result.map_err(|err| format!("line {}: {}", i + 1, err))?;
+    }
+
+    let face_vertices = || faces.iter().flatten();
+    let has_uvs = face_vertices().all(|(_, uv, _)| uv.is_some());
+    let has_normals = face_vertices().all(|(_, _, normal)| normal.is_some());
+
+    // The mesh has a single index per vertex, so each distinct combination of OBJ indices becomes
+    // a vertex of its own
+    let mut vertex_indices: HashMap<ObjVertex, usize> = HashMap::new();
+    let mut mesh_positions: Vec<Vec3A> = Vec::new();
+    let mut mesh_uvs: Vec<Vec2> = Vec::new();
+    let mut mesh_normals: Vec<Vec3A> = Vec::new();
+    let mut triangles: Vec<[usize; 3]> = Vec::new();
+
+    for face in faces.iter() {
+        let indices: Vec<usize> = face
+            .iter()
+            .map(|&vertex| {
+                *vertex_indices.entry(vertex).or_insert_with(|| {
+                    let (position, uv, normal) = vertex;
+                    mesh_positions.push(positions[position]);
+                    if has_uvs {
+                        mesh_uvs.push(uvs[uv.unwrap()]);
+                    }
+                    if has_normals {
+                        mesh_normals.push(normals[normal.unwrap()]);
+                    }
+                    mesh_positions.len() - 1
+                })
+            })
+            .collect();
+
+        for k in 1..indices.len() - 1 {
+            triangles.push([indices[0], indices[k], indices[k + 1]]);
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err("no faces".to_string());
+    }
+
+    Ok(TriangleMesh::new(
+        mesh_positions,
+        has_normals.then_some(mesh_normals),
+        has_uvs.then_some(mesh_uvs),
+        triangles,
+        material,
+    ))
+}
+
+impl Scene {
+    /// Loads a Wavefront OBJ file as a grey diffuse mesh, see `Scene::from_mesh`.
+    pub fn from_obj_file<P: AsRef<Path>>(path: P) -> Result<Scene, Box<dyn Error>> {
+        let source = read_to_string(&path)?;
+        let material = Arc::new(Lambertian::from_rgb(0.73, 0.73, 0.73));
+        let mesh = parse_obj(&source, material)
+            .map_err(|err| format!("{}: {}", path.as_ref().display(), err))?;
+
+        Ok(Scene::from_mesh(mesh))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{geometry::Hittable, material::HitRecord, ray::Ray};
+
+    fn parse(source: &str) -> Result<TriangleMesh, String> {
+        parse_obj(source, Arc::new(Lambertian::from_rgb(0.5, 0.5, 0.5)))
+    }
+
+    // Hit by a ray going down -z through (x, y)
+    fn hit(mesh: &TriangleMesh, x: f32, y: f32) -> Option<HitRecord> {
+        let ray = Ray::new(vec3a(x, y, 1.), vec3a(0., 0., -1.), 0.);
+        mesh.hit(&ray, 0.001, f32::INFINITY)
+    }
+
+    #[test]
+    fn texture_coordinates_take_one_to_three_components() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.5\nvt 0.25 0.75\nvt 1 1 0\nf 1/1 2/2 3/3\n";
+        let mesh = parse(source).unwrap();
+
+        // At the first vertex, whose `v` defaults to 0
+        let rec = hit(&mesh, 1e-3, 1e-3).unwrap();
+        assert!((rec.u - 0.5).abs() < 1e-2 && rec.v.abs() < 1e-2);
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+        let mesh = parse(source).unwrap();
+
+        assert!(hit(&mesh, 0.75, 0.25).is_some());
+        assert!(hit(&mesh, 0.25, 0.75).is_some());
+        assert!(hit(&mesh, 1.5, 0.5).is_none());
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_vertex() {
+        let source = "v 5 5 5\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n";
+        let mesh = parse(source).unwrap();
+
+        assert!(hit(&mesh, 0.25, 0.25).is_some());
+    }
+
+    #[test]
+    fn reports_the_line_of_errors() {
+        assert_eq!(
+            parse("v 0 0 0\nv 1 0\n").err(),
+            Some("line 2: missing coordinate".to_owned())
+        );
+        assert_eq!(
+            parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n").err(),
+            Some("line 4: index `4` is out of range".to_owned())
+        );
+        assert_eq!(parse("v 0 0 0\n").err(), Some("no faces".to_owned()));
+    }
+}
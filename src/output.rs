@@ -0,0 +1,298 @@
+use std::{
+    convert::TryInto,
+    error::Error,
+    fs::{read, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{codecs::hdr::HdrEncoder, Rgb, RgbImage};
+
+use crate::{
+    ray::FirstHit,
+    render::Frame,
+    vec3::{get_color, Color, ToneMap},
+};
+
+/// Saves a render according to the extension of `path`: `.exr` and `.hdr` files get the linear
+/// radiance buffer as floats, without tone mapping, and every other format gets the tone mapped
+/// 8-bit image, or its RGBA version for frames with alpha. `.ppm` files are written by hand,
+/// as ASCII when `ascii_ppm` is set and binary otherwise, and never have alpha. `.png` files
+/// get the `metadata` keyword and text pairs as text chunks.
+pub fn save_render<P: AsRef<Path>>(
+    path: P,
+    frame: &Frame,
+    ascii_ppm: bool,
+    metadata: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let Frame {
+        image,
+        radiance,
+        alpha,
+        ..
+    } = frame;
+    let (width, height) = image.dimensions();
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("exr") => write_exr(path, width, height, radiance),
+        Some("hdr") => {
+            let pixels: Vec<Rgb<f32>> = radiance.iter().map(|c| Rgb([c.x, c.y, c.z])).collect();
+            let writer = BufWriter::new(File::create(path)?);
+            HdrEncoder::new(writer).encode(&pixels, width as usize, height as usize)?;
+            Ok(())
+        }
+        Some("ppm") => write_ppm(path, image, ascii_ppm),
+        Some("png") => match alpha {
+            Some(alpha) => write_png(
+                path,
+                alpha.dimensions(),
+                png::ColorType::RGBA,
+                alpha,
+                metadata,
+            ),
+            None => write_png(
+                path,
+                image.dimensions(),
+                png::ColorType::RGB,
+                image,
+                metadata,
+            ),
+        },
+        _ => match alpha {
+            Some(alpha) => Ok(alpha.save(path)?),
+            None => Ok(image.save(path)?),
+        },
+    }
+}
+
+// 8-bit PNG with a text chunk per metadata pair: `tEXt` when the text is ASCII, and `iTXt`,
+// which holds UTF-8, otherwise
+fn write_png(
+    path: &Path,
+    (width, height): (u32, u32),
+    color: png::ColorType,
+    pixels: &[u8],
+    metadata: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    for (keyword, text) in metadata {
+        let mut data = keyword.as_bytes().to_vec();
+        data.push(0);
+        if text.is_ascii() {
+            data.extend_from_slice(text.as_bytes());
+            writer.write_chunk(*b"tEXt", &data)?;
+        } else {
+            // Uncompressed, without language tag nor translated keyword
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            data.extend_from_slice(text.as_bytes());
+            writer.write_chunk(*b"iTXt", &data)?;
+        }
+    }
+
+    writer.write_image_data(pixels)?;
+    Ok(())
+}
+
+/// Reads back the keyword and text pairs of the uncompressed `tEXt` and `iTXt` chunks of a PNG
+/// file, in the order of the file.
+pub fn read_png_metadata<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let bytes = read(path)?;
+    if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err("not a PNG file".into());
+    }
+
+    // Chunks are a big-endian length, a type, the data and a CRC
+    let mut metadata = Vec::new();
+    let mut offset = 8;
+    while offset + 12 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        let kind = &bytes[offset + 4..offset + 8];
+        let data = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or("truncated chunk")?;
+        offset += 12 + length;
+
+        let (keyword, rest) = match data.iter().position(|&b| b == 0) {
+            Some(end) => (&data[..end], &data[end + 1..]),
+            None => continue,
+        };
+        // tEXt is Latin-1, whose code points are the first 256 of Unicode
+        let keyword: String = keyword.iter().map(|&b| b as char).collect();
+        match kind {
+            b"tEXt" => metadata.push((keyword, rest.iter().map(|&b| b as char).collect())),
+            // Compression flag and method, then the null-terminated language tag and translated
+            // keyword
+            b"iTXt" if rest.len() >= 2 && rest[0] == 0 => {
+                let text = rest[2..].splitn(3, |&b| b == 0).nth(2).unwrap_or_default();
+                metadata.push((keyword, String::from_utf8_lossy(text).into_owned()));
+            }
+            b"IEND" => break,
+            _ => (),
+        }
+    }
+    Ok(metadata)
+}
+
+// Netpbm color image: a `P3` (ASCII) or `P6` (binary) magic number, the width, height and
+// maximum value, then the RGB triplets row by row from the top
+fn write_ppm(path: &Path, image: &RgbImage, ascii: bool) -> Result<(), Box<dyn Error>> {
+    let (width, height) = image.dimensions();
+    let mut writer = BufWriter::new(File::create(path)?);
+    let magic = if ascii { "P3" } else { "P6" };
+    write!(writer, "{}\n{} {}\n255\n", magic, width, height)?;
+
+    if ascii {
+        for row in image.rows() {
+            let line: Vec<String> = row
+                .flat_map(|pixel| pixel.0)
+                .map(|c| c.to_string())
+                .collect();
+            writeln!(writer, "{}", line.join(" "))?;
+        }
+    } else {
+        writer.write_all(image.as_raw())?;
+    }
+    Ok(writer.flush()?)
+}
+
+// `render.png` becomes `render.<pass>.<extension>` next to it
+fn pass_path(path: &Path, pass: &str, extension: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.{}", stem, pass, extension))
+}
+
+/// Saves the auxiliary passes of a render next to it: the sRGB encoded albedo, the world space
+/// normals remapped from [-1, 1] to [0, 1], and the linear depth as a float image.
+pub fn save_aovs<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    first_hits: &[FirstHit],
+) -> Result<(), Box<dyn Error>> {
+    let path = path.as_ref();
+    let pixel = |i: u32| &first_hits[i as usize];
+
+    let albedo = RgbImage::from_fn(width, height, |x, y| {
+        get_color(pixel(y * width + x).albedo, 1., ToneMap::None)
+    });
+    albedo.save(pass_path(path, "albedo", "png"))?;
+
+    let normal = RgbImage::from_fn(width, height, |x, y| {
+        let n = (pixel(y * width + x).normal + Color::ONE) * 0.5;
+        Rgb([
+            (255. * n.x.clamp(0., 1.)).round() as u8,
+            (255. * n.y.clamp(0., 1.)).round() as u8,
+            (255. * n.z.clamp(0., 1.)).round() as u8,
+        ])
+    });
+    normal.save(pass_path(path, "normal", "png"))?;
+
+    let depth: Vec<Color> = first_hits
+        .iter()
+        .map(|hit| Color::splat(hit.depth))
+        .collect();
+    write_exr(&pass_path(path, "depth", "exr"), width, height, &depth)
+}
+
+// OpenEXR constants, see "The OpenEXR File Layout"
+const EXR_MAGIC: u32 = 20000630;
+const EXR_VERSION: u32 = 2;
+const EXR_PIXEL_TYPE_FLOAT: i32 = 2;
+
+fn write_attribute(out: &mut Vec<u8>, name: &str, type_name: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(type_name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+// Single part, uncompressed scanline file with 32-bit float R, G and B channels
+fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    radiance: &[Color],
+) -> Result<(), Box<dyn Error>> {
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&EXR_MAGIC.to_le_bytes());
+    header.extend_from_slice(&EXR_VERSION.to_le_bytes());
+
+    // Channels are stored in alphabetical order
+    let mut channels: Vec<u8> = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&EXR_PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and three reserved bytes, then the x and y sampling
+        channels.extend_from_slice(&[0; 4]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let mut window: Vec<u8> = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    write_attribute(&mut header, "channels", "chlist", &channels);
+    write_attribute(&mut header, "compression", "compression", &[0]);
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    write_attribute(
+        &mut header,
+        "screenWindowCenter",
+        "v2f",
+        &[0f32.to_le_bytes(), 0f32.to_le_bytes()].concat(),
+    );
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // Without compression every chunk holds a single scanline, preceded by its y coordinate and
+    // its size in bytes
+    let line_size = 3 * 4 * width as usize;
+    let chunk_size = 8 + line_size;
+    let first_chunk = header.len() + 8 * height as usize;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&header)?;
+    for y in 0..height as usize {
+        out.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    for (y, row) in radiance.chunks_exact(width as usize).enumerate() {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        for channel in [2, 1, 0] {
+            for color in row {
+                out.write_all(&color[channel].to_le_bytes())?;
+            }
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
@@ -1,18 +1,23 @@
-use std::{f32::consts::PI, sync::Arc};
+use std::{f32::consts::PI, str::FromStr, sync::Arc};
 
 use glam::{vec3a, Vec3A};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
-use crate::{geometry::Hittable, vec3::OrthNormBasis};
+use crate::{geometry::Hittable, rng::rng, vec3::OrthNormBasis};
 
-pub trait PDF {
+pub trait PDF: Send + Sync {
     fn value(&self, direction: Vec3A) -> f32;
     fn generate(&self) -> Vec3A;
+    // Generates a direction along with the density its contribution is divided by
+    fn sample(&self) -> (Vec3A, f32) {
+        let direction = self.generate();
+        (direction, self.value(direction))
+    }
 }
 
 #[inline]
 fn random_cosine_direction() -> Vec3A {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     let r1: f32 = rng.gen();
     let r2: f32 = rng.gen();
 
@@ -69,13 +74,55 @@ impl PDF for HittablePDF {
     }
 }
 
+// How the two strategies of a MixturePDF are weighted against each other for multiple
+// importance sampling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisHeuristic {
+    Balance,
+    Power,
+}
+
+impl FromStr for MisHeuristic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "balance" => Ok(MisHeuristic::Balance),
+            "power" => Ok(MisHeuristic::Power),
+            _ => Err(format!("unknown heuristic `{}`", s)),
+        }
+    }
+}
+
+impl MisHeuristic {
+    // Weight of a sample drawn with density `p` by one strategy, when `other` is the density
+    // the other strategy would have drawn it with
+    pub fn weight(&self, p: f32, other: f32) -> f32 {
+        let (p, other) = match self {
+            MisHeuristic::Balance => (p, other),
+            MisHeuristic::Power => (p * p, other * other),
+        };
+        if p + other > 0. {
+            p / (p + other)
+        } else {
+            0.
+        }
+    }
+}
+
 pub struct MixturePDF {
     pub p: [Arc<dyn PDF>; 2],
+    pub heuristic: MisHeuristic,
 }
 
 impl MixturePDF {
+    #[allow(unused)]
     pub fn new(p: [Arc<dyn PDF>; 2]) -> Self {
-        Self { p }
+        Self::with_heuristic(p, MisHeuristic::Balance)
+    }
+
+    pub fn with_heuristic(p: [Arc<dyn PDF>; 2], heuristic: MisHeuristic) -> Self {
+        Self { p, heuristic }
     }
 }
 
@@ -85,11 +132,212 @@ impl PDF for MixturePDF {
     }
 
     fn generate(&self) -> Vec3A {
-        let mut rng = thread_rng();
+        let mut rng = rng();
 
         if rng.gen_bool(0.5) {
             return self.p[0].generate();
         }
         self.p[1].generate()
     }
+
+    fn sample(&self) -> (Vec3A, f32) {
+        let mut rng = rng();
+
+        // The balance heuristic weights each strategy by its share of the mixture density
+        if self.heuristic == MisHeuristic::Balance {
+            let direction = self.generate();
+            return (direction, self.value(direction));
+        }
+
+        let selected = if rng.gen_bool(0.5) { 0 } else { 1 };
+        let direction = self.p[selected].generate();
+        let values = [self.p[0].value(direction), self.p[1].value(direction)];
+        if values[selected] <= 0. {
+            return (direction, 0.5 * (values[0] + values[1]));
+        }
+
+        // One-sample power heuristic: the selected strategy's weight p_i² / (p_0² + p_1²),
+        // divided by its selection probability 1/2 and its density p_i
+        let squares = values[0] * values[0] + values[1] * values[1];
+        (direction, 0.5 * squares / values[selected])
+    }
+}
+
+// Henyey-Greenstein phase function around the direction `w` a ray travels in: `g` between -1 and
+// 1 is the average cosine of the scattering angle, positive values scattering forward, negative
+// ones backward, and 0 evenly in every direction
+pub struct HenyeyGreensteinPDF {
+    uvw: OrthNormBasis,
+    g: f32,
+}
+
+impl HenyeyGreensteinPDF {
+    pub fn new(w: Vec3A, g: f32) -> Self {
+        Self {
+            uvw: OrthNormBasis::from_w(w),
+            g,
+        }
+    }
+}
+
+impl PDF for HenyeyGreensteinPDF {
+    fn value(&self, direction: Vec3A) -> f32 {
+        let cosine = direction.normalize().dot(self.uvw.w);
+        let g2 = self.g * self.g;
+        let denom = 1. + g2 - 2. * self.g * cosine;
+        (1. - g2) / (4. * PI * denom * denom.sqrt())
+    }
+
+    // Inverts the cumulative distribution of the cosine, the angle around `w` being uniform
+    fn generate(&self) -> Vec3A {
+        let mut rng = rng();
+        let r1: f32 = rng.gen();
+        let r2: f32 = rng.gen();
+
+        let g = self.g;
+        let cosine = if g.abs() < 1e-3 {
+            1. - 2. * r1
+        } else {
+            let s = (1. - g * g) / (1. - g + 2. * g * r1);
+            ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+        };
+        let sine = (1. - cosine * cosine).max(0.).sqrt();
+        let phi = 2. * PI * r2;
+
+        self.uvw
+            .local(vec3a(sine * phi.cos(), sine * phi.sin(), cosine))
+    }
+}
+
+// Samples reflections off GGX microfacets, drawing only the normals visible from `wo` (Heitz,
+// "Sampling the GGX Distribution of Visible Normals", 2018)
+pub struct GgxPDF {
+    uvw: OrthNormBasis,
+    wo: Vec3A,
+    alpha: f32,
+}
+
+impl GgxPDF {
+    pub fn new(n: Vec3A, wo: Vec3A, alpha: f32) -> Self {
+        Self {
+            uvw: OrthNormBasis::from_w(n),
+            wo,
+            alpha,
+        }
+    }
+}
+
+impl PDF for GgxPDF {
+    fn value(&self, direction: Vec3A) -> f32 {
+        let wi = direction.normalize();
+        let n = self.uvw.w;
+        let n_dot_wo = n.dot(self.wo);
+        if n.dot(wi) <= 0. || n_dot_wo <= 0. {
+            return 0.;
+        }
+
+        // Density of the visible normals, times the 1 / (4 wo.h) Jacobian of the reflection
+        let h = (wi + self.wo).normalize();
+        smith_g1(n_dot_wo, self.alpha) * ggx_d(n.dot(h), self.alpha) / (4. * n_dot_wo)
+    }
+
+    fn generate(&self) -> Vec3A {
+        let mut rng = rng();
+        let r1: f32 = rng.gen();
+        let r2: f32 = rng.gen();
+
+        // Stretch the view direction into the configuration where the roughness is 1
+        let wo = vec3a(
+            self.uvw.u.dot(self.wo),
+            self.uvw.v.dot(self.wo),
+            self.uvw.w.dot(self.wo),
+        );
+        let vh = vec3a(self.alpha * wo.x, self.alpha * wo.y, wo.z).normalize();
+
+        let len_sq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len_sq > 0. {
+            vec3a(-vh.y, vh.x, 0.) / len_sq.sqrt()
+        } else {
+            Vec3A::X
+        };
+        let t2 = vh.cross(t1);
+
+        // Uniform point on the disk, warped onto the part of the hemisphere visible from `vh`
+        let r = r1.sqrt();
+        let phi = 2. * PI * r2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+        // Unstretch the sampled normal and reflect around it
+        let h = vec3a(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(0.)).normalize();
+        let h = self.uvw.local(h);
+        2. * self.wo.dot(h) * h - self.wo
+    }
+}
+
+// Trowbridge-Reitz (GGX) normal distribution
+pub fn ggx_d(n_dot_h: f32, alpha: f32) -> f32 {
+    if n_dot_h <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+// Smith masking term for a single direction
+pub fn smith_g1(n_dot_v: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    2. * n_dot_v / (n_dot_v + (a2 + (1. - a2) * n_dot_v * n_dot_v).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::set_seed;
+
+    #[test]
+    fn heuristic_weights_sum_to_one() {
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            for (p, other) in [(1., 1.), (0.2, 3.), (5., 0.), (1e-3, 1e3)] {
+                let sum = heuristic.weight(p, other) + heuristic.weight(other, p);
+                assert!((sum - 1.).abs() < 1e-6, "{:?} {} {}", heuristic, p, other);
+            }
+            assert_eq!(heuristic.weight(0., 0.), 0.);
+        }
+    }
+
+    #[test]
+    fn mixture_estimates_are_unbiased() {
+        set_seed(6);
+        // Two hemispheres 45 degrees apart overlap over a lune of 3π/2 steradians
+        let (z, tilted) = (Vec3A::Z, vec3a(1., 0., 1.).normalize());
+        let in_lune = |direction: Vec3A| direction.dot(z) > 0. && direction.dot(tilted) > 0.;
+
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let pdf = MixturePDF::with_heuristic(
+                [
+                    Arc::new(CosinePDF::new(z)),
+                    Arc::new(CosinePDF::new(tilted)),
+                ],
+                heuristic,
+            );
+            let n = 200000;
+            let sum: f32 = (0..n)
+                .map(|_| match pdf.sample() {
+                    (direction, density) if in_lune(direction) => 1. / density,
+                    _ => 0.,
+                })
+                .sum();
+            let estimate = sum / n as f32;
+            assert!(
+                (estimate - 1.5 * PI).abs() < 0.02 * 1.5 * PI,
+                "{:?}: {}",
+                heuristic,
+                estimate
+            );
+        }
+    }
 }
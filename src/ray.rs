@@ -1,15 +1,25 @@
 use std::sync::Arc;
 
 use glam::Vec3A;
+use rand::Rng;
 
 use crate::{
+    background::Background,
     bvh::BVH,
     geometry::{Hittable, Hittables, Primitive},
-    material::HitRecord,
-    pdf::{HittablePDF, MixturePDF},
-    vec3::{mul, Color},
+    material::{HitRecord, Scatter},
+    pdf::{HittablePDF, MisHeuristic, MixturePDF},
+    rng::rng,
+    vec3::Color,
 };
 
+// Bounces traced unconditionally before Russian roulette may terminate a path
+pub const MIN_BOUNCES: u32 = 3;
+
+// Share of the distance to a sampled light point that shadow rays stop short of, so that they
+// don't hit the light itself
+const SHADOW_EPSILON: f32 = 1e-3;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     origin: Vec3A,
@@ -42,86 +52,490 @@ impl Ray {
         self.origin + t * self.direction
     }
 
+    // Same as `ray_color` without next event estimation, but intersecting `primitives` through a
+    // flat BVH instead of a tree of `Arc<dyn Hittable>`. `depth` counts bounces, so a depth of 0
+    // still sees the emitters and the sky in front of the ray.
     pub fn get_color(
         &self,
-        background: &Color,
-        bvh: BVH,
+        background: &Background,
+        bvh: &BVH,
         primitives: &[Primitive],
-        depth: usize,
+        lights: &Hittables,
+        depth: u32,
+        heuristic: MisHeuristic,
     ) -> Color {
-        if depth == 0 {
-            return Color::ZERO;
-        }
+        trace_path(
+            self,
+            background,
+            lights,
+            depth,
+            heuristic,
+            false,
+            |ray, t_min, t_max| {
+                bvh.traverse_nearest(
+                    ray,
+                    primitives,
+                    |primitive, t_min, t_max| primitive.hit(ray, t_min, t_max),
+                    t_min,
+                    t_max,
+                )
+            },
+        )
+    }
+}
+
+pub fn ray_color<W: Hittable + ?Sized>(
+    ray: &Ray,
+    background: &Background,
+    world: &W,
+    lights: &Hittables,
+    depth: u32,
+    heuristic: MisHeuristic,
+    next_event: bool,
+) -> Color {
+    trace_path(
+        ray,
+        background,
+        lights,
+        depth,
+        heuristic,
+        next_event,
+        |ray, t_min, t_max| world.hit(ray, t_min, t_max),
+    )
+}
 
-        let mut t = f32::INFINITY;
-        let mut closest_hit: Option<HitRecord> = None;
+// What a camera ray sees at its first intersection, for the auxiliary render passes. `depth` is
+// the distance from the ray origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FirstHit {
+    pub albedo: Color,
+    pub normal: Vec3A,
+    pub depth: f32,
+}
 
-        let possible_hits = bvh.traverse(self, primitives, 0.0001, t);
+impl FirstHit {
+    // Per-pixel average of the hits of `samples` camera rays: rays that missed count as black
+    // albedo and no normal, and the depth stays infinite when every ray missed
+    pub fn average(hits: &[FirstHit], samples: u32) -> FirstHit {
+        if hits.is_empty() {
+            return FirstHit {
+                albedo: Color::ZERO,
+                normal: Vec3A::ZERO,
+                depth: f32::INFINITY,
+            };
+        }
 
-        for primitive in possible_hits.iter() {
-            let hit = primitive.hit(self, 0.0001, t);
-            match &hit {
-                Some(rec) => {
-                    t = rec.t;
-                    closest_hit = hit;
-                }
-                _ => {}
-            }
+        let scale = 1. / samples as f32;
+        FirstHit {
+            albedo: hits.iter().fold(Color::ZERO, |sum, hit| sum + hit.albedo) * scale,
+            normal: hits
+                .iter()
+                .fold(Vec3A::ZERO, |sum, hit| sum + hit.normal)
+                .normalize_or_zero(),
+            depth: hits.iter().map(|hit| hit.depth).sum::<f32>() / hits.len() as f32,
         }
+    }
+}
+
+pub fn first_hit<W: Hittable + ?Sized>(ray: &Ray, world: &W) -> Option<FirstHit> {
+    let rec = world.hit(ray, 0.0001, f32::INFINITY)?;
+
+    // Emitters don't scatter, their albedo is their clamped emitted color instead
+    let albedo = match rec.mat.scatter(ray, &rec) {
+        Some(scatter) => scatter.attenuation,
+        None => rec
+            .mat
+            .emitted(ray, &rec, rec.u, rec.v, &rec.p)
+            .min(Color::ONE),
+    };
+
+    Some(FirstHit {
+        albedo,
+        normal: rec.normal,
+        depth: rec.t * ray.direction().length(),
+    })
+}
 
-        // TODO: Compute color
+// Whether anything lies along the segment from `origin` toward `target` before `t_max`, in
+// units of the distance between them, `closest_hit` intersecting the scene within a range
+fn occluded<F: Fn(&Ray, f32, f32) -> Option<HitRecord>>(
+    closest_hit: &F,
+    origin: Vec3A,
+    target: Vec3A,
+    time: f32,
+    t_max: f32,
+) -> bool {
+    closest_hit(&Ray::new(origin, target - origin, time), 0.0001, t_max).is_some()
+}
+
+// Next event estimation: the radiance reaching `rec` straight from a point sampled on `lights`,
+// scattered back along `ray`, and weighted against the material drawing the same direction
+fn sample_light<F: Fn(&Ray, f32, f32) -> Option<HitRecord>>(
+    ray: &Ray,
+    rec: &HitRecord,
+    scatter: &Scatter,
+    lights: &Arc<dyn Hittable>,
+    background: &Background,
+    heuristic: MisHeuristic,
+    closest_hit: &F,
+) -> Color {
+    let pdf = match &scatter.pdf {
+        Some(pdf) => pdf,
+        None => return Color::ZERO,
+    };
 
-        *background
+    let direction = lights.random(rec.p);
+    let light_pdf = lights.pdf_value(rec.p, direction);
+    if light_pdf <= 0. {
+        return Color::ZERO;
     }
+
+    // Lights at infinity such as the sun are never hit
+    let shadow = Ray::new(rec.p, direction, ray.time());
+    let t = match lights.hit(&shadow, 0.0001, f32::INFINITY) {
+        Some(light) => light.t,
+        None => f32::INFINITY,
+    };
+    if occluded(
+        closest_hit,
+        rec.p,
+        rec.p + direction,
+        ray.time(),
+        (1. - SHADOW_EPSILON) * t,
+    ) {
+        return Color::ZERO;
+    }
+
+    // The radiance comes from the light as it is in the scene, where it may be one-sided, rather
+    // than from the copy used for sampling. That of the lights at infinity is the sky's.
+    let emitted = if t.is_finite() {
+        let (t_min, t_max) = ((1. - SHADOW_EPSILON) * t, (1. + SHADOW_EPSILON) * t);
+        match closest_hit(&shadow, t_min, t_max) {
+            Some(light) => light
+                .mat
+                .emitted(&shadow, &light, light.u, light.v, &light.p),
+            None => return Color::ZERO,
+        }
+    } else {
+        background.value(direction)
+    };
+
+    let (transmittance, _) = background.fog(&shadow, t);
+    let weight = heuristic.weight(light_pdf, pdf.value(direction));
+    rec.mat
+        .scattering_color(ray, rec, &shadow, scatter.attenuation)
+        * transmittance
+        * emitted
+        * weight
+        / light_pdf
 }
 
-pub fn ray_color(
+// Follows a path of at most `depth` bounces from `ray`, `closest_hit` intersecting the scene
+// within a range: a depth of 0 only sees emitters and the sky, and a depth of 1 adds direct
+// lighting. Each bounce adds the radiance emitted toward the camera weighted by the throughput
+// of the path so far, then multiplies the throughput by the scattering weight of the next
+// direction.
+// Diffuse and glossy bounces draw that direction from the material or the lights with equal
+// odds, or with `next_event` from the material alone, the lights being sampled separately
+// through a shadow ray.
+fn trace_path<F: Fn(&Ray, f32, f32) -> Option<HitRecord>>(
     ray: &Ray,
-    background: &Color,
-    world: Arc<dyn Hittable>,
-    lights: Hittables,
+    background: &Background,
+    lights: &Hittables,
     depth: u32,
+    heuristic: MisHeuristic,
+    next_event: bool,
+    closest_hit: F,
 ) -> Color {
-    if depth == 0 {
-        return Color::new(0., 0., 0.);
-    }
+    let mut radiance = Color::ZERO;
+    let mut throughput = Color::ONE;
+    let mut ray = *ray;
 
-    if let Some(rec) = world.hit(ray, 0.0001, f32::INFINITY) {
-        let emitted = rec.mat.emitted(ray, &rec, rec.u, rec.v, &rec.p);
+    // Shared by every bounce of the path rather than rebuilt at each one
+    let lights: Option<Arc<dyn Hittable>> = if lights.is_empty() {
+        None
+    } else {
+        Some(Arc::new(lights.clone()))
+    };
+
+    // Density the material drew `ray` with, when the lights were also sampled at that bounce:
+    // the emitters it reaches are then weighted against the light sampling
+    let mut material_pdf: Option<f32> = None;
+    let emission_weight = |ray: &Ray, material_pdf: Option<f32>| match (material_pdf, &lights) {
+        (Some(pdf), Some(lights)) => {
+            heuristic.weight(pdf, lights.pdf_value(ray.origin(), ray.direction()))
+        }
+        _ => 1.,
+    };
+
+    let mut rng = rng();
+
+    for bounce in 0..=depth {
+        // Russian roulette: paths that can't contribute much are stopped early, and the ones
+        // that survive are boosted by the same odds to keep the estimate unbiased
+        if bounce >= MIN_BOUNCES {
+            let survival = throughput.max_element().min(1.);
+            if rng.gen::<f32>() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        let rec = match closest_hit(&ray, 0.0001, f32::INFINITY) {
+            Some(rec) => rec,
+            None => {
+                // The ray hit nothing
+                let sky = emission_weight(&ray, material_pdf) * background.value(ray.direction());
+                radiance += throughput * background.apply_fog(&ray, f32::INFINITY, sky);
+                break;
+            }
+        };
+
+        let (transmittance, inscattered) = background.fog(&ray, rec.t);
+        radiance += throughput * inscattered;
+        throughput *= transmittance;
 
-        if let Some(scatter) = rec.mat.scatter(ray, &rec) {
-            if let Some(scattered) = scatter.specular_ray {
-                return mul(
-                    scatter.attenuation,
-                    ray_color(&scattered, background, world, lights, depth - 1),
-                );
+        let emitted =
+            emission_weight(&ray, material_pdf) * rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.p);
+        let scatter = match rec.mat.scatter(&ray, &rec) {
+            Some(scatter) => scatter,
+            None => {
+                radiance += throughput * emitted;
+                break;
             }
-            let mut scattered = Ray::new(rec.p, rec.normal, 0.);
-            let mut pdf_val: f32 = 1.;
+        };
 
-            if let Some(mut pdf) = scatter.pdf {
-                if !lights.is_empty() {
-                    let light = Arc::new(HittablePDF::new(rec.p, Arc::new(lights.clone())));
-                    pdf = Arc::new(MixturePDF::new([pdf, light]));
+        if let Some(scattered) = scatter.specular_ray {
+            throughput *= scatter.attenuation;
+            ray = scattered;
+            material_pdf = None;
+            continue;
+        }
+
+        radiance += throughput * emitted;
+
+        let mut scattered = Ray::new(rec.p, rec.normal, 0.);
+        let mut pdf_val: f32 = 1.;
+        material_pdf = None;
+
+        // The lights found by the next bounce would be beyond the maximum depth
+        if next_event && bounce < depth {
+            if let Some(lights) = &lights {
+                radiance += throughput
+                    * sample_light(
+                        &ray,
+                        &rec,
+                        &scatter,
+                        lights,
+                        background,
+                        heuristic,
+                        &closest_hit,
+                    );
+            }
+        }
+
+        if let Some(mut pdf) = scatter.pdf {
+            if !next_event {
+                if let Some(lights) = &lights {
+                    let light = Arc::new(HittablePDF::new(rec.p, lights.clone()));
+                    pdf = Arc::new(MixturePDF::with_heuristic([pdf, light], heuristic));
                 }
+            }
 
-                scattered = Ray::new(rec.p, pdf.generate(), ray.time());
-                pdf_val = pdf.value(scattered.direction());
+            let (direction, value) = pdf.sample();
+            scattered = Ray::new(rec.p, direction, ray.time());
+            pdf_val = value;
+            if next_event && lights.is_some() {
+                material_pdf = Some(value);
             }
+        }
+
+        pdf_val = pdf_val.max(1e-5);
+
+        throughput *= rec
+            .mat
+            .scattering_color(&ray, &rec, &scattered, scatter.attenuation)
+            / pdf_val;
+        ray = scattered;
+    }
+
+    radiance
+}
 
-            pdf_val = pdf_val.max(1e-5);
+#[cfg(test)]
+mod tests {
+    use glam::vec3a;
+
+    use super::*;
+    use crate::{
+        geometry::sphere::Sphere,
+        material::{DiffuseLight, Lambertian},
+        rng::set_seed,
+        scene::{get_scene, SceneType},
+    };
+
+    fn color(world: &Sphere, background: &Background, depth: u32) -> Color {
+        let ray = Ray::new(vec3a(0., 0., 5.), vec3a(0., 0., -1.), 0.);
+        ray_color(
+            &ray,
+            background,
+            world,
+            &Hittables::new(),
+            depth,
+            MisHeuristic::Balance,
+            false,
+        )
+    }
 
-            return emitted
-                + rec.mat.scattering_pdf(ray, &rec, &scattered)
-                    * mul(
-                        scatter.attenuation,
-                        ray_color(&scattered, background, world, lights.clone(), depth - 1),
-                    )
-                    / pdf_val;
+    #[test]
+    fn rays_that_miss_see_the_background() {
+        let sphere = Sphere::new(
+            vec3a(10., 0., 0.),
+            1.,
+            Arc::new(Lambertian::from_color(Color::ONE)),
+        );
+        let background = Background::Solid(Color::new(0.2, 0.4, 0.6));
+
+        assert_eq!(color(&sphere, &background, 12), Color::new(0.2, 0.4, 0.6));
+    }
+
+    #[test]
+    fn throughput_is_multiplied_at_each_bounce() {
+        // Light leaving a convex diffuse ball never comes back to it, so under a uniform sky
+        // every path brings back the albedo times the sky
+        let sphere = Sphere::new(
+            vec3a(0., 0., 0.),
+            1.,
+            Arc::new(Lambertian::from_color(Color::new(0.5, 0.25, 1.))),
+        );
+        let background = Background::Solid(Color::splat(2.));
+
+        for _ in 0..100 {
+            let c = color(&sphere, &background, 12);
+            assert!(
+                (c - Color::new(1., 0.5, 2.)).abs().max_element() < 1e-4,
+                "{}",
+                c
+            );
         }
-        return emitted;
+        // Without any bounce left, the sky isn't reached
+        assert_eq!(color(&sphere, &background, 0), Color::ZERO);
     }
 
-    // The ray hit nothing
-    *background
+    #[test]
+    fn emitters_are_seen_directly() {
+        let light = Sphere::new(
+            vec3a(0., 0., 0.),
+            1.,
+            Arc::new(DiffuseLight::from_color(Color::new(4., 2., 1.))),
+        );
+
+        let c = color(&light, &Background::Solid(Color::ZERO), 0);
+        assert_eq!(c, Color::new(4., 2., 1.));
+    }
+
+    #[test]
+    fn flat_bvh_paths_without_bounces_only_see_emitters_and_the_sky() {
+        let primitives = [
+            Primitive::Sphere(Sphere::new(
+                vec3a(0., 0., 0.),
+                1.,
+                Arc::new(DiffuseLight::from_color(Color::new(4., 2., 1.))),
+            )),
+            Primitive::Sphere(Sphere::new(
+                vec3a(3., 0., 0.),
+                1.,
+                Arc::new(Lambertian::from_color(Color::ONE)),
+            )),
+        ];
+        let bvh = BVH::new(&primitives);
+        let background = Background::Solid(Color::splat(2.));
+        let color = |x: f32| {
+            Ray::new(vec3a(x, 0., 5.), vec3a(0., 0., -1.), 0.).get_color(
+                &background,
+                &bvh,
+                &primitives,
+                &Hittables::new(),
+                0,
+                MisHeuristic::Balance,
+            )
+        };
+
+        assert_eq!(color(0.), Color::new(4., 2., 1.));
+        assert_eq!(color(3.), Color::ZERO);
+        assert_eq!(color(-3.), Color::splat(2.));
+    }
+
+    // The recursive tracer that the loop replaced, for scenes without fog: `depth` counts the
+    // rays traced, so it matches `ray_color` with one bounce less
+    fn recursive_color(ray: &Ray, world: &dyn Hittable, lights: &Hittables, depth: u32) -> Color {
+        if depth == 0 {
+            return Color::ZERO;
+        }
+        let rec = match world.hit(ray, 0.0001, f32::INFINITY) {
+            Some(rec) => rec,
+            None => return Color::ZERO,
+        };
+
+        let emitted = rec.mat.emitted(ray, &rec, rec.u, rec.v, &rec.p);
+        let scatter = match rec.mat.scatter(ray, &rec) {
+            Some(scatter) => scatter,
+            None => return emitted,
+        };
+        if let Some(scattered) = scatter.specular_ray {
+            return scatter.attenuation * recursive_color(&scattered, world, lights, depth - 1);
+        }
+
+        let mut scattered = Ray::new(rec.p, rec.normal, 0.);
+        let mut pdf_val: f32 = 1.;
+        if let Some(mut pdf) = scatter.pdf {
+            if !lights.is_empty() {
+                let light = Arc::new(HittablePDF::new(rec.p, Arc::new(lights.clone())));
+                pdf = Arc::new(MixturePDF::new([pdf, light]));
+            }
+            scattered = Ray::new(rec.p, pdf.generate(), ray.time());
+            pdf_val = pdf.value(scattered.direction());
+        }
+        pdf_val = pdf_val.max(1e-5);
+
+        emitted
+            + rec
+                .mat
+                .scattering_color(ray, &rec, &scattered, scatter.attenuation)
+                * recursive_color(&scattered, world, lights, depth - 1)
+                / pdf_val
+    }
+
+    #[test]
+    fn the_loop_matches_the_recursive_tracer() {
+        set_seed(21);
+        let scene = get_scene(SceneType::CornellBox, 1., None);
+        // From the camera to the back wall, below the tall box
+        let ray = Ray::new(vec3a(278., 278., -800.), vec3a(0.1, -0.15, 1.), 0.);
+        let n = 100000;
+
+        let mut iterative = Color::ZERO;
+        let mut recursive = Color::ZERO;
+        for _ in 0..n {
+            iterative += ray_color(
+                &ray,
+                &scene.background,
+                &scene.world,
+                &scene.lights,
+                8,
+                MisHeuristic::Balance,
+                false,
+            );
+            recursive += recursive_color(&ray, scene.world.as_ref(), &scene.lights, 9);
+        }
+        let (iterative, recursive) = (iterative / n as f32, recursive / n as f32);
+
+        assert!(recursive.min_element() > 0.);
+        assert!(
+            ((iterative - recursive) / recursive).abs().max_element() < 0.05,
+            "{} {}",
+            iterative,
+            recursive
+        );
+    }
 }
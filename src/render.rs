@@ -0,0 +1,402 @@
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use image::{ImageBuffer, RgbImage, Rgba, RgbaImage};
+use rayon::prelude::*;
+
+use crate::{
+    filter::Filter,
+    pdf::MisHeuristic,
+    ray::{first_hit, ray_color, FirstHit},
+    rng,
+    sampler::{Sampler, SamplerType},
+    scene::Scene,
+    vec3::{clamp_luminance, get_color, Color, ToneMap},
+};
+
+// Everything about a render besides the scene
+#[derive(Clone, Copy)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub sampler_type: SamplerType,
+    pub filter: Filter,
+    pub tone_map: ToneMap,
+    pub max_depth: u32,
+    pub heuristic: MisHeuristic,
+    pub next_event: bool,
+    pub clamp: Option<f32>,
+    pub seed: u64,
+    pub aovs: bool,
+    pub alpha: bool,
+}
+
+impl RenderSettings {
+    // Seeded with 0, with a box filter, without next event estimation, tone mapping, clamping,
+    // AOVs or alpha
+    pub fn new(width: u32, height: u32, samples: u32, max_depth: u32) -> RenderSettings {
+        RenderSettings {
+            width,
+            height,
+            samples,
+            sampler_type: SamplerType::Random,
+            filter: Filter::Box,
+            tone_map: ToneMap::None,
+            max_depth,
+            heuristic: MisHeuristic::Balance,
+            next_event: false,
+            clamp: None,
+            seed: 0,
+            aovs: false,
+            alpha: false,
+        }
+    }
+}
+
+pub struct Frame {
+    pub image: RgbImage,
+    pub radiance: Vec<Color>,
+    pub first_hits: Option<Vec<FirstHit>>,
+    // The image with the fraction of the camera rays that hit the world as alpha
+    pub alpha: Option<RgbaImage>,
+}
+
+// Side of the square tiles the image is split into for rendering
+const TILE_SIZE: u32 = 32;
+
+// Tiles in row-major order as (x0, y0, x1, y1), clipped to the image
+pub fn tiles(width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+    let mut out = Vec::new();
+    for y0 in (0..height).step_by(TILE_SIZE as usize) {
+        for x0 in (0..width).step_by(TILE_SIZE as usize) {
+            out.push((
+                x0,
+                y0,
+                (x0 + TILE_SIZE).min(width),
+                (y0 + TILE_SIZE).min(height),
+            ));
+        }
+    }
+    out
+}
+
+// Samples of a pixel: the sum of their colors weighted by the filter and the sum of the weights,
+// the first hits of their camera rays if AOVs are asked for, and the same sums for those whose
+// camera ray hit the world if alpha is
+struct PixelSamples {
+    color: Color,
+    weight: f32,
+    first_hits: Vec<FirstHit>,
+    covered_color: Color,
+    covered_weight: f32,
+}
+
+// Samples of the range `samples` for the pixel (x, y), whose film footprint and filter span
+// `footprint` pixels. Each range reseeds the generator, so that successive ranges get new
+// random numbers.
+fn sample_pixel(
+    scene: &Scene,
+    settings: &RenderSettings,
+    x: u32,
+    y: u32,
+    footprint: u32,
+    samples: Range<u32>,
+) -> PixelSamples {
+    let seed = settings.seed ^ (samples.start as u64) << 32;
+    rng::seed_pixel(x, y, seed);
+    let mut sampler = Sampler::new(settings.sampler_type, x, y, settings.samples);
+    let mut out = PixelSamples {
+        color: Color::ZERO,
+        weight: 0.,
+        first_hits: Vec::new(),
+        covered_color: Color::ZERO,
+        covered_weight: 0.,
+    };
+
+    for i in samples {
+        sampler.start_sample(i);
+        let (dx, dy) = sampler.next_2d();
+        let ((dx, wx), (dy, wy)) = (settings.filter.sample(dx), settings.filter.sample(dy));
+        let weight = wx * wy;
+        let u = (x as f32 + footprint as f32 * dx) / settings.width as f32;
+        let v = (y as f32 + footprint as f32 * dy) / settings.height as f32;
+        let ray = scene.camera.get_ray(u, v, &mut sampler);
+        if settings.aovs {
+            out.first_hits.extend(first_hit(&ray, &scene.world));
+        }
+        let covered = settings.alpha && scene.world.hit(&ray, 0.0001, f32::INFINITY).is_some();
+        let sample = ray_color(
+            &ray,
+            &scene.background,
+            &scene.world,
+            &scene.lights,
+            settings.max_depth,
+            settings.heuristic,
+            settings.next_event,
+        );
+        // Clamping is biased, so it's only done when asked for
+        let sample = match settings.clamp {
+            Some(max) => clamp_luminance(sample, max),
+            None => sample,
+        };
+        out.color += weight * sample;
+        out.weight += weight;
+        if covered {
+            out.covered_color += weight * sample;
+            out.covered_weight += weight;
+        }
+    }
+
+    out
+}
+
+// Renders the pixels of each tile of a `width` x `height` grid in parallel, calling `progress`
+// with the fraction of the tiles done. The pixels come back in image order, from the top row.
+fn render_tiles<T: Send>(
+    width: u32,
+    height: u32,
+    render_pixel: impl Fn(u32, u32) -> T + Sync,
+    progress: impl Fn(f32) + Sync,
+) -> Vec<T> {
+    let tiles = tiles(width, height);
+    let tile_count = tiles.len();
+    let done = AtomicUsize::new(0);
+    let rendered_tiles: Vec<_> = tiles
+        .into_par_iter()
+        .map(|tile| {
+            let (x0, y0, x1, y1) = tile;
+            let pixels: Vec<_> = (y0..y1)
+                .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+                .map(|(x, y)| render_pixel(x, y))
+                .collect();
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(done as f32 / tile_count as f32);
+            (tile, pixels)
+        })
+        .collect();
+
+    let mut out: Vec<Option<T>> = (0..width * height).map(|_| None).collect();
+    for ((x0, y0, x1, _), pixels) in rendered_tiles {
+        let tile_width = (x1 - x0) as usize;
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let x = x0 + (i % tile_width) as u32;
+            // Rows go up from the bottom of the film but down the image
+            let y = height - 1 - (y0 + (i / tile_width) as u32);
+            out[(y * width + x) as usize] = Some(pixel);
+        }
+    }
+    out.into_iter().flatten().collect()
+}
+
+// Tone maps the weighted sums of the samples of each pixel, divided by the sums of their weights
+fn weighted_image(
+    width: u32,
+    height: u32,
+    sums: &[Color],
+    weights: &[f32],
+    tone_map: ToneMap,
+) -> RgbImage {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let i = (y * width + x) as usize;
+        get_color(sums[i], weights[i], tone_map)
+    })
+}
+
+/// Tone maps an already averaged radiance buffer into an 8-bit image.
+pub fn to_image(width: u32, height: u32, radiance: &[Color], tone_map: ToneMap) -> RgbImage {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        get_color(radiance[(y * width + x) as usize], 1., tone_map)
+    })
+}
+
+// Weighted sums of the samples divided by the sums of their weights
+fn average(sums: &[Color], weights: &[f32]) -> Vec<Color> {
+    sums.iter()
+        .zip(weights)
+        .map(|(sum, weight)| *sum / *weight)
+        .collect()
+}
+
+/// Renders each tile into its own buffers in parallel, then copies them into the frame.
+/// `progress` is called with the fraction of the frame done after each tile, from the rendering
+/// threads.
+pub fn render_frame(
+    scene: &Scene,
+    settings: &RenderSettings,
+    progress: impl Fn(f32) + Sync,
+) -> Frame {
+    let RenderSettings {
+        width,
+        height,
+        samples,
+        tone_map,
+        aovs,
+        alpha,
+        ..
+    } = *settings;
+
+    let pixels = render_tiles(
+        width,
+        height,
+        |x, y| sample_pixel(scene, settings, x, y, 1, 0..samples),
+        progress,
+    );
+
+    let sums: Vec<Color> = pixels.iter().map(|pixel| pixel.color).collect();
+    let weights: Vec<f32> = pixels.iter().map(|pixel| pixel.weight).collect();
+    let first_hits = aovs.then(|| {
+        pixels
+            .iter()
+            .map(|pixel| FirstHit::average(&pixel.first_hits, samples))
+            .collect()
+    });
+    // Straight alpha: the color only averages the samples that hit the world, so that edges
+    // don't carry the background once composited. Empty pixels keep the background.
+    let alpha = alpha.then(|| {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let pixel = &pixels[(y * width + x) as usize];
+            let [r, g, b] = if pixel.covered_weight > 0. {
+                get_color(pixel.covered_color, pixel.covered_weight, tone_map)
+            } else {
+                get_color(pixel.color, pixel.weight, tone_map)
+            }
+            .0;
+            let a = (255. * pixel.covered_weight / pixel.weight).round() as u8;
+            Rgba([r, g, b, a])
+        })
+    });
+
+    Frame {
+        image: weighted_image(width, height, &sums, &weights, tone_map),
+        radiance: average(&sums, &weights),
+        first_hits,
+        alpha,
+    }
+}
+
+/// Renders progressively: first a quarter resolution preview with a single sample per pixel,
+/// then the full image with 1, 4, 16... samples per pixel up to `settings.samples`. Each pass
+/// adds its samples to those of the previous ones, and `preview` gets the image and the number
+/// of samples per pixel after each of them. `progress` gets the fraction of the samples done.
+/// AOVs and alpha aren't computed in this mode.
+pub fn render_progressive(
+    scene: &Scene,
+    settings: &RenderSettings,
+    progress: impl Fn(f32) + Sync,
+    mut preview: impl FnMut(&RgbImage, u32),
+) -> Frame {
+    let RenderSettings {
+        width,
+        height,
+        samples,
+        tone_map,
+        ..
+    } = *settings;
+    let settings = RenderSettings {
+        aovs: false,
+        alpha: false,
+        ..*settings
+    };
+
+    // Each preview pixel covers a 2x2 block of the image
+    let (preview_width, preview_height) = (width.div_ceil(2), height.div_ceil(2));
+    let blocks = render_tiles(
+        preview_width,
+        preview_height,
+        |x, y| {
+            let pixel = sample_pixel(scene, &settings, 2 * x, 2 * y, 2, 0..1);
+            (pixel.color, pixel.weight)
+        },
+        |_| (),
+    );
+    let (sums, weights): (Vec<Color>, Vec<f32>) = blocks.into_iter().unzip();
+    let blocks = weighted_image(preview_width, preview_height, &sums, &weights, tone_map);
+    // Rows of the preview go down the image from its last full block
+    let offset = 2 * preview_height - height;
+    preview(
+        &ImageBuffer::from_fn(width, height, |x, y| {
+            *blocks.get_pixel(x / 2, (y + offset) / 2)
+        }),
+        1,
+    );
+
+    let mut sums = vec![Color::ZERO; (width * height) as usize];
+    let mut weights = vec![0.; (width * height) as usize];
+    let mut done = 0;
+    while done < samples {
+        let pass = (4 * done).clamp(1, samples);
+        let pixels = render_tiles(
+            width,
+            height,
+            |x, y| sample_pixel(scene, &settings, x, y, 1, done..pass),
+            |fraction| progress((done as f32 + fraction * (pass - done) as f32) / samples as f32),
+        );
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            sums[i] += pixel.color;
+            weights[i] += pixel.weight;
+        }
+        done = pass;
+
+        preview(
+            &weighted_image(width, height, &sums, &weights, tone_map),
+            done,
+        );
+    }
+
+    Frame {
+        image: weighted_image(width, height, &sums, &weights, tone_map),
+        radiance: average(&sums, &weights),
+        first_hits: None,
+        alpha: None,
+    }
+}
+
+/// Renders `scene` into an 8-bit image, without reporting progress, so that it can be used outside
+/// of the command line.
+pub fn render(scene: &Scene, width: u32, height: u32, samples: u32, depth: u32) -> RgbImage {
+    let settings = RenderSettings::new(width, height, samples, depth);
+    render_frame(scene, &settings, |_| ()).image
+}
+
+/// Same as `render`, but returns the linear radiance of each pixel, row by row from the top.
+pub fn render_hdr(scene: &Scene, width: u32, height: u32, samples: u32, depth: u32) -> Vec<Color> {
+    let settings = RenderSettings::new(width, height, samples, depth);
+    render_frame(scene, &settings, |_| ()).radiance
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::ThreadPoolBuilder;
+
+    use super::*;
+    use crate::scene::{get_scene, SceneType};
+
+    #[test]
+    fn same_seed_gives_the_same_image_on_any_number_of_threads() {
+        let scene = get_scene(SceneType::CornellBox, 1., None);
+        let settings = RenderSettings {
+            seed: 42,
+            ..RenderSettings::new(24, 24, 4, 6)
+        };
+        let render_on = |threads: usize| {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| render_frame(&scene, &settings, |_| ()).radiance)
+        };
+
+        let radiance = render_on(1);
+        assert_eq!(render_on(3), radiance);
+
+        // Another seed gives other noise
+        let settings = RenderSettings {
+            seed: 43,
+            ..settings
+        };
+        assert_ne!(render_frame(&scene, &settings, |_| ()).radiance, radiance);
+    }
+}
@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+
+use rand::{rngs::SmallRng, Error, RngCore, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Handle to the calling thread's random number generator.
+///
+/// The renderer reseeds it at the start of every pixel from the pixel coordinates and the global
+/// seed, so that a pixel always gets the same random numbers whichever thread renders it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelRng;
+
+pub fn rng() -> PixelRng {
+    PixelRng
+}
+
+pub fn set_seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+pub fn seed_pixel(x: u32, y: u32, seed: u64) {
+    // `seed_from_u64` scrambles its input, so neighboring pixels still get unrelated streams
+    set_seed(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ ((y as u64) << 32 | x as u64));
+}
+
+impl RngCore for PixelRng {
+    fn next_u32(&mut self) -> u32 {
+        RNG.with(|rng| rng.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        RNG.with(|rng| rng.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn pixel_stream(x: u32, y: u32, seed: u64) -> Vec<u32> {
+        seed_pixel(x, y, seed);
+        (0..8).map(|_| rng().gen()).collect()
+    }
+
+    #[test]
+    fn same_pixel_and_seed_give_the_same_numbers() {
+        let stream = pixel_stream(12, 34, 5);
+
+        // Whatever the thread, and whatever was drawn before
+        let _: [u32; 3] = rng().gen();
+        assert_eq!(pixel_stream(12, 34, 5), stream);
+        let other_thread = std::thread::spawn(|| pixel_stream(12, 34, 5));
+        assert_eq!(other_thread.join().unwrap(), stream);
+    }
+
+    #[test]
+    fn neighboring_pixels_and_seeds_are_decorrelated() {
+        let stream = pixel_stream(12, 34, 5);
+
+        for other in [
+            pixel_stream(13, 34, 5),
+            pixel_stream(12, 35, 5),
+            pixel_stream(34, 12, 5),
+            pixel_stream(12, 34, 6),
+        ] {
+            assert!(stream.iter().zip(other.iter()).all(|(a, b)| a != b));
+        }
+    }
+}
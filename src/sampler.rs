@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::rng::{rng, PixelRng};
+
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+// (degree, coefficients, initial direction numbers) of the primitive polynomials used by the
+// Sobol dimensions after the first one (from Joe & Kuo's new-joe-kuo-6.21201 table)
+const SOBOL_POLYNOMIALS: [(u32, u32, [u32; 5]); 7] = [
+    (1, 0, [1, 0, 0, 0, 0]),
+    (2, 1, [1, 3, 0, 0, 0]),
+    (3, 1, [1, 3, 1, 0, 0]),
+    (3, 2, [1, 1, 1, 0, 0]),
+    (4, 1, [1, 1, 3, 3, 0]),
+    (4, 4, [1, 3, 5, 13, 0]),
+    (5, 2, [1, 1, 5, 5, 17]),
+];
+
+const SOBOL_DIMENSIONS: usize = SOBOL_POLYNOMIALS.len() + 1;
+const SOBOL_MATRICES: [[u32; 32]; SOBOL_DIMENSIONS] = sobol_matrices();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerType {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl FromStr for SamplerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(SamplerType::Random),
+            "stratified" => Ok(SamplerType::Stratified),
+            "halton" => Ok(SamplerType::Halton),
+            "sobol" => Ok(SamplerType::Sobol),
+            _ => Err(format!("unknown sampler `{}`", s)),
+        }
+    }
+}
+
+/// Generates the sample values of a single pixel.
+///
+/// Each camera sample consumes consecutive dimensions (pixel offset, lens, time...). The
+/// stratified sampler splits every pair of dimensions into a jittered n×n grid when the sample
+/// count is a perfect square, and falls back to random values otherwise. The
+/// low-discrepancy sequences are decorrelated between pixels with a per-pixel Cranley-Patterson
+/// rotation (Halton) or random digital shift (Sobol).
+pub struct Sampler {
+    sampler_type: SamplerType,
+    pixel_hash: u32,
+    // Side of the stratified grid, 0 when the sample count isn't a perfect square
+    strata: u32,
+    index: u32,
+    dimension: u32,
+    rng: PixelRng,
+}
+
+impl Sampler {
+    pub fn new(sampler_type: SamplerType, x: u32, y: u32, samples: u32) -> Self {
+        let strata = (samples as f64).sqrt().round() as u32;
+
+        Sampler {
+            sampler_type,
+            pixel_hash: hash(x ^ hash(y)),
+            strata: if strata * strata == samples {
+                strata
+            } else {
+                0
+            },
+            index: 0,
+            dimension: 0,
+            rng: rng(),
+        }
+    }
+
+    /// Starts the `index`-th sample of the pixel, resetting the dimension counter.
+    pub fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    pub fn next_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        match self.sampler_type {
+            SamplerType::Stratified if self.strata > 0 => {
+                // Shuffle the cells of each pair of dimensions so that they aren't correlated
+                let cells = self.strata * self.strata;
+                let offset = hash(self.pixel_hash ^ hash(dimension / 2)) % cells;
+                let cell = (self.index + offset) % cells;
+                let stratum = if dimension.is_multiple_of(2) {
+                    cell % self.strata
+                } else {
+                    cell / self.strata
+                };
+                let x = (stratum as f32 + self.rng.gen::<f32>()) / self.strata as f32;
+                x.min(1. - f32::EPSILON)
+            }
+            SamplerType::Halton if (dimension as usize) < PRIMES.len() => {
+                let offset = to_unit_float(hash(self.pixel_hash ^ hash(dimension)));
+                let x = radical_inverse(PRIMES[dimension as usize], self.index) + offset;
+                x.fract()
+            }
+            SamplerType::Sobol if (dimension as usize) < SOBOL_DIMENSIONS => {
+                let shift = hash(self.pixel_hash ^ hash(dimension));
+                to_unit_float(sobol(dimension as usize, self.index) ^ shift)
+            }
+            _ => self.rng.gen(),
+        }
+    }
+
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        let x = self.next_1d();
+        let y = self.next_1d();
+        (x, y)
+    }
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inv_base = 1. / base as f64;
+    let mut inv_base_n = 1.;
+    let mut reversed: u64 = 0;
+
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+
+    ((reversed as f64 * inv_base_n) as f32).min(1. - f32::EPSILON)
+}
+
+const fn sobol_matrices() -> [[u32; 32]; SOBOL_DIMENSIONS] {
+    let mut v = [[0u32; 32]; SOBOL_DIMENSIONS];
+
+    let mut i = 0;
+    while i < 32 {
+        v[0][i] = 1 << (31 - i);
+        i += 1;
+    }
+
+    let mut dimension = 1;
+    while dimension < SOBOL_DIMENSIONS {
+        let (s, a, m) = SOBOL_POLYNOMIALS[dimension - 1];
+        let s = s as usize;
+
+        let mut i = 0;
+        while i < s {
+            v[dimension][i] = m[i] << (31 - i);
+            i += 1;
+        }
+        while i < 32 {
+            v[dimension][i] = v[dimension][i - s] ^ (v[dimension][i - s] >> s);
+            let mut k = 1;
+            while k < s {
+                v[dimension][i] ^= ((a >> (s - 1 - k)) & 1) * v[dimension][i - k];
+                k += 1;
+            }
+            i += 1;
+        }
+        dimension += 1;
+    }
+
+    v
+}
+
+fn sobol(dimension: usize, mut index: u32) -> u32 {
+    let mut out = 0;
+    let mut bit = 0;
+
+    while index > 0 {
+        if index & 1 == 1 {
+            out ^= SOBOL_MATRICES[dimension][bit];
+        }
+        index >>= 1;
+        bit += 1;
+    }
+
+    out
+}
+
+pub fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+pub fn to_unit_float(x: u32) -> f32 {
+    // Keep the 24 most significant bits so that the result stays strictly below 1
+    (x >> 8) as f32 / (1 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stratified_samples_fill_one_cell_each() {
+        for (x, y) in [(0, 0), (3, 7), (120, 45)] {
+            let mut sampler = Sampler::new(SamplerType::Stratified, x, y, 16);
+            // Every pair of dimensions is stratified, not just the pixel offset
+            let mut cells = [[[0; 4]; 4]; 3];
+
+            for i in 0..16 {
+                sampler.start_sample(i);
+                for pair in cells.iter_mut() {
+                    let (u, v) = sampler.next_2d();
+                    pair[(4. * u) as usize][(4. * v) as usize] += 1;
+                }
+            }
+
+            assert_eq!(cells, [[[1; 4]; 4]; 3]);
+        }
+    }
+
+    #[test]
+    fn other_sample_counts_fall_back_to_random_values() {
+        let mut sampler = Sampler::new(SamplerType::Stratified, 0, 0, 10);
+
+        assert_eq!(sampler.strata, 0);
+        for i in 0..10 {
+            sampler.start_sample(i);
+            let (u, v) = sampler.next_2d();
+            assert!((0. ..1.).contains(&u) && (0. ..1.).contains(&v));
+        }
+    }
+}
@@ -1,37 +1,254 @@
-use std::sync::Arc;
+use std::{error::Error, path::Path, str::FromStr, sync::Arc};
 
-use glam::{vec3, vec3a, Affine3A};
-use rand::{thread_rng, Rng};
+use glam::{vec3, vec3a};
+use image::{DynamicImage, ImageBuffer, Luma};
+use rand::Rng;
 
 use crate::{
+    background::{Background, Sun},
     camera::Camera,
     geometry::{
         aarect::{XYRect, XZRect, YZRect},
         constant_medium::ConstantMedium,
-        cuboid::Cuboid,
+        cuboid::{AaBox, Cuboid},
+        height_field::HeightField,
         sphere::{MovingSphere, Sphere},
         transform::{RotateY, Translate},
         triangle::Triangle,
-        BVHNode, FlipFace, Hittable, Hittables,
+        triangle_mesh::TriangleMesh,
+        volumetric_medium::VolumetricMedium,
+        BVHNode, FlipFace, Hittable, Hittables, WeightedHittables,
     },
     material::{
-        texture::{Checker, ImageTexture, Noise},
+        texture::{Checker, ImageTexture, Noise, NoiseStyle},
         DiffuseLight,
     },
-    material::{Dielectric, Lambertian, Metal},
-    vec3::{random_vector, Color},
+    material::{Dielectric, HenyeyGreenstein, Lambertian, Metal, Translucent},
+    ray::Ray,
+    rng::rng,
+    vec3::{luminance, random_vector, Color},
 };
 
-pub struct Scene {
+/// A renderable scene. The world defaults to a dynamic BVH of `Arc<dyn Hittable>`, but any
+/// concrete `Hittable` can be used instead so that its intersection code gets inlined.
+pub struct Scene<W: Hittable = Arc<dyn Hittable>> {
     pub camera: Camera,
     pub lights: Hittables,
-    pub world: Arc<dyn Hittable>,
-    pub background: Color,
+    pub world: W,
+    pub background: Background,
+    pub time0: f32,
+    pub time1: f32,
+}
+
+impl Scene {
+    /// Builds a scene whose shutter interval `[time0, time1]` drives both the camera ray times
+    /// and the bounding boxes of moving objects in the BVH.
+    pub fn new(
+        mut camera: Camera,
+        objects: Hittables,
+        lights: Hittables,
+        background: Background,
+        time0: f32,
+        time1: f32,
+    ) -> Scene {
+        camera.set_shutter(time0, time1);
+
+        Scene {
+            world: BVHNode::build(objects, time0, time1),
+            camera,
+            background,
+            lights,
+            time0,
+            time1,
+        }
+    }
+}
+
+// Vertical field of view of the camera framing single meshes, in degrees
+const MESH_VFOV: f32 = 30.;
+
+// Width of the terrains made from heightmaps, and how far their white points rise above the
+// black ones, in world units
+const TERRAIN_SIZE: f32 = 10.;
+const TERRAIN_HEIGHT: f32 = 1.5;
+
+impl Scene {
+    /// Shows a single mesh loaded from a file without camera or lights (OBJ, STL...) under a sky
+    /// gradient, seen from the +z side by a camera framing its bounding box. The mesh is made
+    /// watertight, so that the sky can't show through the edges between its triangles.
+    pub fn from_mesh(mut mesh: TriangleMesh) -> Scene {
+        mesh.set_watertight(true);
+        let aabb = mesh.bounding_box(0., 1.).unwrap();
+        let camera = Camera::frame(&aabb, 1., MESH_VFOV, 0.);
+
+        Scene::new(
+            camera,
+            vec![Arc::new(mesh)],
+            Vec::new(),
+            Background::Gradient {
+                top: Color::new(0.5, 0.7, 1.),
+                bottom: Color::new(1., 1., 1.),
+            },
+            0.,
+            1.,
+        )
+    }
+
+    /// Shows a grayscale heightmap image as a terrain `TERRAIN_SIZE` wide, lit by a low
+    /// afternoon sun and seen from above its +z side.
+    pub fn from_heightmap_file<P: AsRef<Path>>(path: P) -> Result<Scene, Box<dyn Error>> {
+        let image =
+            image::open(&path).map_err(|err| format!("{}: {}", path.as_ref().display(), err))?;
+        // Widening 8-bit samples by a shift would leave white short of the full height
+        let heights = match image {
+            DynamicImage::ImageLuma16(heights) => heights,
+            image => {
+                let gray = image.to_luma8();
+                ImageBuffer::from_fn(gray.width(), gray.height(), |x, z| {
+                    Luma([257 * gray.get_pixel(x, z)[0] as u16])
+                })
+            }
+        };
+        let samples = heights.width().max(heights.height()).max(2);
+        let spacing = TERRAIN_SIZE / (samples - 1) as f32;
+        let material = Arc::new(Lambertian::from_rgb(0.45, 0.5, 0.35));
+        let terrain = HeightField::new(&heights, spacing, TERRAIN_HEIGHT, material);
+
+        let camera = Camera::look_at(
+            vec3(0., 0.8, 0.9) * TERRAIN_SIZE,
+            vec3(0., 0.2 * TERRAIN_HEIGHT, 0.),
+            vec3(0., 1., 0.),
+            1.,
+            40.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+
+        // About 25 degrees above the horizon, so that the relief casts shadows
+        let sun = Arc::new(Sun::new(
+            vec3a(-1., 0.5, -0.3),
+            Color::new(3., 2.8, 2.5),
+            0.53,
+        ));
+        let background = Background::Sun {
+            sky: Box::new(Background::Gradient {
+                top: Color::new(0.3, 0.5, 0.9),
+                bottom: Color::new(0.8, 0.85, 0.9),
+            }),
+            sun: sun.clone(),
+        };
+
+        Ok(Scene::new(
+            camera,
+            vec![Arc::new(terrain)],
+            vec![sun],
+            background,
+            0.,
+            1.,
+        ))
+    }
+}
+
+impl<W: Hittable> Scene<W> {
+    /// Builds a scene around an already assembled world, e.g. a `PrimitiveBVH` for small static
+    /// scenes where the dynamic dispatch of the default world isn't wanted.
+    pub fn with_world(
+        mut camera: Camera,
+        world: W,
+        lights: Hittables,
+        background: Background,
+        time0: f32,
+        time1: f32,
+    ) -> Scene<W> {
+        camera.set_shutter(time0, time1);
+
+        Scene {
+            world,
+            camera,
+            background,
+            lights,
+            time0,
+            time1,
+        }
+    }
+
+    /// Has the lights sampled in proportion to the power they send toward the center of the
+    /// world rather than uniformly. See `LightSampling::Power`.
+    pub fn weight_lights_by_power(&mut self) {
+        let center = match self.world.bounding_box(self.time0, self.time1) {
+            Some(aabb) if self.lights.len() > 1 => aabb.centroid(),
+            _ => return,
+        };
+
+        let weights: Vec<f32> = self
+            .lights
+            .iter()
+            .map(|light| {
+                // Unoccluded radiance from the light divided by the density of its direction,
+                // whose average is the power reaching the center
+                let sum: f32 = (0..LIGHT_POWER_SAMPLES)
+                    .map(|_| {
+                        let direction = light.random(center);
+                        let pdf = light.pdf_value(center, direction);
+                        let ray = Ray::new(center, direction, self.time0);
+                        match light.hit(&ray, 0.0001, f32::INFINITY) {
+                            Some(rec) if pdf > 0. => {
+                                luminance(rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.p)) / pdf
+                            }
+                            _ => 0.,
+                        }
+                    })
+                    .sum();
+                sum / LIGHT_POWER_SAMPLES as f32
+            })
+            .collect();
+
+        // A share of uniform selection keeps the lights that look dark from the center, or that
+        // can't be hit like the sun, from never being sampled
+        let total: f32 = weights.iter().sum();
+        let uniform = total / weights.len() as f32;
+        let weights: Vec<f32> = weights
+            .iter()
+            .map(|weight| (1. - UNIFORM_LIGHT_SHARE) * weight + UNIFORM_LIGHT_SHARE * uniform)
+            .collect();
+
+        let lights = std::mem::take(&mut self.lights);
+        self.lights = vec![Arc::new(WeightedHittables::new(lights, &weights))];
+    }
+}
+
+// Samples per light of the estimate of their power
+const LIGHT_POWER_SAMPLES: u32 = 256;
+
+// Part of the odds of picking each light that is spread uniformly over all of them
+const UNIFORM_LIGHT_SHARE: f32 = 0.1;
+
+// How direct lighting picks which light to sample a direction toward: with equal odds, or in
+// proportion to their estimated power so that small bright lights aren't drowned out by large
+// dim ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightSampling {
+    Uniform,
+    Power,
+}
+
+impl FromStr for LightSampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uniform" => Ok(LightSampling::Uniform),
+            "power" => Ok(LightSampling::Power),
+            _ => Err(format!("unknown light sampling `{}`", s)),
+        }
+    }
 }
 
 #[allow(unused)]
 fn random_scene() -> Hittables {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     let mut world: Hittables = Vec::new();
 
     let pertex = Arc::new(Noise::new(4.));
@@ -72,7 +289,7 @@ fn random_scene() -> Hittables {
                     let material = Arc::new(Metal { albedo, fuzziness });
                     world.push(Arc::new(Sphere::new(center, 0.2, material)))
                 } else {
-                    let material = Arc::new(Dielectric { ir: 1.5 });
+                    let material = Arc::new(Dielectric::new(1.5));
                     world.push(Arc::new(Sphere::new(center, 0.2, material)))
                 }
             }
@@ -81,7 +298,7 @@ fn random_scene() -> Hittables {
 
     let material = Arc::new(Lambertian::from_rgb(0.4, 0.2, 0.1));
     world.push(Arc::new(Sphere::new(vec3a(-4., 1., 0.), 1., material)));
-    let material = Arc::new(Dielectric { ir: 1.5 });
+    let material = Arc::new(Dielectric::new(1.5));
     world.push(Arc::new(Sphere::new(vec3a(0., 1., 0.), 1., material)));
     let img_tex = Arc::new(ImageTexture::from_file("./earthmap.jpg"));
     let img_mat = Arc::new(Lambertian::new(img_tex));
@@ -123,18 +340,32 @@ fn two_spheres() -> Hittables {
 fn perlin_spheres() -> Hittables {
     let mut world: Hittables = Vec::new();
 
-    let pertex = Arc::new(Noise::new(4.));
+    let marble = Arc::new(Noise::new(4.));
+    let mut wood = Noise::new(4.);
+    wood.set_style(NoiseStyle::Wood);
+    let mut turbulence = Noise::new(4.);
+    turbulence.set_style(NoiseStyle::Turbulence);
 
     world.push(Arc::new(Sphere::new(
         vec3a(0., -1000., 0.),
         1000.,
-        Arc::new(Lambertian::new(pertex.clone())),
+        Arc::new(Lambertian::new(marble.clone())),
     )));
 
     world.push(Arc::new(Sphere::new(
         vec3a(0., 1., 0.),
         1.,
-        Arc::new(Lambertian::new(pertex)),
+        Arc::new(Lambertian::new(marble)),
+    )));
+    world.push(Arc::new(Sphere::new(
+        vec3a(0., 1., -2.5),
+        1.,
+        Arc::new(Lambertian::new(Arc::new(wood))),
+    )));
+    world.push(Arc::new(Sphere::new(
+        vec3a(0., 1., 2.5),
+        1.,
+        Arc::new(Lambertian::new(Arc::new(turbulence))),
     )));
 
     world
@@ -224,7 +455,7 @@ fn cornell_box() -> Hittables {
     //     albedo: Color::new(0.8, 0.85, 0.88),
     //     fuzziness: 0.,
     // });
-    let mut box1: Arc<dyn Hittable> = Arc::new(Cuboid::new(
+    let mut box1: Arc<dyn Hittable> = Arc::new(AaBox::new(
         vec3a(0., 0., 0.),
         vec3a(165., 330., 165.),
         white.clone(),
@@ -233,7 +464,7 @@ fn cornell_box() -> Hittables {
     box1 = Arc::new(Translate::new(box1.clone(), vec3a(265., 0., 295.)));
     world.push(box1);
 
-    let mut box2: Arc<dyn Hittable> = Arc::new(Cuboid::new(
+    let mut box2: Arc<dyn Hittable> = Arc::new(AaBox::new(
         vec3a(0., 0., 0.),
         vec3a(165., 165., 165.),
         white,
@@ -256,7 +487,9 @@ fn cornell_triangle() -> Hittables {
 
     world.push(Arc::new(YZRect::new(0., 555., 0., 555., 555., green)));
     world.push(Arc::new(YZRect::new(0., 555., 0., 555., 0., red)));
-    world.push(Arc::new(XZRect::new(213., 343., 227., 332., 554., light)));
+    world.push(Arc::new(FlipFace {
+        hittable: Arc::new(XZRect::new(213., 343., 227., 332., 554., light)),
+    }));
     world.push(Arc::new(XZRect::new(0., 555., 0., 555., 0., white.clone())));
     world.push(Arc::new(XZRect::new(
         0.,
@@ -273,7 +506,7 @@ fn cornell_triangle() -> Hittables {
         fuzziness: 0.,
     });
     // let mat = Arc::new(Lambertian::new(Arc::new(Noise::new(0.07))));
-    // let mat = Arc::new(Dielectric { ir: 1.5 });
+    // let mat = Arc::new(Dielectric::new(1.5));
     world.push(Arc::new(Triangle::new(
         vec3a(250., 0., 400.),
         vec3a(100., 150., 400.),
@@ -285,8 +518,10 @@ fn cornell_triangle() -> Hittables {
 }
 
 #[allow(unused)]
-fn final_scene() -> Hittables {
-    let mut rng = thread_rng();
+// With an `anisotropy`, the media scatter light with a Henyey-Greenstein phase function of that
+// `g` rather than evenly
+fn final_scene(anisotropy: Option<f32>) -> Hittables {
+    let mut rng = rng();
 
     let mut world: Hittables = Vec::new();
 
@@ -302,7 +537,7 @@ fn final_scene() -> Hittables {
             let z0 = -1000. + (j as f32) * w;
             let y0 = 0.;
             let x1 = x0 + w;
-            let y1: f32 = rng.gen_range((1.)..(101.));
+            let y1: f32 = rng.gen_range(1. ..101.);
             let z1 = z0 + w;
 
             boxes1.push(Arc::new(Cuboid::new(
@@ -313,10 +548,12 @@ fn final_scene() -> Hittables {
         }
     }
 
-    world.push(BVHNode::new(boxes1, 0., 1.));
+    world.push(BVHNode::build(boxes1, 0., 1.));
 
     let light = Arc::new(DiffuseLight::from_color(Color::new(7., 7., 7.)));
-    world.push(Arc::new(XZRect::new(123., 423., 147., 412., 554., light)));
+    world.push(Arc::new(FlipFace {
+        hittable: Arc::new(XZRect::new(123., 423., 147., 412., 554., light)),
+    }));
 
     let center0 = vec3a(400., 400., 200.);
     let center1 = center0 + vec3a(30., 0., 0.);
@@ -333,7 +570,7 @@ fn final_scene() -> Hittables {
     world.push(Arc::new(Sphere::new(
         vec3a(260., 150., 45.),
         45.,
-        Arc::new(Dielectric { ir: 1.5 }),
+        Arc::new(Dielectric::new(1.5)),
     )));
     world.push(Arc::new(Sphere::new(
         vec3a(0., 150., 145.),
@@ -346,24 +583,41 @@ fn final_scene() -> Hittables {
     let boundary = Arc::new(Sphere::new(
         vec3a(360., 150., 145.),
         70.,
-        Arc::new(Dielectric { ir: 1.5 }),
+        Arc::new(Dielectric::new(1.5)),
     ));
     world.push(boundary.clone());
-    world.push(Arc::new(ConstantMedium::from_color(
-        boundary,
-        0.2,
-        Color::new(0.2, 0.4, 0.9),
-    )));
     let fog = Arc::new(Sphere::new(
         vec3a(0., 0., 0.),
         5000.,
-        Arc::new(Dielectric { ir: 1.5 }),
+        Arc::new(Dielectric::new(1.5)),
     ));
-    world.push(Arc::new(ConstantMedium::from_texture(
-        fog,
-        0.0001,
-        Arc::new(ImageTexture::from_file("./earthmap.jpg")),
-    )));
+    let fog_texture = Arc::new(ImageTexture::from_file("./earthmap.jpg"));
+    match anisotropy {
+        Some(g) => {
+            world.push(Arc::new(ConstantMedium::with_phase_function(
+                boundary,
+                0.2,
+                Arc::new(HenyeyGreenstein::from_color(Color::new(0.2, 0.4, 0.9), g)),
+            )));
+            world.push(Arc::new(ConstantMedium::with_phase_function(
+                fog,
+                0.0001,
+                Arc::new(HenyeyGreenstein::new(fog_texture, g)),
+            )));
+        }
+        None => {
+            world.push(Arc::new(ConstantMedium::from_color(
+                boundary,
+                0.2,
+                Color::new(0.2, 0.4, 0.9),
+            )));
+            world.push(Arc::new(ConstantMedium::from_texture(
+                fog,
+                0.0001,
+                fog_texture,
+            )));
+        }
+    }
     let emat = Arc::new(Lambertian::new(Arc::new(ImageTexture::from_file(
         "earthmap.jpg",
     ))));
@@ -383,14 +637,103 @@ fn final_scene() -> Hittables {
     }
 
     world.push(Arc::new(Translate::new(
-        Arc::new(RotateY::new(BVHNode::new(boxes2, 0., 1.), 15.)),
+        Arc::new(RotateY::new(BVHNode::build(boxes2, 0., 1.), 15.)),
         vec3a(-100., 270., 395.),
     )));
 
     world
 }
 
-#[allow(dead_code)]
+// Wisps of Perlin turbulence in a box under the light of the final scene
+fn cloud(anisotropy: Option<f32>) -> Arc<dyn Hittable> {
+    let boundary = Arc::new(AaBox::new(
+        vec3a(-150., 330., 50.),
+        vec3a(120., 450., 300.),
+        Arc::new(Lambertian::from_rgb(1., 1., 1.)),
+    ));
+    let mut density = Noise::new(0.05);
+    density.set_style(NoiseStyle::Turbulence);
+    let (density, max_density) = (Arc::new(density), 0.05);
+
+    let white = Color::new(0.9, 0.9, 0.9);
+    match anisotropy {
+        Some(g) => Arc::new(VolumetricMedium::new(
+            boundary,
+            density,
+            max_density,
+            Arc::new(HenyeyGreenstein::from_color(white, g)),
+        )),
+        None => Arc::new(VolumetricMedium::from_color(
+            boundary,
+            density,
+            max_density,
+            white,
+        )),
+    }
+}
+
+#[allow(unused)]
+fn backlit_paper() -> Hittables {
+    let mut world: Hittables = Vec::new();
+
+    let ground = Arc::new(Lambertian::from_rgb(0.5, 0.5, 0.5));
+    world.push(Arc::new(XZRect::new(-10., 10., -10., 10., 0., ground)));
+
+    let paper = Arc::new(Translucent::new(
+        Color::new(0.8, 0.8, 0.75),
+        Color::new(0.9, 0.75, 0.5),
+        0.6,
+    ));
+    world.push(Arc::new(XYRect::new(-1., 1., 0., 2., 0., paper)));
+
+    let light = Arc::new(DiffuseLight::from_color(Color::new(8., 8., 8.)));
+    world.push(Arc::new(FlipFace {
+        hittable: Arc::new(XYRect::new(-0.5, 0.5, 0.5, 1.5, 1., light)),
+    }));
+
+    world
+}
+
+#[allow(unused)]
+fn outdoor() -> Hittables {
+    let mut world: Hittables = Vec::new();
+
+    let ground = Arc::new(Lambertian::new(Arc::new(Checker::from_colors(
+        Color::new(0.35, 0.4, 0.3),
+        Color::new(0.55, 0.6, 0.5),
+    ))));
+    world.push(Arc::new(Sphere::new(vec3a(0., -1000., 0.), 1000., ground)));
+
+    let white = Arc::new(Lambertian::from_rgb(0.73, 0.73, 0.73));
+    world.push(Arc::new(AaBox::new(
+        vec3a(-3., 0., -1.),
+        vec3a(-1.5, 1.5, 0.5),
+        white.clone(),
+    )));
+    // Thin pole whose shadow shows the size of the sun
+    world.push(Arc::new(AaBox::new(
+        vec3a(2.45, 0., -0.05),
+        vec3a(2.55, 3., 0.05),
+        white,
+    )));
+
+    world.push(Arc::new(Sphere::new(
+        vec3a(0., 1., 0.),
+        1.,
+        Arc::new(Lambertian::from_rgb(0.7, 0.2, 0.15)),
+    )));
+    world.push(Arc::new(Sphere::new(
+        vec3a(1.2, 0.5, 1.8),
+        0.5,
+        Arc::new(Metal {
+            albedo: Color::new(0.8, 0.85, 0.88),
+            fuzziness: 0.05,
+        }),
+    )));
+
+    world
+}
+
 pub enum SceneType {
     Random,
     TwoSpheres,
@@ -400,83 +743,167 @@ pub enum SceneType {
     CornellBox,
     CornellTriangle,
     FinalScene,
+    HazyFinalScene,
+    CloudyFinalScene,
+    BacklitPaper,
+    Outdoor,
 }
 
-#[allow(unused)]
-pub fn get_scene(scene_type: SceneType, aspect_ratio: f32) -> Scene {
-    let vup = vec3a(0., 1., 0.);
+impl FromStr for SceneType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(SceneType::Random),
+            "two-spheres" => Ok(SceneType::TwoSpheres),
+            "perlin-spheres" => Ok(SceneType::PerlinSpheres),
+            "earth" => Ok(SceneType::Earth),
+            "rect-light" => Ok(SceneType::RectLight),
+            "cornell" => Ok(SceneType::CornellBox),
+            "cornell-triangle" => Ok(SceneType::CornellTriangle),
+            "final" => Ok(SceneType::FinalScene),
+            "hazy-final" => Ok(SceneType::HazyFinalScene),
+            "cloud" => Ok(SceneType::CloudyFinalScene),
+            "backlit-paper" => Ok(SceneType::BacklitPaper),
+            "outdoor" => Ok(SceneType::Outdoor),
+            _ => Err(format!("unknown scene `{}`", s)),
+        }
+    }
+}
+
+/// Builds a built-in scene. `anisotropy` is the Henyey-Greenstein `g` the participating media of
+/// the final scenes scatter light with, from -1 (backward) to 1 (forward), rather than evenly.
+pub fn get_scene(scene_type: SceneType, aspect_ratio: f32, anisotropy: Option<f32>) -> Scene {
     let dist_to_focus = 10.;
+    let (time0, time1) = (0., 1.);
 
     match scene_type {
         SceneType::Random => {
             let scene = random_scene();
 
-            let lookfrom = vec3(13., -2., 3.);
+            let lookfrom = vec3(13., 2., 3.);
             let lookat = vec3(0., 0., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
             let vfov = 20.;
             let aperture = 0.1;
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0.7, 0.8, 1.),
-                lights: Vec::new(),
-            };
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
+                Vec::new(),
+                Background::Gradient {
+                    top: Color::new(0.5, 0.7, 1.),
+                    bottom: Color::new(1., 1., 1.),
+                },
+                time0,
+                time1,
+            )
         }
         SceneType::TwoSpheres => {
             let scene = two_spheres();
-            let lookfrom = vec3(13., -2., 3.);
+            let lookfrom = vec3(13., 2., 3.);
             let lookat = vec3(0., 0., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
             let vfov = 40.;
             let aperture = 0.;
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0.7, 0.8, 1.),
-                lights: Vec::new(),
-            };
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
+                Vec::new(),
+                Background::Gradient {
+                    top: Color::new(0.5, 0.7, 1.),
+                    bottom: Color::new(1., 1., 1.),
+                },
+                time0,
+                time1,
+            )
         }
         SceneType::PerlinSpheres => {
             let scene = perlin_spheres();
-            let lookfrom = vec3(13., -2., 7.);
+            let lookfrom = vec3(13., 2., 7.);
             let lookat = vec3(0., 0., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
             let vfov = 20.;
             let aperture = 0.;
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0.7, 0.8, 1.),
-                lights: Vec::new(),
-            };
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
+                Vec::new(),
+                Background::Solid(Color::new(0.7, 0.8, 1.)),
+                time0,
+                time1,
+            )
         }
         SceneType::Earth => {
             let scene = earth();
-            let lookfrom = vec3(13., -2., 3.);
+            let lookfrom = vec3(13., 2., 3.);
             let lookat = vec3(0., 0., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
             let vfov = 20.;
             let aperture = 0.;
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0.7, 0.8, 1.),
-                lights: Vec::new(),
-            };
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
+                Vec::new(),
+                Background::Solid(Color::new(0.7, 0.8, 1.)),
+                time0,
+                time1,
+            )
         }
         SceneType::RectLight => {
             let scene = simple_light();
-            let lookfrom = vec3(26., -6., 6.);
-            let lookat = vec3(0., -2., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
+            let lookfrom = vec3(26., 6., 6.);
+            let lookat = vec3(0., 2., 0.);
             let vfov = 20.;
             let aperture = 0.;
-            let mut lights: Hittables = vec![Arc::new(XYRect::new(
+            let lights: Hittables = vec![Arc::new(XYRect::new(
                 3.,
                 5.,
                 1.,
@@ -485,21 +912,34 @@ pub fn get_scene(scene_type: SceneType, aspect_ratio: f32) -> Scene {
                 Arc::new(DiffuseLight::from_color(Color::new(1., 1., 1.))),
             ))];
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0., 0., 0.),
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
                 lights,
-            };
+                Background::Solid(Color::new(0., 0., 0.)),
+                time0,
+                time1,
+            )
         }
         SceneType::CornellBox => {
             let scene = cornell_box();
-            let lookfrom = vec3(278., -278., -800.);
-            let lookat = vec3(278., -278., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
+            let lookfrom = vec3(278., 278., -800.);
+            let lookat = vec3(278., 278., 0.);
             let vfov = 40.;
             let aperture = 0.;
-            let mut lights: Hittables = vec![Arc::new(XZRect::new(
+            let lights: Hittables = vec![Arc::new(XZRect::new(
                 213.,
                 343.,
                 227.,
@@ -508,40 +948,69 @@ pub fn get_scene(scene_type: SceneType, aspect_ratio: f32) -> Scene {
                 Arc::new(DiffuseLight::from_color(Color::new(15., 15., 15.))),
             ))];
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0., 0., 0.),
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
                 lights,
-            };
+                Background::Solid(Color::new(0., 0., 0.)),
+                time0,
+                time1,
+            )
         }
         SceneType::CornellTriangle => {
             let scene = cornell_triangle();
-            let lookfrom = vec3(278., -278., -800.);
-            let lookat = vec3(278., -278., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
+            let lookfrom = vec3(278., 278., -800.);
+            let lookat = vec3(278., 278., 0.);
             let vfov = 40.;
             let aperture = 0.;
 
             let light = Arc::new(DiffuseLight::from_color(Color::new(15., 15., 15.)));
-            let mut lights: Hittables =
+            let lights: Hittables =
                 vec![Arc::new(XZRect::new(213., 343., 227., 332., 554., light))];
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0., 0., 0.),
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
                 lights,
-            };
+                Background::Solid(Color::new(0., 0., 0.)),
+                time0,
+                time1,
+            )
         }
-        SceneType::FinalScene => {
-            let scene = final_scene();
-            let lookfrom = vec3(478., -278., -600.);
-            let lookat = vec3(278., -278., 0.);
-            let camera_to_world = Affine3A::look_at_rh(lookfrom, lookat, vec3(0., 1., 0.));
+        SceneType::FinalScene | SceneType::HazyFinalScene | SceneType::CloudyFinalScene => {
+            let mut scene = final_scene(anisotropy);
+            if matches!(scene_type, SceneType::CloudyFinalScene) {
+                scene.push(cloud(anisotropy));
+            }
+            let lookfrom = vec3(478., 278., -600.);
+            let lookat = vec3(278., 278., 0.);
             let vfov = 40.;
             let aperture = 0.;
-            let mut lights: Hittables = vec![Arc::new(FlipFace {
+            let lights: Hittables = vec![Arc::new(FlipFace {
                 hittable: Arc::new(XZRect::new(
                     123.,
                     423.,
@@ -552,12 +1021,173 @@ pub fn get_scene(scene_type: SceneType, aspect_ratio: f32) -> Scene {
                 )),
             })];
 
-            return Scene {
-                world: BVHNode::new(scene, 0., 1.),
-                camera: Camera::new(aspect_ratio, vfov, 0.1, 100., camera_to_world, 0., 1.),
-                background: Color::new(0., 0., 0.),
+            let mut background = Background::Solid(Color::new(0., 0., 0.));
+            if matches!(scene_type, SceneType::HazyFinalScene) {
+                background = Background::Fog {
+                    sky: Box::new(background),
+                    color: Color::new(0.3, 0.3, 0.35),
+                    density: 0.0015,
+                    height_falloff: 0.002,
+                };
+            }
+
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(camera, scene, lights, background, time0, time1)
+        }
+        SceneType::BacklitPaper => {
+            let scene = backlit_paper();
+            let lookfrom = vec3(0., 1., -6.);
+            let lookat = vec3(0., 1., 0.);
+            let vfov = 40.;
+            let aperture = 0.;
+            let lights: Hittables = vec![Arc::new(FlipFace {
+                hittable: Arc::new(XYRect::new(
+                    -0.5,
+                    0.5,
+                    0.5,
+                    1.5,
+                    1.,
+                    Arc::new(DiffuseLight::from_color(Color::new(8., 8., 8.))),
+                )),
+            })];
+
+            let camera = Camera::look_at(
+                lookfrom,
+                lookat,
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                vfov,
+                aperture,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            Scene::new(
+                camera,
+                scene,
                 lights,
+                Background::Solid(Color::new(0.02, 0.02, 0.02)),
+                time0,
+                time1,
+            )
+        }
+        SceneType::Outdoor => {
+            let scene = outdoor();
+            let camera = Camera::look_at(
+                vec3(-2., 4., 12.),
+                vec3(0., 0.8, 0.),
+                vec3(0., 1., 0.),
+                aspect_ratio,
+                30.,
+                0.,
+                dist_to_focus,
+                time0,
+                time1,
+            );
+
+            // Afternoon sun about 35 degrees above the horizon, with its real apparent size
+            let sun = Arc::new(Sun::new(
+                vec3a(-1., 0.9, 0.6),
+                Color::new(3., 2.8, 2.5),
+                0.53,
+            ));
+            let lights: Hittables = vec![sun.clone()];
+            let background = Background::Sun {
+                sky: Box::new(Background::Gradient {
+                    top: Color::new(0.3, 0.5, 0.9),
+                    bottom: Color::new(0.8, 0.85, 0.9),
+                }),
+                sun,
             };
+
+            Scene::new(camera, scene, lights, background, time0, time1)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+    use crate::{
+        camera::Projection,
+        rng::set_seed,
+        sampler::{Sampler, SamplerType},
+    };
+
+    // How far a sphere moving one unit along x per unit of time smears across an orthographic
+    // view, through the shutter of a scene over [`time0`, `time1`]
+    fn blur_extent(time0: f32, time1: f32) -> f32 {
+        let sphere = Arc::new(MovingSphere {
+            center0: vec3a(-1., 0., -5.),
+            center1: vec3a(0., 0., -5.),
+            time0: 0.,
+            time1: 1.,
+            radius: 0.5,
+            material: Arc::new(Lambertian::from_color(Color::ONE)),
+        });
+        let mut camera = Camera::default();
+        camera.set_projection(Projection::Orthographic { height: 8. });
+        let scene = Scene::new(
+            camera,
+            vec![sphere],
+            Vec::new(),
+            Background::Solid(Color::ZERO),
+            time0,
+            time1,
+        );
+
+        // Across the middle row of the view, spanning x in [-4, 4]
+        let mut sampler = Sampler::new(SamplerType::Random, 0, 0, 1);
+        let hit_xs: Vec<f32> = (0..800)
+            .flat_map(|i| (0..64).map(move |_| (i as f32 + 0.5) / 800.))
+            .map(|u| scene.camera.get_ray(u, 0.5, &mut sampler))
+            .filter(|ray| scene.world.hit(ray, 0.001, f32::INFINITY).is_some())
+            .map(|ray| ray.origin().x)
+            .collect();
+        let min = hit_xs.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = hit_xs.iter().copied().fold(-f32::INFINITY, f32::max);
+        // Less the diameter of the sphere
+        max - min - 1.
+    }
+
+    #[test]
+    fn longer_shutters_blur_moving_spheres_further() {
+        set_seed(8);
+        let (short, long) = (blur_extent(0., 1.), blur_extent(0., 2.));
+        assert!((short - 1.).abs() < 0.1, "{}", short);
+        assert!((long - 2.).abs() < 0.1, "{}", long);
+    }
+
+    #[test]
+    fn heightmaps_load_as_sunlit_terrains() {
+        // Flat but for its white middle sample
+        let path = std::env::temp_dir().join("heightmap.png");
+        GrayImage::from_fn(3, 3, |x, z| Luma([if (x, z) == (1, 1) { 255 } else { 0 }]))
+            .save(&path)
+            .unwrap();
+
+        let scene = Scene::from_heightmap_file(&path).unwrap();
+        assert_eq!(scene.lights.len(), 1);
+        let ray = Ray::new(vec3a(0., 10., 0.), vec3a(0., -1., 0.), 0.);
+        let rec = scene.world.hit(&ray, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.p.y - TERRAIN_HEIGHT).abs() < 1e-4, "{:?}", rec.p);
+
+        let missing = std::env::temp_dir().join("missing_heightmap.png");
+        let err = Scene::from_heightmap_file(&missing).err().unwrap();
+        assert!(err.to_string().contains("missing_heightmap.png"));
+    }
+}
@@ -0,0 +1,762 @@
+use std::{error::Error, fs::read_to_string, path::Path, sync::Arc};
+
+use glam::{Affine3A, EulerRot, Quat, Vec2, Vec3, Vec3A};
+use serde::Deserialize;
+
+use crate::{
+    background::Background,
+    camera::Camera,
+    geometry::{
+        csg::{Csg, CsgOp},
+        cuboid::AaBox,
+        disk::Disk,
+        quad::Quad,
+        sphere::Sphere,
+        transform::Transform,
+        Hittable, Hittables,
+    },
+    material::{
+        texture::{
+            Checker, CheckerMapping, Gradient, GradientAxis, SolidColor, Texture, TextureTransform,
+            Worley, WorleyFeature, WorleyMetric,
+        },
+        Coated, Dielectric, DiffuseLight, Lambertian, Material, Metal, OrenNayar, RoughMetal,
+        SpotLight,
+    },
+    scene::Scene,
+    vec3::Color,
+};
+
+// JSON scene descriptions, e.g.:
+//
+// {
+//   "camera": { "lookfrom": [0, 2, 10], "lookat": [0, 1, 0], "vfov": 30 },
+//   "background": { "type": "gradient", "top": [0.5, 0.7, 1], "bottom": [1, 1, 1] },
+//   "objects": [
+//     { "type": "sphere", "center": [0, 1, 0], "radius": 1,
+//       "material": { "type": "lambertian", "albedo": [0.7, 0.2, 0.1] } },
+//     { "type": "quad", "q": [-5, 0, 5], "u": [10, 0, 0], "v": [0, 0, -10],
+//       "material": { "type": "lambertian", "albedo": { "type": "checker",
+//         "even": [0.2, 0.3, 0.1], "odd": [0.9, 0.9, 0.9], "mapping": "uv", "scale": 8 } } },
+//     { "type": "box", "min": [-1, 0, -1], "max": [1, 2, 1],
+//       "transform": { "rotate": [0, 15, 0], "translate": [3, 0, 0] },
+//       "material": { "type": "metal", "albedo": [0.8, 0.8, 0.8], "fuzziness": 0.1 } }
+//   ]
+// }
+//
+// Objects with a light material are also sampled as lights. Lights shine from the front of quads,
+// the side `u × v` points to, and of disks, the side their `normal` points to, unless they are
+// `two_sided`.
+
+#[derive(Deserialize, Debug)]
+struct SceneDescription {
+    camera: CameraDescription,
+    #[serde(default)]
+    background: BackgroundDescription,
+    objects: Vec<ObjectDescription>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CameraDescription {
+    lookfrom: [f32; 3],
+    lookat: [f32; 3],
+    #[serde(default = "default_vup")]
+    vup: [f32; 3],
+    #[serde(default = "default_one")]
+    aspect_ratio: f32,
+    vfov: f32,
+    #[serde(default)]
+    aperture: f32,
+    #[serde(default = "default_one")]
+    focus_dist: f32,
+    #[serde(default)]
+    time0: f32,
+    #[serde(default = "default_one")]
+    time1: f32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BackgroundDescription {
+    Solid { color: [f32; 3] },
+    Gradient { top: [f32; 3], bottom: [f32; 3] },
+}
+
+impl Default for BackgroundDescription {
+    fn default() -> Self {
+        BackgroundDescription::Solid { color: [0.; 3] }
+    }
+}
+
+// Space in which checkers are laid out
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum CheckerSpace {
+    #[default]
+    World,
+    Uv,
+}
+
+// Texture coordinate a gradient varies along
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum UvAxis {
+    U,
+    #[default]
+    V,
+}
+
+// Distance to the feature points of Worley noise
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum WorleyDistance {
+    #[default]
+    Euclidean,
+    Manhattan,
+}
+
+// Round cells, or the edges between them
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum WorleyPattern {
+    #[default]
+    F1,
+    F2MinusF1,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TextureDescription {
+    // `scale` squares per unit of the mapping space
+    Checker {
+        even: [f32; 3],
+        odd: [f32; 3],
+        #[serde(default)]
+        mapping: CheckerSpace,
+        #[serde(default = "default_checker_scale")]
+        scale: f32,
+    },
+    // Color ramp through `[stop, color]` pairs along a texture coordinate
+    Gradient {
+        stops: Vec<(f32, [f32; 3])>,
+        #[serde(default)]
+        axis: UvAxis,
+    },
+    // Cellular noise with `scale` cells per unit
+    Worley {
+        scale: f32,
+        #[serde(default)]
+        distance: WorleyDistance,
+        #[serde(default)]
+        pattern: WorleyPattern,
+    },
+    // `texture` looked up at texture coordinates scaled, rotated by `rotation` degrees and then
+    // offset
+    UvTransform {
+        texture: Box<TextureDescription>,
+        #[serde(default = "default_uv_scale")]
+        scale: [f32; 2],
+        #[serde(default)]
+        offset: [f32; 2],
+        #[serde(default)]
+        rotation: f32,
+    },
+}
+
+// Plain colors can be given wherever a texture is expected
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ColorOrTexture {
+    Color([f32; 3]),
+    Texture(TextureDescription),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MaterialDescription {
+    Lambertian {
+        albedo: ColorOrTexture,
+    },
+    // Rough diffuse surface whose facet slopes have a `roughness` in radians
+    OrenNayar {
+        albedo: ColorOrTexture,
+        roughness: f32,
+    },
+    Metal {
+        albedo: [f32; 3],
+        #[serde(default)]
+        fuzziness: f32,
+    },
+    // GGX microfacets, from a `roughness` of 0 for a mirror to 1
+    RoughMetal {
+        albedo: [f32; 3],
+        roughness: f32,
+    },
+    // Tinted by Beer-Lambert `absorption` coefficients per unit of distance inside, and frosted
+    // by a `roughness` between 0 and 1
+    Dielectric {
+        ior: f32,
+        #[serde(default)]
+        absorption: [f32; 3],
+        #[serde(default)]
+        roughness: f32,
+    },
+    // Clearcoat of index `ior` over a `base` material
+    Coated {
+        base: Box<MaterialDescription>,
+        ior: f32,
+    },
+    Light {
+        color: [f32; 3],
+        #[serde(default = "default_one")]
+        intensity: f32,
+        #[serde(default)]
+        two_sided: bool,
+    },
+    // Light shining along `axis` at full intensity up to `inner_angle` degrees off it, fading
+    // out at `outer_angle`
+    SpotLight {
+        color: [f32; 3],
+        #[serde(default = "default_one")]
+        intensity: f32,
+        axis: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+// Uniform scale, then rotation by Euler angles in degrees around x, y and z, then translation
+#[derive(Deserialize, Debug)]
+struct TransformDescription {
+    #[serde(default = "default_one")]
+    scale: f32,
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default)]
+    translate: [f32; 3],
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ObjectDescription {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        material: MaterialDescription,
+        transform: Option<TransformDescription>,
+    },
+    // Parallelogram spanned by the edges `u` and `v` from the corner `q`
+    Quad {
+        q: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        material: MaterialDescription,
+        transform: Option<TransformDescription>,
+    },
+    Box {
+        min: [f32; 3],
+        max: [f32; 3],
+        material: MaterialDescription,
+        transform: Option<TransformDescription>,
+    },
+    Disk {
+        center: [f32; 3],
+        normal: [f32; 3],
+        radius: f32,
+        material: MaterialDescription,
+        transform: Option<TransformDescription>,
+    },
+    // Boolean combination of two solids, `right` being carved out of `left` for a difference.
+    // Only spheres, boxes and other combinations have an inside.
+    Csg {
+        op: CsgOperation,
+        left: Box<ObjectDescription>,
+        right: Box<ObjectDescription>,
+        transform: Option<TransformDescription>,
+    },
+}
+
+fn default_vup() -> [f32; 3] {
+    [0., 1., 0.]
+}
+
+fn default_one() -> f32 {
+    1.
+}
+
+fn default_checker_scale() -> f32 {
+    10.
+}
+
+fn default_uv_scale() -> [f32; 2] {
+    [1., 1.]
+}
+
+impl TextureDescription {
+    fn build(&self) -> Arc<dyn Texture> {
+        match *self {
+            TextureDescription::Checker {
+                even,
+                odd,
+                mapping,
+                scale,
+            } => {
+                let mut checker = Checker::from_colors(Color::from(even), Color::from(odd));
+                checker.set_mapping(match mapping {
+                    CheckerSpace::World => CheckerMapping::World { scale },
+                    CheckerSpace::Uv => CheckerMapping::UV { scale },
+                });
+                Arc::new(checker)
+            }
+            TextureDescription::Gradient { ref stops, axis } => {
+                let mut gradient = Gradient::new(
+                    stops
+                        .iter()
+                        .map(|&(stop, color)| (stop, Color::from(color)))
+                        .collect(),
+                );
+                gradient.set_axis(match axis {
+                    UvAxis::U => GradientAxis::U,
+                    UvAxis::V => GradientAxis::V,
+                });
+                Arc::new(gradient)
+            }
+            TextureDescription::Worley {
+                scale,
+                distance,
+                pattern,
+            } => {
+                let mut worley = Worley::new(scale);
+                worley.set_metric(match distance {
+                    WorleyDistance::Euclidean => WorleyMetric::Euclidean,
+                    WorleyDistance::Manhattan => WorleyMetric::Manhattan,
+                });
+                worley.set_feature(match pattern {
+                    WorleyPattern::F1 => WorleyFeature::F1,
+                    WorleyPattern::F2MinusF1 => WorleyFeature::F2MinusF1,
+                });
+                Arc::new(worley)
+            }
+            TextureDescription::UvTransform {
+                ref texture,
+                scale,
+                offset,
+                rotation,
+            } => {
+                let mut transform = TextureTransform::new(texture.build());
+                transform.scale = Vec2::from(scale);
+                transform.offset = Vec2::from(offset);
+                transform.rotation = rotation;
+                Arc::new(transform)
+            }
+        }
+    }
+}
+
+impl ColorOrTexture {
+    fn build(&self) -> Arc<dyn Texture> {
+        match self {
+            ColorOrTexture::Color(color) => Arc::new(SolidColor::new(Color::from(*color))),
+            ColorOrTexture::Texture(texture) => texture.build(),
+        }
+    }
+}
+
+impl MaterialDescription {
+    fn build(&self) -> Arc<dyn Material> {
+        match *self {
+            MaterialDescription::Lambertian { ref albedo } => {
+                Arc::new(Lambertian::new(albedo.build()))
+            }
+            MaterialDescription::OrenNayar {
+                ref albedo,
+                roughness,
+            } => Arc::new(OrenNayar::new(albedo.build(), roughness)),
+            MaterialDescription::Metal { albedo, fuzziness } => Arc::new(Metal {
+                albedo: Color::from(albedo),
+                fuzziness,
+            }),
+            MaterialDescription::RoughMetal { albedo, roughness } => {
+                Arc::new(RoughMetal::new(Color::from(albedo), roughness))
+            }
+            MaterialDescription::Dielectric {
+                ior,
+                absorption,
+                roughness,
+            } => {
+                let mut dielectric = Dielectric::with_absorption(ior, Color::from(absorption));
+                dielectric.set_roughness(roughness);
+                Arc::new(dielectric)
+            }
+            MaterialDescription::Coated { ref base, ior } => {
+                Arc::new(Coated::new(base.build(), ior))
+            }
+            MaterialDescription::Light {
+                color,
+                intensity,
+                two_sided,
+            } => {
+                let mut light = DiffuseLight::from_color(Color::from(color));
+                light.intensity = intensity;
+                light.set_two_sided(two_sided);
+                Arc::new(light)
+            }
+            MaterialDescription::SpotLight {
+                color,
+                intensity,
+                axis,
+                inner_angle,
+                outer_angle,
+            } => Arc::new(SpotLight::new(
+                intensity * Color::from(color),
+                Vec3A::from(axis),
+                inner_angle,
+                outer_angle,
+            )),
+        }
+    }
+
+    fn is_light(&self) -> bool {
+        match self {
+            MaterialDescription::Light { .. } | MaterialDescription::SpotLight { .. } => true,
+            MaterialDescription::Coated { base, .. } => base.is_light(),
+            _ => false,
+        }
+    }
+}
+
+impl TransformDescription {
+    fn affine(&self) -> Affine3A {
+        let [x, y, z] = self.rotate.map(f32::to_radians);
+        Affine3A::from_scale_rotation_translation(
+            Vec3::splat(self.scale),
+            Quat::from_euler(EulerRot::ZYX, z, y, x),
+            Vec3::from(self.translate),
+        )
+    }
+}
+
+impl ObjectDescription {
+    // The object and whether it should be sampled as a light. Spheres, quads and disks are
+    // transformed in place, boxes and combinations are instanced. Combinations are never sampled
+    // as lights.
+    fn build(&self) -> (Arc<dyn Hittable>, bool) {
+        match self {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+                transform,
+            } => {
+                let affine = transform
+                    .as_ref()
+                    .map_or(Affine3A::IDENTITY, |t| t.affine());
+                let radius = transform.as_ref().map_or(1., |t| t.scale) * radius;
+                let sphere = Sphere::new(
+                    affine.transform_point3a(Vec3A::from(*center)),
+                    radius,
+                    material.build(),
+                );
+                (Arc::new(sphere), material.is_light())
+            }
+            ObjectDescription::Quad {
+                q,
+                u,
+                v,
+                material,
+                transform,
+            } => {
+                let affine = transform
+                    .as_ref()
+                    .map_or(Affine3A::IDENTITY, |t| t.affine());
+                let quad = Quad::new(
+                    affine.transform_point3a(Vec3A::from(*q)),
+                    affine.transform_vector3a(Vec3A::from(*u)),
+                    affine.transform_vector3a(Vec3A::from(*v)),
+                    material.build(),
+                );
+                (Arc::new(quad), material.is_light())
+            }
+            ObjectDescription::Box {
+                min,
+                max,
+                material,
+                transform,
+            } => {
+                let aabox = Arc::new(AaBox::new(
+                    Vec3A::from(*min),
+                    Vec3A::from(*max),
+                    material.build(),
+                ));
+                match transform {
+                    Some(transform) => (
+                        Arc::new(Transform::new(aabox, transform.affine())),
+                        material.is_light(),
+                    ),
+                    None => (aabox, material.is_light()),
+                }
+            }
+            ObjectDescription::Disk {
+                center,
+                normal,
+                radius,
+                material,
+                transform,
+            } => {
+                let affine = transform
+                    .as_ref()
+                    .map_or(Affine3A::IDENTITY, |t| t.affine());
+                let radius = transform.as_ref().map_or(1., |t| t.scale) * radius;
+                let disk = Disk::new(
+                    affine.transform_point3a(Vec3A::from(*center)),
+                    affine.transform_vector3a(Vec3A::from(*normal)),
+                    radius,
+                    material.build(),
+                );
+                (Arc::new(disk), material.is_light())
+            }
+            ObjectDescription::Csg {
+                op,
+                left,
+                right,
+                transform,
+            } => {
+                let op = match op {
+                    CsgOperation::Union => CsgOp::Union,
+                    CsgOperation::Intersection => CsgOp::Intersection,
+                    CsgOperation::Difference => CsgOp::Difference,
+                };
+                let csg = Arc::new(Csg::new(left.build().0, right.build().0, op));
+                match transform {
+                    Some(transform) => (Arc::new(Transform::new(csg, transform.affine())), false),
+                    None => (csg, false),
+                }
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// Loads a JSON scene description: a camera, a background and a list of spheres, quads,
+    /// boxes, disks and boolean combinations of them with their materials and transforms.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scene, Box<dyn Error>> {
+        let source = read_to_string(&path)?;
+        let description: SceneDescription = serde_json::from_str(&source)
+            .map_err(|err| format!("{}: {}", path.as_ref().display(), err))?;
+
+        let mut objects: Hittables = Vec::new();
+        let mut lights: Hittables = Vec::new();
+        for object in description.objects.iter() {
+            let (object, is_light) = object.build();
+            if is_light {
+                lights.push(object.clone());
+            }
+            objects.push(object);
+        }
+
+        let camera = &description.camera;
+        let background = match description.background {
+            BackgroundDescription::Solid { color } => Background::Solid(Color::from(color)),
+            BackgroundDescription::Gradient { top, bottom } => Background::Gradient {
+                top: Color::from(top),
+                bottom: Color::from(bottom),
+            },
+        };
+
+        Ok(Scene::new(
+            Camera::look_at(
+                Vec3::from(camera.lookfrom),
+                Vec3::from(camera.lookat),
+                Vec3::from(camera.vup),
+                camera.aspect_ratio,
+                camera.vfov,
+                camera.aperture,
+                camera.focus_dist,
+                camera.time0,
+                camera.time1,
+            ),
+            objects,
+            lights,
+            background,
+            camera.time0,
+            camera.time1,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3a;
+
+    use super::*;
+    use crate::ray::Ray;
+
+    #[test]
+    fn objects_with_a_light_material_are_lights() {
+        let light = r#"{ "type": "light", "color": [1, 1, 1] }"#;
+        let grey = r#"{ "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }"#;
+        let objects = [
+            r#"{ "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": M }"#,
+            r#"{ "type": "quad", "q": [0, 0, 0], "u": [1, 0, 0], "v": [0, 1, 0], "material": M }"#,
+            r#"{ "type": "box", "min": [0, 0, 0], "max": [1, 1, 1], "material": M }"#,
+            r#"{ "type": "disk", "center": [0, 0, 0], "normal": [0, 1, 0], "radius": 1,
+                 "material": M }"#,
+            r#"{ "type": "box", "min": [0, 0, 0], "max": [1, 1, 1], "material": M,
+                 "transform": { "rotate": [0, 45, 0] } }"#,
+        ];
+
+        for object in objects {
+            let build = |material: &str| {
+                let description: ObjectDescription =
+                    serde_json::from_str(&object.replace('M', material)).unwrap();
+                description.build().1
+            };
+            assert!(build(light), "{}", object);
+            assert!(!build(grey), "{}", object);
+        }
+    }
+
+    #[test]
+    fn spotlights_on_disks_light_a_soft_edged_pool() {
+        let json = r#"{ "type": "disk", "center": [0, 2, 0], "normal": [0, -1, 0], "radius": 0.1,
+            "material": { "type": "spot_light", "color": [1, 1, 1], "intensity": 4,
+                "axis": [0, -1, 0], "inner_angle": 20, "outer_angle": 30 } }"#;
+        let (disk, is_light) = serde_json::from_str::<ObjectDescription>(json)
+            .unwrap()
+            .build();
+        assert!(is_light);
+
+        // Seen from points of the floor this many degrees off the axis, or from above
+        let emitted = |angle: f32, from_above: bool| {
+            let direction = if from_above {
+                -Vec3A::Y
+            } else {
+                vec3a(angle.to_radians().tan(), 1., 0.)
+            };
+            let ray = Ray::new(vec3a(0., 2., 0.) - 2. * direction, direction, 0.);
+            let rec = disk.hit(&ray, 0.001, f32::INFINITY).unwrap();
+            rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.p).x
+        };
+
+        assert_eq!(emitted(0., false), 4.);
+        assert_eq!(emitted(15., false), 4.);
+        assert!((0. ..4.).contains(&emitted(25., false)));
+        assert!(emitted(22., false) > emitted(28., false));
+        assert_eq!(emitted(35., false), 0.);
+        assert_eq!(emitted(0., true), 0.);
+    }
+
+    #[test]
+    fn example_scenes_load() {
+        for name in ["cornell.json", "csg.json"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("assets")
+                .join(name);
+            assert!(Scene::from_file(&path).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn combinations_are_solid() {
+        let json = r#"{ "type": "csg", "op": "difference",
+            "left": { "type": "box", "min": [-1, -1, -1], "max": [1, 1, 1], "material": M },
+            "right": { "type": "sphere", "center": [0, 0, 0], "radius": 0.5, "material": M },
+            "transform": { "translate": [0, 0, -5] } }"#;
+        let grey = r#"{ "type": "lambertian", "albedo": [0.5, 0.5, 0.5] }"#;
+        let description: ObjectDescription =
+            serde_json::from_str(&json.replace('M', grey)).unwrap();
+        let (csg, is_light) = description.build();
+        assert!(!is_light);
+
+        // Into the front wall of the box, out into the hole, and back into the wall behind it
+        let ray = Ray::new(Vec3A::ZERO, -Vec3A::Z, 0.);
+        for (t_min, t) in [(0.001, 4.), (4.1, 4.5), (4.7, 5.5)] {
+            let rec = csg.hit(&ray, t_min, f32::INFINITY).unwrap();
+            assert!((rec.t - t).abs() < 1e-4, "{} {}", rec.t, t);
+        }
+    }
+
+    #[test]
+    fn checkers_follow_their_mapping() {
+        let texture = |mapping: &str| {
+            let description: ColorOrTexture = serde_json::from_str(&format!(
+                r#"{{ "type": "checker", "even": [0, 0, 0], "odd": [1, 1, 1], {} "scale": 2 }}"#,
+                mapping
+            ))
+            .unwrap();
+            description.build()
+        };
+
+        // Two squares per unit of UV, whatever the point
+        let uv = texture(r#""mapping": "uv","#);
+        assert_eq!(uv.value(0.25, 0.25, &Vec3A::ZERO), Color::ZERO);
+        assert_eq!(uv.value(0.75, 0.25, &Vec3A::ZERO), Color::ONE);
+
+        // Solid checkers by default, whatever the UVs
+        let world = texture("");
+        let p = Vec3A::splat(0.5);
+        assert_eq!(world.value(0.25, 0.25, &p), world.value(0.75, 0.25, &p));
+        assert_ne!(world.value(0., 0., &p), world.value(0., 0., &-p));
+
+        let color: ColorOrTexture = serde_json::from_str("[0.5, 0.25, 1]").unwrap();
+        assert_eq!(
+            color.build().value(0., 0., &Vec3A::ZERO),
+            Color::new(0.5, 0.25, 1.)
+        );
+    }
+
+    #[test]
+    fn gradients_take_stops_and_an_axis() {
+        let json =
+            r#"{ "type": "gradient", "stops": [[1, [0, 0, 1]], [0, [1, 0, 0]]], "axis": "u" }"#;
+        let gradient = serde_json::from_str::<ColorOrTexture>(json)
+            .unwrap()
+            .build();
+        assert_eq!(
+            gradient.value(0.5, 0., &Vec3A::ZERO),
+            Color::new(0.5, 0., 0.5)
+        );
+        assert_eq!(
+            gradient.value(0., 0.5, &Vec3A::ZERO),
+            Color::new(1., 0., 0.)
+        );
+    }
+
+    #[test]
+    fn uv_transforms_wrap_a_texture() {
+        let json = r#"{ "type": "uv_transform", "scale": [2, 1], "offset": [0.5, 0],
+            "texture": { "type": "gradient", "stops": [[0, [0, 0, 0]], [2, [1, 1, 1]]],
+                "axis": "u" } }"#;
+        let texture = serde_json::from_str::<ColorOrTexture>(json)
+            .unwrap()
+            .build();
+        assert_eq!(texture.value(0.5, 0., &Vec3A::ZERO), Color::splat(0.75));
+    }
+
+    #[test]
+    fn worley_options_are_optional() {
+        for options in [
+            "",
+            r#", "distance": "manhattan""#,
+            r#", "pattern": "f2_minus_f1""#,
+        ] {
+            let json = format!(r#"{{ "type": "worley", "scale": 4{} }}"#, options);
+            let texture = serde_json::from_str::<ColorOrTexture>(&json)
+                .unwrap()
+                .build();
+            let value = texture.value(0., 0., &Vec3A::splat(0.3));
+            assert!((0. ..=1.).contains(&value.x), "{}", json);
+        }
+
+        let json = r#"{ "type": "worley", "scale": 4, "pattern": "f3" }"#;
+        assert!(serde_json::from_str::<ColorOrTexture>(json).is_err());
+    }
+}
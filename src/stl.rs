@@ -0,0 +1,220 @@
+use std::{convert::TryInto, error::Error, fs::read, path::Path, sync::Arc};
+
+use glam::{vec3a, Vec3A};
+
+use crate::{
+    geometry::{triangle::is_degenerate, triangle_mesh::TriangleMesh},
+    material::{Lambertian, Material},
+    scene::Scene,
+};
+
+// 80-byte header and little-endian u32 triangle count, then 50 bytes per triangle: the normal
+// and vertices as 12 f32, and a 2-byte attribute
+const STL_HEADER_SIZE: usize = 84;
+const STL_TRIANGLE_SIZE: usize = 50;
+
+// A facet as its normal and vertices
+type Facet = (Vec3A, [Vec3A; 3]);
+
+fn read_vec3(bytes: &[u8]) -> Vec3A {
+    let float = |i: usize| f32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap());
+    vec3a(float(0), float(1), float(2))
+}
+
+fn parse_binary_stl(bytes: &[u8]) -> Vec<Facet> {
+    bytes[STL_HEADER_SIZE..]
+        .chunks_exact(STL_TRIANGLE_SIZE)
+        .map(|triangle| {
+            (
+                read_vec3(&triangle[0..12]),
+                [
+                    read_vec3(&triangle[12..24]),
+                    read_vec3(&triangle[24..36]),
+                    read_vec3(&triangle[36..48]),
+                ],
+            )
+        })
+        .collect()
+}
+
+fn parse_ascii_stl(source: &str) -> Result<Vec<Facet>, String> {
+    let mut facets: Vec<Facet> = Vec::new();
+    let mut normal = Vec3A::ZERO;
+    let mut vertices: Vec<Vec3A> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let parse_vec3 = |args: &[&str]| -> Result<Vec3A, String> {
+            let mut out = Vec3A::ZERO;
+            for k in 0..3 {
+                let token = args.get(k).ok_or("missing coordinate")?;
+                out[k] = token
+                    .parse()
+                    .map_err(|_| format!("invalid coordinate `{}`", token))?;
+            }
+            Ok(out)
+        };
+
+        let result = match tokens.as_slice() {
+            ["facet", "normal", args @ ..] => parse_vec3(args).map(|n| {
+                normal = n;
+                vertices.clear();
+            }),
+            ["vertex", args @ ..] => parse_vec3(args).map(|v| vertices.push(v)),
+            ["endfacet", ..] => match vertices.as_slice() {
+                &[v0, v1, v2] => {
+                    facets.push((normal, [v0, v1, v2]));
+                    Ok(())
+                }
+                _ => Err(format!("facet with {} vertices", vertices.len())),
+            },
+            _ => Ok(()),
+        };
+        result.map_err(|err| format!("line {}: {}", i + 1, err))?;
+    }
+
+    Ok(facets)
+}
+
+// Positions and normals of the vertices of the facets, three per facet, leaving out the degenerate
+// ones
+fn facet_vertices(facets: Vec<Facet>) -> (Vec<Vec3A>, Vec<Vec3A>) {
+    let mut positions: Vec<Vec3A> = Vec::new();
+    let mut normals: Vec<Vec3A> = Vec::new();
+    for (normal, [v0, v1, v2]) in facets {
+        if is_degenerate(v0, v1, v2) {
+            continue;
+        }
+
+        let normal = if normal.length_squared() > 0. {
+            normal.normalize()
+        } else {
+            (v1 - v0).cross(v2 - v0).normalize()
+        };
+        positions.extend([v0, v1, v2]);
+        normals.extend([normal; 3]);
+    }
+    (positions, normals)
+}
+
+/// Loads a binary or ASCII STL file into a triangle mesh. Binary files are recognized by their
+/// size matching the triangle count of their header, since some of them also start with `solid`.
+/// Each facet gets its own vertices, shaded with the facet normal, or the geometric normal when
+/// the file leaves it at zero. Degenerate facets are skipped.
+pub fn load_stl<P: AsRef<Path>>(
+    path: P,
+    material: Arc<dyn Material>,
+) -> Result<TriangleMesh, Box<dyn Error>> {
+    let bytes = read(&path)?;
+    let error = |err: String| format!("{}: {}", path.as_ref().display(), err);
+
+    let is_binary = bytes.len() >= STL_HEADER_SIZE && {
+        let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+        bytes.len() == STL_HEADER_SIZE + count * STL_TRIANGLE_SIZE
+    };
+    let facets = if is_binary {
+        parse_binary_stl(&bytes)
+    } else {
+        let source = String::from_utf8(bytes).map_err(|_| error("not a valid STL file".into()))?;
+        parse_ascii_stl(&source).map_err(error)?
+    };
+
+    let (positions, normals) = facet_vertices(facets);
+    if positions.is_empty() {
+        return Err(error("no triangles".into()).into());
+    }
+
+    let triangles: Vec<[usize; 3]> = (0..positions.len() / 3)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+    Ok(TriangleMesh::new(
+        positions,
+        Some(normals),
+        None,
+        triangles,
+        material,
+    ))
+}
+
+impl Scene {
+    /// Loads an STL file as a grey diffuse mesh, see `Scene::from_mesh`.
+    pub fn from_stl_file<P: AsRef<Path>>(path: P) -> Result<Scene, Box<dyn Error>> {
+        let material = Arc::new(Lambertian::from_rgb(0.73, 0.73, 0.73));
+        Ok(Scene::from_mesh(load_stl(path, material)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ascii_facets() {
+        let source = "solid cube
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1.5 0
+    endloop
+  endfacet
+endsolid cube
+";
+        let facets = parse_ascii_stl(source).unwrap();
+        assert_eq!(
+            facets,
+            [(
+                vec3a(0., 0., 1.),
+                [vec3a(0., 0., 0.), vec3a(1., 0., 0.), vec3a(0., 1.5, 0.)]
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_ascii_errors() {
+        let source = "solid\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 1 x 0\n";
+        assert_eq!(
+            parse_ascii_stl(source),
+            Err("line 4: invalid coordinate `x`".to_owned())
+        );
+
+        let source = "solid\nfacet normal 0 0 1\nvertex 0 0 0\nvertex 1 0 0\nendfacet\n";
+        assert_eq!(
+            parse_ascii_stl(source),
+            Err("line 5: facet with 2 vertices".to_owned())
+        );
+    }
+
+    #[test]
+    fn parses_binary_facets() {
+        let mut bytes = vec![0; 80];
+        bytes.extend(1u32.to_le_bytes());
+        for x in [0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0.] {
+            bytes.extend((x as f32).to_le_bytes());
+        }
+        bytes.extend([0, 0]);
+
+        assert_eq!(
+            parse_binary_stl(&bytes),
+            [(
+                vec3a(0., 0., 1.),
+                [vec3a(0., 0., 0.), vec3a(1., 0., 0.), vec3a(0., 1., 0.)]
+            )]
+        );
+    }
+
+    #[test]
+    fn only_degenerate_facets_are_dropped() {
+        let (v0, v1, v2) = (vec3a(0., 0., 0.), vec3a(1., 0., 0.), vec3a(0., 1., 0.));
+        // A small facet with no normal, and a flat one
+        let tiny = 1e-4;
+        let facets = vec![
+            (Vec3A::ZERO, [tiny * v0, tiny * v1, tiny * v2]),
+            (vec3a(0., 0., 1.), [v0, v1, 2. * v1]),
+        ];
+
+        let (positions, normals) = facet_vertices(facets);
+        assert_eq!(positions, [tiny * v0, tiny * v1, tiny * v2]);
+        assert_eq!(normals, [vec3a(0., 0., 1.); 3]);
+    }
+}
@@ -1,13 +1,13 @@
+use std::str::FromStr;
+
 use glam::{vec3a, Vec3A};
 use image::Rgb;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
-pub fn mul(v: Vec3A, w: Vec3A) -> Vec3A {
-    vec3a(v[0] * w[0], v[1] * w[1], v[2] * w[2])
-}
+use crate::rng::rng;
 
 pub fn random_vector(min: f32, max: f32) -> Vec3A {
-    let mut rng = thread_rng();
+    let mut rng = rng();
     vec3a(
         rng.gen_range(min..max),
         rng.gen_range(min..max),
@@ -26,24 +26,71 @@ pub fn random_in_unit_sphere() -> Vec3A {
 
 pub type Color = Vec3A;
 
+// Exposure applied before the ACES curve, so that mid grey stays about where it is without tone
+// mapping
+const ACES_EXPOSURE: f32 = 0.7;
+
+// Compresses HDR colors into [0, 1] before they are gamma corrected and quantized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMap {
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ToneMap::None),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone mapping `{}`", s)),
+        }
+    }
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            ToneMap::None => color,
+            ToneMap::Reinhard => color / (Color::ONE + color),
+            // Krzysztof Narkowicz's fit of the ACES filmic curve
+            ToneMap::Aces => {
+                let x = color * ACES_EXPOSURE;
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }
+        }
+    }
+}
+
+// Rec. 709 relative luminance of a linear color
+pub fn luminance(color: Color) -> f32 {
+    color.dot(Color::new(0.2126, 0.7152, 0.0722))
+}
+
+// Scales `color` down so that its luminance doesn't exceed `max`, keeping its hue
+pub fn clamp_luminance(color: Color, max: f32) -> Color {
+    let y = luminance(color);
+    if y > max {
+        color * (max / y)
+    } else {
+        color
+    }
+}
+
 #[inline(always)]
-pub fn get_color(color: Color, samples: u32) -> Rgb<u8> {
-    // Divide color by number of samples
-    let scale = 1. / (samples as f32);
-
-    // Gamma-corrected color with gamma=2.0
-    let r = match color.x.is_nan() {
-        true => 0.,
-        false => (color.x * scale).sqrt(),
-    };
-    let g = match color.y.is_nan() {
-        true => 0.,
-        false => (color.y * scale).sqrt(),
-    };
-    let b = match color.z.is_nan() {
-        true => 0.,
-        false => (color.z * scale).sqrt(),
-    };
+pub fn get_color(color: Color, weight: f32, tone_map: ToneMap) -> Rgb<u8> {
+    // Divide color by the summed weight of the samples, dropping NaN channels
+    let scale = if weight > 0. { 1. / weight } else { 0. };
+    let channel = |c: f32| if c.is_nan() { 0. } else { (c * scale).max(0.) };
+    let color = tone_map.apply(vec3a(channel(color.x), channel(color.y), channel(color.z)));
+
+    // sRGB encoded color
+    let r = linear_to_srgb(color.x);
+    let g = linear_to_srgb(color.y);
+    let b = linear_to_srgb(color.z);
 
     Rgb([
         (256. * r.clamp(0., 0.999)) as u8,
@@ -52,6 +99,32 @@ pub fn get_color(color: Color, samples: u32) -> Rgb<u8> {
     ])
 }
 
+// sRGB transfer function: linear below 0.0031308, then a 1/2.4 power curve
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Inverse of the encoding done in `get_color` without tone mapping
+pub fn decode_color(pixel: Rgb<u8>) -> Color {
+    vec3a(
+        srgb_to_linear(pixel[0] as f32 / 255.),
+        srgb_to_linear(pixel[1] as f32 / 255.),
+        srgb_to_linear(pixel[2] as f32 / 255.),
+    )
+}
+
 pub struct OrthNormBasis {
     pub u: Vec3A,
     pub v: Vec3A,
@@ -71,3 +144,34 @@ impl OrthNormBasis {
         a[0] * self.u + a[1] * self.v + a[2] * self.w
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_encodes_mid_grey_above_gamma_2() {
+        assert!((linear_to_srgb(0.5) - 0.735).abs() < 1e-3);
+        assert_eq!(
+            get_color(Color::splat(0.5), 1., ToneMap::None),
+            Rgb([188, 188, 188])
+        );
+    }
+
+    #[test]
+    fn srgb_curve_is_continuous_and_inverted_by_the_decoding() {
+        let (below, above) = (linear_to_srgb(0.0031308), linear_to_srgb(0.0031309));
+        assert!((above - below).abs() < 1e-4);
+
+        for i in 0..=100 {
+            let c = i as f32 / 100.;
+            assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn nan_and_negative_channels_are_black() {
+        let color = vec3a(f32::NAN, -1., 1.);
+        assert_eq!(get_color(color, 1., ToneMap::None), Rgb([0, 0, 255]));
+    }
+}
@@ -0,0 +1,21 @@
+use image::{ImageBuffer, Pixel};
+
+/// Darkens a tone mapped image toward its corners, by `1 - strength * r²` where `r` is the
+/// distance to the center, 0 there and 1 in the corners. Alpha channels are left as they are.
+pub fn vignette<P: Pixel<Subpixel = u8> + 'static>(
+    image: &mut ImageBuffer<P, Vec<u8>>,
+    strength: f32,
+) {
+    if strength <= 0. {
+        return;
+    }
+
+    let (width, height) = image.dimensions();
+    let (cx, cy) = (0.5 * width as f32, 0.5 * height as f32);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let dx = (x as f32 + 0.5 - cx) / cx;
+        let dy = (y as f32 + 0.5 - cy) / cy;
+        let falloff = (1. - strength * 0.5 * (dx * dx + dy * dy)).max(0.);
+        pixel.apply_without_alpha(|c| (c as f32 * falloff).round() as u8);
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+
+use glam::{vec3, vec3a};
+use rust_ray_tracer::{
+    background::Background,
+    camera::Camera,
+    geometry::{sphere::Sphere, Hittables},
+    material::Lambertian,
+    render::{render, render_frame, render_hdr, RenderSettings},
+    scene::{get_scene, Scene, SceneType},
+    vec3::{luminance, Color},
+};
+
+// A red ball in front of a white sky, filling the middle of the frame
+fn red_ball() -> Scene {
+    let camera = Camera::look_at(
+        vec3(0., 0., 3.),
+        vec3(0., 0., 0.),
+        vec3(0., 1., 0.),
+        1.,
+        40.,
+        0.,
+        1.,
+        0.,
+        1.,
+    );
+    let red = Arc::new(Lambertian::from_color(Color::new(0.8, 0.1, 0.1)));
+    let objects: Hittables = vec![Arc::new(Sphere::new(vec3a(0., 0., 0.), 0.5, red))];
+
+    Scene::new(
+        camera,
+        objects,
+        Hittables::new(),
+        Background::Solid(Color::ONE),
+        0.,
+        1.,
+    )
+}
+
+#[test]
+fn renders_a_scene_without_the_command_line() {
+    let scene = get_scene(SceneType::CornellBox, 1., None);
+    let settings = RenderSettings {
+        seed: 7,
+        ..RenderSettings::new(32, 32, 16, 8)
+    };
+    let radiance = render_frame(&scene, &settings, |_| ()).radiance;
+
+    assert_eq!(radiance.len(), 32 * 32);
+    // About 0.157 once converged, at over a thousand samples per pixel
+    let mean = radiance.iter().map(|&c| luminance(c)).sum::<f32>() / radiance.len() as f32;
+    assert!((mean - 0.157).abs() < 0.02, "mean luminance is {}", mean);
+}
+
+#[test]
+fn same_settings_give_the_same_image() {
+    let scene = red_ball();
+
+    assert_eq!(render(&scene, 16, 16, 4, 4), render(&scene, 16, 16, 4, 4));
+}
+
+#[test]
+fn hdr_render_matches_the_image_layout() {
+    let radiance = render_hdr(&red_ball(), 16, 12, 8, 4);
+
+    assert_eq!(radiance.len(), 16 * 12);
+    // Rows go from the top, so the first pixel is sky
+    assert_eq!(radiance[0], Color::ONE);
+    assert!(radiance.iter().all(|c| c.is_finite()));
+}